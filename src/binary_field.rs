@@ -0,0 +1,106 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// A single value in the binary config layout shared with the firmware.
+#[derive(Debug, PartialEq, Clone)]
+pub enum BinaryField {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    F32(f32),
+    Bytes(Vec<u8>),
+}
+
+impl BinaryField {
+    pub fn len(&self) -> usize {
+        match self {
+            BinaryField::U8(_) => 1,
+            BinaryField::U16(_) => 2,
+            BinaryField::U32(_) | BinaryField::I32(_) | BinaryField::F32(_) => 4,
+            BinaryField::U64(_) => 8,
+            BinaryField::Bytes(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn write<W: Write>(&self, buf: &mut W) -> io::Result<()> {
+        match self {
+            BinaryField::U8(val) => buf.write_u8(*val),
+            BinaryField::U16(val) => buf.write_u16::<LittleEndian>(*val),
+            BinaryField::U32(val) => buf.write_u32::<LittleEndian>(*val),
+            BinaryField::I32(val) => buf.write_i32::<LittleEndian>(*val),
+            BinaryField::U64(val) => buf.write_u64::<LittleEndian>(*val),
+            BinaryField::F32(val) => buf.write_f32::<LittleEndian>(*val),
+            BinaryField::Bytes(bytes) => buf.write_all(bytes),
+        }
+    }
+
+    /// Reads a value of the same type as `self`, or for `Bytes` the same number of bytes.
+    pub fn read_like<R: Read>(&self, buf: &mut R) -> io::Result<BinaryField> {
+        Ok(match self {
+            BinaryField::U8(_) => BinaryField::U8(buf.read_u8()?),
+            BinaryField::U16(_) => BinaryField::U16(buf.read_u16::<LittleEndian>()?),
+            BinaryField::U32(_) => BinaryField::U32(buf.read_u32::<LittleEndian>()?),
+            BinaryField::I32(_) => BinaryField::I32(buf.read_i32::<LittleEndian>()?),
+            BinaryField::U64(_) => BinaryField::U64(buf.read_u64::<LittleEndian>()?),
+            BinaryField::F32(_) => BinaryField::F32(buf.read_f32::<LittleEndian>()?),
+            BinaryField::Bytes(bytes) => {
+                let mut out = vec![0u8; bytes.len()];
+                buf.read_exact(&mut out)?;
+                BinaryField::Bytes(out)
+            }
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BinaryError {
+    Truncated,
+    ChecksumMismatch { expected: u32, actual: u32 },
+    BufferTooSmall { needed: usize },
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BinaryError::Truncated => write!(f, "Binary config ended before all fields were read"),
+            BinaryError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Binary config checksum mismatch: expected {:#010x}, got {:#010x}",
+                expected, actual
+            ),
+            BinaryError::BufferTooSmall { needed } => {
+                write!(
+                    f,
+                    "Binary config needs a buffer of at least {} bytes",
+                    needed
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BinaryError {}
+
+impl From<io::Error> for BinaryError {
+    fn from(_: io::Error) -> Self {
+        // Reading from an in-memory slice can only fail by running out of bytes
+        BinaryError::Truncated
+    }
+}
+
+impl fmt::Display for BinaryField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BinaryField::U8(val) => write!(f, "{}", val),
+            BinaryField::U16(val) => write!(f, "{}", val),
+            BinaryField::U32(val) => write!(f, "{}", val),
+            BinaryField::I32(val) => write!(f, "{}", val),
+            BinaryField::U64(val) => write!(f, "{}", val),
+            BinaryField::F32(val) => write!(f, "{}", val),
+            BinaryField::Bytes(bytes) => write!(f, "{:?}", String::from_utf8_lossy(bytes)),
+        }
+    }
+}
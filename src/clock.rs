@@ -0,0 +1,26 @@
+use chrono::{NaiveDateTime, Utc};
+
+/// Source of the current time, so that time dependent behaviour can be tested deterministically.
+pub trait Clock {
+    fn now_utc(&self) -> NaiveDateTime;
+}
+
+/// The system clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_utc(&self) -> NaiveDateTime {
+        Utc::now().naive_utc()
+    }
+}
+
+/// A clock that is always at the given UTC time.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub NaiveDateTime);
+
+impl Clock for FixedClock {
+    fn now_utc(&self) -> NaiveDateTime {
+        self.0
+    }
+}
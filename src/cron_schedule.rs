@@ -0,0 +1,113 @@
+use chrono::{Duration, NaiveDateTime, Timelike};
+use serde::{Serialize, Serializer};
+
+// How far ahead to look for the next matching minute; every supported schedule repeats daily.
+const MAX_SEARCH_MINUTES: i64 = 2 * 24 * 60;
+
+/// A minimal cron-like schedule, supporting the minute and hour fields of a standard
+/// `minute hour day-of-month month day-of-week` expression.  The remaining fields must be `*`.
+///
+/// Each field may be `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`, or a comma separated
+/// list of those.  A time is in the schedule for every minute that matches both fields.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u32,
+}
+
+fn parse_field(field: &str, max: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("Invalid step '{}'", step))?,
+            ),
+            None => (part, 1),
+        };
+        let parse_value = |value: &str| {
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|value| *value <= max)
+                .ok_or_else(|| format!("Invalid value '{}', expected 0-{}", value, max))
+        };
+        let (from, to) = if range == "*" {
+            (0, max)
+        } else if let Some((from, to)) = range.split_once('-') {
+            (parse_value(from)?, parse_value(to)?)
+        } else {
+            let value = parse_value(range)?;
+            (value, value)
+        };
+        if from > to {
+            return Err(format!("Invalid range '{}'", range));
+        }
+        for value in (from..=to).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<CronSchedule, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "Expected 5 fields in cron expression '{}', got {}",
+                expression,
+                fields.len()
+            ));
+        }
+        if fields[2..].iter().any(|field| *field != "*") {
+            return Err(format!(
+                "Only the minute and hour fields are supported in cron expression '{}'",
+                expression
+            ));
+        }
+        let minutes = parse_field(fields[0], 59)
+            .map_err(|e| format!("Cron expression '{}' minutes: {}", expression, e))?;
+        let hours = parse_field(fields[1], 23)
+            .map_err(|e| format!("Cron expression '{}' hours: {}", expression, e))?;
+        Ok(CronSchedule {
+            expression: expression.to_string(),
+            minutes,
+            hours: hours as u32,
+        })
+    }
+
+    pub fn matches(&self, local_time: &NaiveDateTime) -> bool {
+        self.minutes & (1 << local_time.minute()) != 0 && self.hours & (1 << local_time.hour()) != 0
+    }
+
+    /// The next (or current) run of matching minutes, in local time.
+    pub fn next_window(
+        &self,
+        local_time: &NaiveDateTime,
+    ) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let current_minute = local_time.with_second(0)?.with_nanosecond(0)?;
+        let start = (0..MAX_SEARCH_MINUTES)
+            .map(|i| current_minute + Duration::minutes(i))
+            .find(|time| self.matches(time))?;
+        // A schedule matching every minute is treated as a window spanning the whole search
+        let end = (1..MAX_SEARCH_MINUTES)
+            .map(|i| start + Duration::minutes(i))
+            .find(|time| !self.matches(time))
+            .unwrap_or(start + Duration::minutes(MAX_SEARCH_MINUTES));
+        Some((start, end))
+    }
+}
+
+impl Serialize for CronSchedule {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.expression)
+    }
+}
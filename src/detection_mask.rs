@@ -1,17 +1,225 @@
+use std::fmt;
+use triangulate::{ListFormat, Polygon};
+
+// The thermal sensor's frame size, which masks are created at unless resized
+pub const FRAME_WIDTH: usize = 160;
+pub const FRAME_HEIGHT: usize = 120;
+pub const FRAME_SIZE: (usize, usize) = (FRAME_WIDTH, FRAME_HEIGHT);
+
+type Triangle = ((f32, f32), (f32, f32), (f32, f32));
+// Outer ring and holes of a mask region, in normalized coordinates
+pub type MaskRegion = (Vec<[f32; 2]>, Vec<Vec<[f32; 2]>>);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum MaskError {
+    // Combining masks needs them to cover the same frame, as (width, height)
+    #[cfg(test)]
+    SizeMismatch {
+        left: (usize, usize),
+        right: (usize, usize),
+    },
+    // Raw mask bytes that don't have one bit for each pixel of the frame
+    #[cfg(feature = "protobuf")]
+    WrongLength {
+        expected: usize,
+        actual: usize,
+    },
+    // A polygon that can't be triangulated, e.g. one with fewer than three points or edges that
+    // cross
+    InvalidPolygon(String),
+}
+
+impl fmt::Display for MaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(test)]
+            MaskError::SizeMismatch { left, right } => write!(
+                f,
+                "Can't combine a {}x{} mask with a {}x{} mask, resize one of them first",
+                left.0, left.1, right.0, right.1
+            ),
+            #[cfg(feature = "protobuf")]
+            MaskError::WrongLength { expected, actual } => write!(
+                f,
+                "Mask has {} bytes, expected {} for its frame size",
+                actual, expected
+            ),
+            MaskError::InvalidPolygon(reason) => write!(f, "Invalid mask polygon: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for MaskError {}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct DetectionMask {
-    inner: [u8; 2400],
+    // One bit per pixel, row by row
+    inner: Vec<u8>,
+    width: usize,
+    height: usize,
 }
 
 impl DetectionMask {
-    pub fn new(mask: Option<[u8; 2400]>) -> DetectionMask {
+    /// Creates a mask for a `frame` of `(width, height)` pixels with every pixel either masked
+    /// (`Some(true)`) or clear (`None`/`Some(false)`).
+    pub fn new(fill: Option<bool>, frame: (usize, usize)) -> DetectionMask {
+        DetectionMask::with_size(frame.0, frame.1, fill.unwrap_or(false))
+    }
+
+    fn with_size(width: usize, height: usize, fill: bool) -> DetectionMask {
+        let fill = if fill { 0xff } else { 0u8 };
         DetectionMask {
-            inner: mask.unwrap_or([0u8; 2400]),
+            inner: vec![fill; (width * height).div_ceil(8)],
+            width,
+            height,
+        }
+    }
+
+    /// Creates a mask from normalized `(x, y, width, height)` rectangles, scaled to a `frame` of
+    /// `(width, height)` pixels, which is the size of the returned mask.  Pixels on the rectangle
+    /// edges are masked, and anything outside the frame is ignored.
+    #[cfg(test)]
+    pub fn from_rects(rects: &[(f32, f32, f32, f32)], frame: (usize, usize)) -> DetectionMask {
+        let mut mask = DetectionMask::with_size(frame.0, frame.1, false);
+        // The first and last pixel covered along an axis, clipped to the frame
+        let covered = |start: f32, len: f32, size: usize| {
+            let first = (start * size as f32).ceil() as i64;
+            let last = ((start + len) * size as f32).floor() as i64;
+            first.max(0)..=last.min(size as i64 - 1)
+        };
+        for (x, y, w, h) in rects {
+            for y in covered(*y, *h, frame.1) {
+                for x in covered(*x, *w, frame.0) {
+                    mask.set_pos(x as usize, y as usize);
+                }
+            }
+        }
+        mask
+    }
+
+    /// Creates a mask of the pixels inside a polygon of normalized `[x, y]` points, scaled to a
+    /// `frame` of `(width, height)` pixels, e.g. to preview a mask region before saving it.
+    #[cfg(test)]
+    pub fn from_polygon(
+        points: &[[f32; 2]],
+        frame: (usize, usize),
+    ) -> Result<DetectionMask, MaskError> {
+        DetectionMask::from_regions(&[(points.to_vec(), Vec::new())], frame)
+    }
+
+    /// Creates a mask from regions as read from `[thermal-recorder].mask-regions`, where a hole
+    /// only unmasks pixels of the region it belongs to.
+    pub fn from_regions(
+        regions: &[MaskRegion],
+        frame: (usize, usize),
+    ) -> Result<DetectionMask, MaskError> {
+        // Triangulate the polygons, then do point in triangle checks for each pixel of the frame.
+        let regions = regions
+            .iter()
+            .map(|(outer, holes)| {
+                let holes = holes
+                    .iter()
+                    .map(|hole| triangulate_ring(hole, frame))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((triangulate_ring(outer, frame)?, holes.concat()))
+            })
+            .collect::<Result<Vec<(Vec<Triangle>, Vec<Triangle>)>, MaskError>>()?;
+        let mut mask = DetectionMask::with_size(frame.0, frame.1, false);
+        for y in 0..frame.1 {
+            for x in 0..frame.0 {
+                let point = (x as f32, y as f32);
+                let masked = regions.iter().any(|(outer, holes)| {
+                    outer
+                        .iter()
+                        .any(|triangle| point_in_triangle(*triangle, point))
+                        && !holes
+                            .iter()
+                            .any(|triangle| point_in_triangle(*triangle, point))
+                });
+                if masked {
+                    mask.set_pos(x, y);
+                }
+            }
         }
+        Ok(mask)
+    }
+
+    /// Creates a mask from the bytes returned by `as_bytes` for a `frame` of `(width, height)`
+    /// pixels.
+    #[cfg(feature = "protobuf")]
+    pub fn from_raw_bytes(bytes: Vec<u8>, frame: (usize, usize)) -> Result<Self, MaskError> {
+        let expected = (frame.0 * frame.1).div_ceil(8);
+        if bytes.len() != expected {
+            return Err(MaskError::WrongLength {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+        Ok(DetectionMask {
+            inner: bytes,
+            width: frame.0,
+            height: frame.1,
+        })
+    }
+
+    /// One bit per pixel, row by row, with the first pixel of each byte in its least significant
+    /// bit.
+    #[cfg(feature = "protobuf")]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.inner
+    }
+
+    #[cfg(any(test, feature = "protobuf"))]
+    pub fn width(&self) -> usize {
+        self.width
     }
+
+    #[cfg(any(test, feature = "protobuf"))]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The mask scaled to `new_w` by `new_h` pixels, e.g. for a higher resolution sensor.  Each
+    /// new pixel takes the value of the nearest pixel in this mask.
+    #[cfg(test)]
+    pub fn resized(&self, new_w: usize, new_h: usize) -> DetectionMask {
+        let mut mask = DetectionMask::with_size(new_w, new_h, false);
+        for y in 0..new_h {
+            let source_y = y * self.height / new_h;
+            for x in 0..new_w {
+                let source_x = x * self.width / new_w;
+                if self.is_masked_at_pos(source_x, source_y) {
+                    mask.set_pos(x, y);
+                }
+            }
+        }
+        mask
+    }
+
+    /// A mask of the pixels masked in either this mask or `other`, which must be the same size.
+    #[cfg(test)]
+    pub fn union(&self, other: &DetectionMask) -> Result<DetectionMask, MaskError> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Err(MaskError::SizeMismatch {
+                left: (self.width, self.height),
+                right: (other.width, other.height),
+            });
+        }
+        Ok(DetectionMask {
+            inner: self
+                .inner
+                .iter()
+                .zip(&other.inner)
+                .map(|(a, b)| a | b)
+                .collect(),
+            width: self.width,
+            height: self.height,
+        })
+    }
+
     #[allow(unused)]
     pub fn is_masked_at_pos(&self, x: usize, y: usize) -> bool {
-        let index = (y * 160) + x;
+        let index = (y * self.width) + x;
         self.inner[index >> 3] & (1 << (index % 8)) != 0
     }
 
@@ -21,7 +229,7 @@ impl DetectionMask {
     }
 
     pub fn set_pos(&mut self, x: usize, y: usize) {
-        let i = (y * 160) + x;
+        let i = (y * self.width) + x;
         self.inner[i >> 3] |= 1 << (i % 8);
     }
 
@@ -32,3 +240,99 @@ impl DetectionMask {
         group != 0 && group & (1 << (index % 8)) != 0
     }
 }
+
+/// Checks that a ring of normalized `[x, y]` points is a simple polygon, as the triangulation
+/// panics on rings that repeat a point, fold back or cross themselves.
+pub fn check_ring(ring: &[[f32; 2]]) -> Result<(), MaskError> {
+    let invalid = |reason: String| Err(MaskError::InvalidPolygon(reason));
+    if ring.len() < 3 {
+        return invalid(format!("needs at least 3 points, got {}", ring.len()));
+    }
+    for (i, point) in ring.iter().enumerate() {
+        if ring[i + 1..].contains(point) {
+            return invalid(format!("point {:?} is repeated", point));
+        }
+    }
+    let n = ring.len();
+    let point = |i: usize| (ring[i % n][0], ring[i % n][1]);
+    for i in 0..n {
+        let (a, b, c) = (point(i), point(i + 1), point(i + 2));
+        let turns_back = (b.0 - a.0) * (c.0 - b.0) + (b.1 - a.1) * (c.1 - b.1) < 0.0;
+        if sign(a, c, b) == 0.0 && turns_back {
+            return invalid(format!("folds back on itself at {:?}", ring[(i + 1) % n]));
+        }
+        // Edges next to each other share a point, so only the others are checked
+        for j in i + 2..n {
+            if (j + 1) % n != i && segments_touch((a, b), (point(j), point(j + 1))) {
+                return invalid(format!(
+                    "edges from {:?} and {:?} cross or touch",
+                    ring[i], ring[j]
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn segments_touch(first: ((f32, f32), (f32, f32)), second: ((f32, f32), (f32, f32))) -> bool {
+    let ((a, b), (c, d)) = (first, second);
+    // Whether `p`, already known to be on the line through `start` and `end`, is between them
+    let between = |start: (f32, f32), end: (f32, f32), p: (f32, f32)| {
+        start.0.min(end.0) <= p.0
+            && p.0 <= start.0.max(end.0)
+            && start.1.min(end.1) <= p.1
+            && p.1 <= start.1.max(end.1)
+    };
+    let (d1, d2) = (sign(c, d, a), sign(c, d, b));
+    let (d3, d4) = (sign(a, b, c), sign(a, b, d));
+    (d1 * d2 < 0.0 && d3 * d4 < 0.0)
+        || (d1 == 0.0 && between(c, d, a))
+        || (d2 == 0.0 && between(c, d, b))
+        || (d3 == 0.0 && between(a, b, c))
+        || (d4 == 0.0 && between(a, b, d))
+}
+
+fn triangulate_ring(
+    polygon: &Vec<[f32; 2]>,
+    frame: (usize, usize),
+) -> Result<Vec<Triangle>, MaskError> {
+    check_ring(polygon)?;
+    let w = frame.0 as f32;
+    let h = frame.1 as f32;
+    let mut triangulated_indices: Vec<usize> = Vec::new();
+    polygon
+        .triangulate(
+            triangulate::formats::IndexedListFormat::new(&mut triangulated_indices)
+                .into_fan_format(),
+        )
+        .map_err(|e| MaskError::InvalidPolygon(e.to_string()))?;
+    Ok(triangulated_indices
+        .chunks_exact(3)
+        .map(|corners| {
+            // Map each triangle into the frame space. Vertices just past the frame edge are
+            // clamped to it.
+            let vertex = |corner: usize| {
+                (
+                    (polygon[corner][0] * w).clamp(0.0, w),
+                    (polygon[corner][1] * h).clamp(0.0, h),
+                )
+            };
+            (vertex(corners[0]), vertex(corners[1]), vertex(corners[2]))
+        })
+        .collect())
+}
+
+fn sign(p1: (f32, f32), p2: (f32, f32), p3: (f32, f32)) -> f32 {
+    (p1.0 - p3.0) * (p2.1 - p3.1) - (p2.0 - p3.0) * (p1.1 - p3.1)
+}
+
+fn point_in_triangle(triangle: Triangle, point: (f32, f32)) -> bool {
+    let d1 = sign(point, triangle.0, triangle.1);
+    let d2 = sign(point, triangle.1, triangle.2);
+    let d3 = sign(point, triangle.2, triangle.0);
+
+    let has_neg = (d1 < 0.) || (d2 < 0.) || (d3 < 0.);
+    let has_pos = (d1 > 0.) || (d2 > 0.) || (d3 > 0.);
+
+    return !(has_neg && has_pos);
+}
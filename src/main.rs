@@ -1,24 +1,47 @@
 use std::collections::HashMap;
 // Read camera config file
-use crate::detection_mask::DetectionMask;
-use byteorder::{LittleEndian, WriteBytesExt};
+use crate::binary_field::{BinaryError, BinaryField};
+use crate::clock::{Clock, SystemClock};
+use crate::cron_schedule::CronSchedule;
+#[cfg(feature = "protobuf")]
+use crate::detection_mask::MaskError;
+use crate::detection_mask::{check_ring, DetectionMask, MaskRegion, FRAME_SIZE};
+use crate::recording_metadata::RecordingMetadata;
+use crate::status_cache::StatusCache;
+use crate::sun_times_cache::SunTimesCache;
+use crate::window_evaluator::WindowEvaluator;
 use chrono::{
-    DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+    DateTime, Datelike, Duration, FixedOffset, Local, LocalResult, NaiveDate, NaiveDateTime,
+    NaiveTime, TimeZone, Timelike, Utc,
 };
-use log::{error, info};
+use chrono_tz::{OffsetComponents, Tz};
+use log::{error, info, warn};
 use serde::de::Error;
-use serde::{Deserialize, Deserializer};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
-use std::io::{Cursor, Write};
-use std::ops::Add;
-use sun_times::sun_times;
+use std::io::{Cursor, Read};
+use std::ops::{Add, Range};
+use std::path::{Path, PathBuf};
+use sun_times::{try_civil_twilight, try_sun_times_with_horizon, SunTimesError};
 use toml::value::Offset;
 use toml::Value;
-use triangulate::{ListFormat, Polygon};
+use url::Url;
 
+mod binary_field;
+mod clock;
+mod cron_schedule;
 mod detection_mask;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod recording_metadata;
+mod status_cache;
 mod sun_times;
+mod sun_times_cache;
 mod tests;
+mod window_evaluator;
 
 fn default_constant_recorder() -> bool {
     false
@@ -28,8 +51,17 @@ fn default_low_power_mode() -> bool {
     false
 }
 
+fn default_motion_only() -> bool {
+    false
+}
+
+// Must be the same size as masks read from mask-regions so the two can be combined
 fn default_mask_regions() -> DetectionMask {
-    DetectionMask::new(None)
+    DetectionMask::new(None, FRAME_SIZE)
+}
+
+fn default_sample_probability() -> f32 {
+    1.0
 }
 
 fn default_min_disk_space_mb() -> u32 {
@@ -40,6 +72,10 @@ fn default_location_timestamp() -> Option<u64> {
     None
 }
 
+fn default_location_updated() -> Option<u64> {
+    None
+}
+
 fn default_location_accuracy() -> Option<f32> {
     None
 }
@@ -56,6 +92,7 @@ fn default_recording_start_time() -> AbsRelTime {
     AbsRelTime {
         relative_time_seconds: Some(-(60 * 30)),
         absolute_time: None,
+        anchor: None,
     }
 }
 
@@ -63,119 +100,156 @@ fn default_recording_stop_time() -> AbsRelTime {
     AbsRelTime {
         relative_time_seconds: Some(60 * 30),
         absolute_time: None,
+        anchor: None,
     }
 }
 
+fn default_recording_windows() -> Vec<TimeWindow> {
+    vec![TimeWindow::default()]
+}
+
 #[derive(Debug)]
 struct TimeUnit(char);
 
 #[derive(Debug)]
 struct NumberString(String, Option<TimeUnit>, bool);
 
-fn deserialize_mask_regions<'de, D>(deserializer: D) -> Result<DetectionMask, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let masks: toml::map::Map<String, toml::Value> = Deserialize::deserialize(deserializer)?;
-    let mut regions: HashMap<String, Vec<[f32; 2]>> = HashMap::new();
-    for (label, mask_region) in masks {
-        let mut region = Vec::new();
-        match mask_region {
-            Value::Array(val) => {
-                for (i, item) in val.iter().enumerate() {
-                    match item {
-                        Value::Array(coord) => {
-                            if coord.len() != 2 {
-                                error!(
-                                    "Region '{}'[{}]: Expected coord array of length 2, got {}",
-                                    label,
-                                    i,
-                                    coord.len()
-                                );
+// Editors on Windows sometimes save config files with a UTF-8 byte order mark, which toml rejects
+fn strip_bom(config_toml: &str) -> &str {
+    config_toml.strip_prefix('\u{feff}').unwrap_or(config_toml)
+}
+
+// Merges `overlay` into `base`, recursing into tables and otherwise replacing values
+fn merge_toml(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_toml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// How far past the frame edge a normalized coordinate can be, e.g. from float imprecision in
+// tools that generate masks, before it's treated as a mistake rather than clamped to the edge
+const MASK_COORDINATE_TOLERANCE: f32 = 0.01;
+
+fn parse_mask_ring<E: Error>(label: &str, ring: &[Value]) -> Result<Vec<[f32; 2]>, E> {
+    let mut region = Vec::new();
+    for (i, item) in ring.iter().enumerate() {
+        match item {
+            Value::Array(coord) => {
+                if coord.len() != 2 {
+                    error!(
+                        "Region '{}'[{}]: Expected coord array of length 2, got {}",
+                        label,
+                        i,
+                        coord.len()
+                    );
+                } else {
+                    let mut x = 0.0;
+                    let mut y;
+                    for (idx, el) in coord.iter().enumerate() {
+                        let el_val = match &el {
+                            Value::Float(float_val) => Some(*float_val as f32),
+                            Value::Integer(int_val) => Some(*int_val as f32),
+                            _ => {
+                                error!("Region '{}'[{}].{}: Unsupported coordinate value, expected Float or Integer", label, i, if idx == 0 {'x'} else { 'y' });
+                                None
+                            }
+                        };
+                        if let Some(val) = el_val {
+                            if idx == 0 {
+                                x = val;
                             } else {
-                                let mut x = 0.0;
-                                let mut y;
-                                for (idx, el) in coord.iter().enumerate() {
-                                    let el_val = match &el {
-                                        Value::Float(float_val) => Some(*float_val as f32),
-                                        Value::Integer(int_val) => Some(*int_val as f32),
-                                        _ => {
-                                            error!("Region '{}'[{}].{}: Unsupported coordinate value, expected Float or Integer", label, i, if idx == 0 {'x'} else { 'y' });
-                                            None
-                                        }
-                                    };
-                                    if let Some(val) = el_val {
-                                        if idx == 0 {
-                                            x = val;
-                                        } else {
-                                            y = val;
-                                            region.push([x, y]);
-                                        }
-                                    }
+                                y = val;
+                                let range =
+                                    -MASK_COORDINATE_TOLERANCE..=1.0 + MASK_COORDINATE_TOLERANCE;
+                                if !range.contains(&x) || !range.contains(&y) {
+                                    return Err(Error::custom(format!(
+                                        "Region '{}'[{}]: Coordinate [{}, {}] is outside the normalized 0..1 range, are these pixel coordinates?",
+                                        label, i, x, y
+                                    )));
                                 }
+                                region.push([x, y]);
                             }
                         }
-                        _ => error!(
-                            "Region '{}'[{}]: Expected array of [x, y] coordinates",
-                            label, i
-                        ),
                     }
                 }
             }
             _ => error!(
-                "Region '{}': Must be an array of [[x, y], ...] coordinates",
-                label
+                "Region '{}'[{}]: Expected array of [x, y] coordinates",
+                label, i
             ),
         }
-        regions.insert(label.clone(), region);
-    }
-    // Now need to triangulate polygons, and then fill the mask.
-    let mut triangles = Vec::new();
-    let w = 160.0;
-    let h = 120.0;
-    for (_label, polygon) in regions {
-        let mut triangulated_indices: Vec<usize> = Vec::new();
-        polygon
-            .triangulate(
-                triangulate::formats::IndexedListFormat::new(&mut triangulated_indices)
-                    .into_fan_format(),
-            )
-            .expect("Triangulation failed");
-        for corners in triangulated_indices.chunks_exact(3) {
-            // Map each triangle into the frame space, then do 'point-in triangle checks for each pixel of the frame.
-            triangles.push((
-                (polygon[corners[0]][0] * w, polygon[corners[0]][1] * h),
-                (polygon[corners[1]][0] * w, polygon[corners[1]][1] * h),
-                (polygon[corners[2]][0] * w, polygon[corners[2]][1] * h),
-            ));
-        }
-    }
-    let mut mask = DetectionMask::new(None);
-    for y in 0..120 {
-        for x in 0..160 {
-            for triangle in &triangles {
-                if point_in_triangle(*triangle, (x as f32, y as f32)) {
-                    mask.set_pos(x, y);
-                }
-            }
-        }
     }
-    Ok(mask)
-}
-
-fn sign(p1: (f32, f32), p2: (f32, f32), p3: (f32, f32)) -> f32 {
-    (p1.0 - p3.0) * (p2.1 - p3.1) - (p2.0 - p3.0) * (p1.1 - p3.1)
+    check_ring(&region).map_err(|e| Error::custom(format!("Region '{}': {}", label, e)))?;
+    Ok(region)
 }
 
-fn point_in_triangle(triangle: ((f32, f32), (f32, f32), (f32, f32)), point: (f32, f32)) -> bool {
-    let d1 = sign(point, triangle.0, triangle.1);
-    let d2 = sign(point, triangle.1, triangle.2);
-    let d3 = sign(point, triangle.2, triangle.0);
-
-    let has_neg = (d1 < 0.) || (d2 < 0.) || (d3 < 0.);
-    let has_pos = (d1 > 0.) || (d2 > 0.) || (d3 > 0.);
-
-    return !(has_neg && has_pos);
+/// Each region is either an array of `[x, y]` coordinates, or a table with an `outer` array and
+/// optionally `holes`, an array of coordinate arrays that are left unmasked inside the region.
+fn deserialize_mask_regions<'de, D>(deserializer: D) -> Result<DetectionMask, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let masks: toml::map::Map<String, toml::Value> = Deserialize::deserialize(deserializer)?;
+    let mut regions: HashMap<String, MaskRegion> = HashMap::new();
+    for (label, mask_region) in masks {
+        let region = match mask_region {
+            Value::Array(ring) => (parse_mask_ring(&label, &ring)?, Vec::new()),
+            Value::Table(region) => {
+                let outer = match region.get("outer") {
+                    Some(Value::Array(ring)) => parse_mask_ring(&label, ring)?,
+                    _ => {
+                        return Err(Error::custom(format!(
+                            "Region '{}': Must have an 'outer' array of [[x, y], ...] coordinates",
+                            label
+                        )))
+                    }
+                };
+                let holes = match region.get("holes") {
+                    None => Vec::new(),
+                    Some(Value::Array(holes)) => holes
+                        .iter()
+                        .enumerate()
+                        .map(|(i, hole)| match hole {
+                            Value::Array(ring) => {
+                                parse_mask_ring(&format!("{}.holes[{}]", label, i), ring)
+                            }
+                            _ => Err(Error::custom(format!(
+                                "Region '{}': Each hole must be an array of [[x, y], ...] coordinates",
+                                label
+                            ))),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?,
+                    Some(_) => {
+                        return Err(Error::custom(format!(
+                            "Region '{}': 'holes' must be an array of [[x, y], ...] coordinate arrays",
+                            label
+                        )))
+                    }
+                };
+                (outer, holes)
+            }
+            _ => {
+                error!(
+                    "Region '{}': Must be an array of [[x, y], ...] coordinates",
+                    label
+                );
+                (Vec::new(), Vec::new())
+            }
+        };
+        regions.insert(label.clone(), region);
+    }
+    let regions: Vec<MaskRegion> = regions.into_values().collect();
+    DetectionMask::from_regions(&regions, FRAME_SIZE).map_err(Error::custom)
 }
 
 fn from_time_abs_or_rel_str<'de, D>(deserializer: D) -> Result<AbsRelTime, D::Error>
@@ -185,12 +259,30 @@ where
     let s: String = Deserialize::deserialize(deserializer)?;
 
     info!("Deserialising time from config {}", s);
+    // A time may start with the solar event it is relative to, e.g. "civil_dusk-30m"
+    let (anchor, offset) = match SolarAnchor::ALL
+        .iter()
+        .find_map(|anchor| Some((*anchor, s.strip_prefix(anchor.keyword())?)))
+    {
+        Some((anchor, offset)) => (Some(anchor), offset),
+        None => (None, s.as_str()),
+    };
+    if offset.trim_start_matches(['-', '+']).starts_with('P') {
+        let relative_time_seconds = parse_iso8601_duration(offset).ok_or_else(|| {
+            Error::custom(format!("Invalid ISO-8601 duration in time string '{}'", s))
+        })?;
+        return Ok(AbsRelTime {
+            absolute_time: None,
+            relative_time_seconds: Some(relative_time_seconds),
+            anchor,
+        });
+    }
     // NOTE: This is probably not that robust on all possible input strings – but we should solve this
     //  with better validation/UI elsewhere where users are inputting time offsets
     let mut tokens: Vec<NumberString> = Vec::new();
-    for char in s.chars() {
+    for char in offset.chars() {
         match char {
-            '-' | '+' | '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
+            '-' | '+' | '.' | '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
                 if let Some(NumberString(ref mut n, _, _)) = tokens.last_mut() {
                     n.push(char);
                 } else {
@@ -239,6 +331,7 @@ where
     }
     let mut relative_time_seconds = None;
     let mut absolute_time = None;
+    let mut has_minutes = false;
     for token in &tokens {
         if token.2 {
             if relative_time_seconds.is_none() {
@@ -246,22 +339,27 @@ where
             }
         } else {
             if absolute_time.is_none() {
-                absolute_time = Some(HourMin { hour: 0, min: 0 });
+                absolute_time = Some(HourMin {
+                    hour: 0,
+                    min: 0,
+                    sec: 0,
+                });
             }
         }
         if let Some(ref mut seconds) = relative_time_seconds {
-            if let Ok(mut num) = i32::from_str_radix(&token.0, 10) {
-                if let Some(unit) = &token.1 {
-                    let mul = match unit.0 {
-                        's' => 1,
-                        'm' => 60,
-                        'h' => 60 * 60,
-                        _ => 1,
-                    };
-                    num *= mul;
+            // Relative offsets may be fractional, e.g. "1.5h"
+            if let Ok(value) = token.0.parse::<f64>() {
+                let mul = if let Some(unit) = &token.1 {
+                    match unit.0 {
+                        's' => 1.0,
+                        'm' => 60.0,
+                        'h' => 60.0 * 60.0,
+                        _ => 1.0,
+                    }
                 } else {
-                    num *= 60; // Default unit is minutes if none specified
-                }
+                    60.0 // Default unit is minutes if none specified
+                };
+                let num = (value * mul).round() as i32;
                 if *seconds < 0 && num > 0 {
                     *seconds += -num;
                 } else {
@@ -269,34 +367,395 @@ where
                 }
             }
         } else if let Some(ref mut hour_min) = absolute_time {
+            if token.0.contains('.') {
+                return Err(Error::custom(format!(
+                    "Absolute times can't be fractional in time string '{}'",
+                    s
+                )));
+            }
             if let Ok(num) = i32::from_str_radix(&token.0, 10) {
-                if let Some(unit) = &token.1 {
-                    match unit.0 {
-                        'm' => hour_min.min = num as u8,
-                        'h' => hour_min.hour = num as u8,
-                        _ => {}
-                    };
-                } else {
-                    hour_min.min = num as u8
+                let unit = match &token.1 {
+                    Some(unit) => unit.0,
+                    // The trailing part of a HH:MM:SS time
+                    None if has_minutes => 's',
+                    None => 'm',
+                };
+                let max = if unit == 'h' { 23 } else { 59 };
+                if !(0..=max).contains(&num) {
+                    return Err(Error::custom(format!(
+                        "Out of range value in time string '{}': {}",
+                        s, num
+                    )));
                 }
+                match unit {
+                    's' => hour_min.sec = num as u8,
+                    'm' => {
+                        hour_min.min = num as u8;
+                        has_minutes = true;
+                    }
+                    'h' => hour_min.hour = num as u8,
+                    _ => {}
+                };
             }
         }
     }
+    if anchor.is_some() {
+        if absolute_time.is_some() {
+            return Err(Error::custom(format!(
+                "Only a relative offset can follow a solar event in time string '{}'",
+                s
+            )));
+        }
+        relative_time_seconds.get_or_insert(0);
+    }
     if absolute_time.is_none() && relative_time_seconds.is_none() {
         Err(Error::custom(format!("Failed to parse window time: {}", s)))
     } else {
         Ok(AbsRelTime {
             absolute_time,
             relative_time_seconds,
+            anchor,
         })
     }
 }
 
+/// Parses an ISO-8601 duration such as `PT1H30M` into seconds, with a leading `-` for negative
+/// durations.  Only days, hours, minutes and whole seconds are supported.
+fn parse_iso8601_duration(s: &str) -> Option<i32> {
+    let (sign, s) = match s.strip_prefix('-') {
+        Some(s) => (-1, s),
+        None => (1, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let s = s.strip_prefix('P')?;
+    let (date_part, time_part) = match s.split_once('T') {
+        Some((date_part, time_part)) if !time_part.is_empty() => (date_part, Some(time_part)),
+        Some(_) => return None,
+        None => (s, None),
+    };
+    let mut seconds: i32 = 0;
+    let mut has_value = false;
+    for (part, units) in [
+        (date_part, &[('D', 86_400)][..]),
+        (
+            time_part.unwrap_or(""),
+            &[('H', 3600), ('M', 60), ('S', 1)][..],
+        ),
+    ] {
+        let mut rest = part;
+        let mut units = units.iter();
+        while !rest.is_empty() {
+            let end = rest.find(|c: char| !c.is_ascii_digit())?;
+            let value: i32 = rest[..end].parse().ok()?;
+            let designator = rest[end..].chars().next()?;
+            // Designators must appear in order, each at most once
+            let (_, multiplier) = units.find(|(unit, _)| *unit == designator)?;
+            seconds = seconds.checked_add(value.checked_mul(*multiplier)?)?;
+            has_value = true;
+            rest = &rest[end + 1..];
+        }
+    }
+    if has_value {
+        Some(sign * seconds)
+    } else {
+        None
+    }
+}
+
+fn deserialize_cron_schedule<'de, D>(deserializer: D) -> Result<Option<CronSchedule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    CronSchedule::parse(&s).map(Some).map_err(Error::custom)
+}
+
+fn time_window_from_table<E: Error>(
+    mut window: toml::map::Map<String, toml::Value>,
+) -> Result<TimeWindow, E> {
+    // Bare numbers in relative times are minutes unless the window says otherwise. The time
+    // deserializer can't see the setting, so give any bare numbers an explicit unit first.
+    match window.remove("default-unit") {
+        None => {}
+        Some(Value::String(unit)) if unit == "minutes" => {}
+        Some(Value::String(unit)) if unit == "seconds" => {
+            for key in ["start-recording", "stop-recording"] {
+                if let Some(Value::String(time)) = window.get_mut(key) {
+                    let has_bare_number =
+                        time.ends_with(|c: char| c.is_ascii_digit()) && !time.contains([':', 'P']);
+                    if has_bare_number {
+                        time.push('s');
+                    }
+                }
+            }
+        }
+        Some(unit) => {
+            return Err(Error::custom(format!(
+                "Invalid default-unit {}, expected \"minutes\" or \"seconds\"",
+                unit
+            )))
+        }
+    }
+    if window.contains_key("cron")
+        && (window.contains_key("start-recording") || window.contains_key("stop-recording"))
+    {
+        return Err(Error::custom(
+            "Recording window can be set with either 'cron' or 'start-recording'/'stop-recording', not both",
+        ));
+    }
+    TimeWindow::deserialize(Value::Table(window)).map_err(Error::custom)
+}
+
+// Splits a `window = "from sunset-1h to sunrise+2h"` phrase into its start and stop times.
+fn window_phrase_times<E: Error>(phrase: &str) -> Result<(String, String), E> {
+    let invalid = || {
+        Error::custom(format!(
+            "Invalid window '{}', expected something like \"from sunset-1h to sunrise+2h\"",
+            phrase
+        ))
+    };
+    let (start, stop) = phrase
+        .trim()
+        .strip_prefix("from ")
+        .and_then(|times| times.split_once(" to "))
+        .ok_or_else(invalid)?;
+    let (start, stop) = (start.trim(), stop.trim());
+    for time in [start, stop] {
+        from_time_abs_or_rel_str(Value::String(time.to_string())).map_err(|e| {
+            Error::custom(format!(
+                "Invalid time '{}' in window '{}': {}",
+                time, phrase, e
+            ))
+        })?;
+    }
+    Ok((start.to_string(), stop.to_string()))
+}
+
+// A `mode = "crepuscular"` window records for `offset` either side of both sunrise and sunset,
+// so expands to two windows.  Any other settings, like polar-day, apply to both.
+fn time_windows_from_table<E: Error>(
+    mut window: toml::map::Map<String, toml::Value>,
+) -> Result<Vec<TimeWindow>, E> {
+    match window.remove("window") {
+        None => {}
+        Some(Value::String(phrase)) => {
+            if window.contains_key("start-recording") || window.contains_key("stop-recording") {
+                return Err(Error::custom(
+                    "A window is set with either 'window' or 'start-recording'/'stop-recording', not both",
+                ));
+            }
+            let (start, stop) = window_phrase_times(&phrase)?;
+            window.insert(String::from("start-recording"), Value::String(start));
+            window.insert(String::from("stop-recording"), Value::String(stop));
+        }
+        Some(other) => {
+            return Err(Error::custom(format!(
+                "Expected 'window' to be a string like \"from sunset-1h to sunrise+2h\", got {}",
+                other
+            )))
+        }
+    }
+    match window.remove("mode") {
+        None => {}
+        Some(Value::String(mode)) if mode == "single" => {}
+        Some(Value::String(mode)) if mode == "crepuscular" => {
+            if ["start-recording", "stop-recording", "cron"]
+                .iter()
+                .any(|key| window.contains_key(*key))
+            {
+                return Err(Error::custom(
+                    "A crepuscular window is set with 'offset', not 'start-recording'/'stop-recording' or 'cron'",
+                ));
+            }
+            let offset = window.remove("offset").ok_or_else(|| {
+                Error::custom("A crepuscular window needs an 'offset', e.g. \"1h\"")
+            })?;
+            let offset_seconds = deserialize_warmup(offset).map_err(|_| {
+                Error::custom("The crepuscular 'offset' must be a positive duration like \"1h\"")
+            })? as i32;
+            return [SolarAnchor::Sunrise, SolarAnchor::Sunset]
+                .into_iter()
+                .map(|anchor| {
+                    let time = |seconds| AbsRelTime {
+                        absolute_time: None,
+                        relative_time_seconds: Some(seconds),
+                        anchor: Some(anchor),
+                    };
+                    let mut window = window.clone();
+                    window.insert(
+                        String::from("start-recording"),
+                        Value::String(time(-offset_seconds).to_config_string()),
+                    );
+                    window.insert(
+                        String::from("stop-recording"),
+                        Value::String(time(offset_seconds).to_config_string()),
+                    );
+                    time_window_from_table(window)
+                })
+                .collect();
+        }
+        Some(mode) => {
+            return Err(Error::custom(format!(
+                "Invalid window mode {}, expected \"single\" or \"crepuscular\"",
+                mode
+            )))
+        }
+    }
+    Ok(vec![time_window_from_table(window)?])
+}
+
+fn deserialize_time_windows<'de, D>(deserializer: D) -> Result<Vec<TimeWindow>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Either a single [windows] table, or multiple [[windows]]
+    let windows: Value = Deserialize::deserialize(deserializer)?;
+    match windows {
+        Value::Table(window) => time_windows_from_table(window),
+        Value::Array(windows) => {
+            if windows.is_empty() {
+                return Err(Error::custom("At least one recording window is required"));
+            }
+            let mut expanded = Vec::new();
+            for window in windows {
+                match window {
+                    Value::Table(window) => expanded.extend(time_windows_from_table(window)?),
+                    _ => return Err(Error::custom("Each [[windows]] entry must be a table")),
+                }
+            }
+            Ok(expanded)
+        }
+        _ => Err(Error::custom(
+            "Recording windows must be a [windows] table or [[windows]] array",
+        )),
+    }
+}
+
+// "/var/spool/cptv/" and "/var/spool/cptv" are the same directory, so store one form
+fn deserialize_output_dir<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let output_dir = String::deserialize(deserializer)?;
+    match output_dir.strip_suffix('/') {
+        Some(stripped) if !stripped.is_empty() => Ok(stripped.to_string()),
+        _ => Ok(output_dir),
+    }
+}
+
+fn deserialize_backup_dir<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_output_dir(deserializer).map(Some)
+}
+
+fn deserialize_audio_window<'de, D>(deserializer: D) -> Result<Option<TimeWindow>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Table(window) => Ok(Some(time_window_from_table(window)?)),
+        _ => Err(Error::custom("[audio-recorder] must be a table")),
+    }
+}
+
+fn deserialize_warmup<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let warmup = from_time_abs_or_rel_str(deserializer)?;
+    match (warmup.relative_time_seconds, warmup.anchor) {
+        (Some(seconds), None) if seconds >= 0 => Ok(seconds as u32),
+        _ => Err(Error::custom(format!(
+            "Warmup must be a positive duration like \"2m\", got '{}'",
+            warmup.to_config_string()
+        ))),
+    }
+}
+
+// The most video kept from before motion is detected, which the camera has to buffer
+const MAX_PRE_TRIGGER_SECS: u16 = 30;
+
+fn deserialize_pre_trigger<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pre_trigger = from_time_abs_or_rel_str(deserializer)?;
+    match (pre_trigger.relative_time_seconds, pre_trigger.anchor) {
+        (Some(seconds), None) if (0..=MAX_PRE_TRIGGER_SECS as i32).contains(&seconds) => {
+            Ok(seconds as u16)
+        }
+        _ => Err(Error::custom(format!(
+            "Pre-trigger must be a duration from 0s to {}s, got '{}'",
+            MAX_PRE_TRIGGER_SECS,
+            pre_trigger.to_config_string()
+        ))),
+    }
+}
+
+fn deserialize_sample_probability<'de, D>(deserializer: D) -> Result<f32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let probability = f32::deserialize(deserializer)?;
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(Error::custom(format!(
+            "Sample probability must be from 0.0 to 1.0, got {}",
+            probability
+        )));
+    }
+    Ok(probability)
+}
+
+fn serialize_pre_trigger<S>(pre_trigger_secs: &u16, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serialize_warmup(&(*pre_trigger_secs as u32), serializer)
+}
+
+fn serialize_warmup<S>(warmup_seconds: &u32, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    AbsRelTime {
+        absolute_time: None,
+        relative_time_seconds: Some(*warmup_seconds as i32),
+        anchor: None,
+    }
+    .serialize(serializer)
+}
+
+fn deserialize_recording_settings<'de, D>(
+    deserializer: D,
+) -> Result<ThermalRecordingSettings, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let settings = ThermalRecordingSettings::deserialize(deserializer)?;
+    if settings.motion_only && settings.constant_recorder {
+        return Err(Error::custom(
+            "'motion-only' and 'constant-recorder' can't both be enabled",
+        ));
+    }
+    Ok(settings)
+}
+
 fn timestamp_to_u64<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let date_time: toml::value::Datetime = Deserialize::deserialize(deserializer)?;
+    // A datetime comes through as a string when deserializing from an already parsed `Value`
+    let date_time: toml::value::Datetime = match Deserialize::deserialize(deserializer)? {
+        Value::Datetime(date_time) => date_time,
+        Value::String(date_time) => date_time.parse().map_err(Error::custom)?,
+        other => {
+            return Err(Error::custom(format!(
+                "Expected a TOML datetime, got {}",
+                other
+            )))
+        }
+    };
     let date = date_time.date.expect("Should have date");
     let time = date_time.time.expect("should have time");
     let offset = date_time.offset.expect("should have offset");
@@ -310,15 +769,18 @@ where
         FixedOffset::west_opt(offset_minutes * 60)
     };
     if let Some(fixed_offset) = fixed_offset {
+        // Leap seconds (:60) can't be represented, so clamp them to the end of the minute
+        let second = (time.second as u32).min(59);
+        let naive_time = NaiveTime::from_hms_nano_opt(
+            time.hour as u32,
+            time.minute as u32,
+            second,
+            time.nanosecond,
+        )
+        .ok_or_else(|| Error::custom(format!("Invalid time in timestamp '{}'", date_time)))?;
         let naive_utc = NaiveDateTime::new(
             NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32).unwrap(),
-            NaiveTime::from_hms_nano_opt(
-                time.hour as u32,
-                time.minute as u32,
-                time.second as u32,
-                time.nanosecond,
-            )
-            .unwrap(),
+            naive_time,
         )
         .add(fixed_offset);
         let local = DateTime::<Utc>::from_naive_utc_and_offset(naive_utc, Utc);
@@ -328,6 +790,71 @@ where
     }
 }
 
+// Microseconds since the epoch as a TOML datetime, in the timezone `offset_minutes` east of UTC
+fn u64_to_toml_datetime(micros: u64, offset_minutes: i32) -> Result<toml::value::Datetime, String> {
+    let date_time = NaiveDateTime::from_timestamp_micros(micros as i64)
+        .ok_or_else(|| String::from("Invalid location timestamp"))?;
+    let date_time = if offset_minutes == 0 {
+        date_time.format("%Y-%m-%dT%H:%M:%S%.fZ").to_string()
+    } else {
+        let offset = FixedOffset::east_opt(offset_minutes * 60)
+            .ok_or_else(|| format!("Invalid timestamp offset {} minutes", offset_minutes))?;
+        offset
+            .from_utc_datetime(&date_time)
+            .format("%Y-%m-%dT%H:%M:%S%.f%:z")
+            .to_string()
+    };
+    date_time.parse().map_err(|e| format!("{}", e))
+}
+
+// The UTC offset of a TOML datetime, which is a string if it came from an already parsed `Value`
+fn toml_datetime_offset_minutes(value: &Value) -> Option<i32> {
+    let date_time: toml::value::Datetime = match value {
+        Value::Datetime(date_time) => *date_time,
+        Value::String(date_time) => date_time.parse().ok()?,
+        _ => return None,
+    };
+    match date_time.offset? {
+        Offset::Z => Some(0),
+        Offset::Custom { minutes } => Some(minutes as i32),
+    }
+}
+
+fn deserialize_location<'de, D>(deserializer: D) -> Result<Option<LocationSettings>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let location: Value = Deserialize::deserialize(deserializer)?;
+    let timestamp_offset_minutes = location
+        .get("timestamp")
+        .and_then(toml_datetime_offset_minutes);
+    let mut location = LocationSettings::deserialize(location).map_err(Error::custom)?;
+    location.timestamp_offset_minutes = timestamp_offset_minutes;
+    Ok(Some(location))
+}
+
+fn coordinate_to_f32<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Value = Deserialize::deserialize(deserializer)?;
+    match value {
+        Value::Float(float_val) => Ok(Some(float_val as f32)),
+        Value::Integer(int_val) => Ok(Some(int_val as f32)),
+        // Hand edited configs sometimes have quoted coordinates
+        Value::String(str_val) => str_val.trim().parse::<f32>().map(Some).map_err(|_| {
+            Error::custom(format!(
+                "Invalid coordinate '{}', expected a number like -46.6",
+                str_val
+            ))
+        }),
+        _ => Err(Error::custom(format!(
+            "Invalid coordinate {}, expected a number like -46.6",
+            value
+        ))),
+    }
+}
+
 fn location_accuracy_to_f32<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
 where
     D: Deserializer<'de>,
@@ -340,28 +867,160 @@ where
     }
 }
 
+// An IANA timezone name, e.g. "Pacific/Auckland", checked when the config is loaded
+fn deserialize_timezone<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let timezone = String::deserialize(deserializer)?;
+    timezone
+        .parse::<Tz>()
+        .map_err(|_| Error::custom(format!("Unknown timezone '{}'", timezone)))?;
+    Ok(Some(timezone))
+}
+
+/// The `[location]` section.  Timestamps are microseconds since the epoch.
 #[derive(Deserialize, Debug, PartialEq, Clone)]
-struct LocationSettings {
-    latitude: Option<f32>,
-    longitude: Option<f32>,
-    altitude: Option<f32>,
+pub struct LocationSettings {
+    #[serde(default, deserialize_with = "coordinate_to_f32")]
+    pub latitude: Option<f32>,
+    #[serde(default, deserialize_with = "coordinate_to_f32")]
+    pub longitude: Option<f32>,
+    pub altitude: Option<f32>,
 
     #[serde(
         deserialize_with = "timestamp_to_u64",
         default = "default_location_timestamp"
     )]
-    timestamp: Option<u64>,
+    pub timestamp: Option<u64>,
+    // The UTC offset the timestamp was written with, e.g. 780 for "+13:00", for showing the fix
+    // in local time.  Set by `deserialize_location`.
+    #[serde(skip)]
+    pub timestamp_offset_minutes: Option<i32>,
+    #[serde(
+        deserialize_with = "timestamp_to_u64",
+        default = "default_location_updated"
+    )]
+    pub updated: Option<u64>,
     #[serde(
         deserialize_with = "location_accuracy_to_f32",
         default = "default_location_accuracy"
     )]
-    accuracy: Option<f32>,
+    pub accuracy: Option<f32>,
+    // Tropical devices can reuse one day's sun times for this many days
+    #[serde(rename = "solar-cache-days")]
+    pub solar_cache_days: Option<u32>,
+    // The IANA timezone of the location, for absolute times
+    #[serde(default, deserialize_with = "deserialize_timezone")]
+    pub timezone: Option<String>,
+    // Degrees above the true horizon the sun disappears at, e.g. behind a ridge, which makes
+    // sunset earlier and sunrise later
+    #[serde(rename = "horizon-angle", default)]
+    pub horizon_angle: Option<f32>,
+}
+
+// The timestamp is written back with the offset it was read with, which a field can't see.
+impl Serialize for LocationSettings {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut location = serializer.serialize_struct("LocationSettings", 9)?;
+        location.serialize_field("latitude", &self.latitude)?;
+        location.serialize_field("longitude", &self.longitude)?;
+        location.serialize_field("altitude", &self.altitude)?;
+        if let Some(timestamp) = self.timestamp {
+            let offset_minutes = self.timestamp_offset_minutes.unwrap_or(0);
+            let timestamp = u64_to_toml_datetime(timestamp, offset_minutes)
+                .map_err(serde::ser::Error::custom)?;
+            location.serialize_field("timestamp", &timestamp)?;
+        } else {
+            location.skip_field("timestamp")?;
+        }
+        if let Some(updated) = self.updated {
+            let updated = u64_to_toml_datetime(updated, 0).map_err(serde::ser::Error::custom)?;
+            location.serialize_field("updated", &updated)?;
+        } else {
+            location.skip_field("updated")?;
+        }
+        location.serialize_field("accuracy", &self.accuracy)?;
+        location.serialize_field("solar-cache-days", &self.solar_cache_days)?;
+        if let Some(timezone) = &self.timezone {
+            location.serialize_field("timezone", timezone)?;
+        } else {
+            location.skip_field("timezone")?;
+        }
+        if let Some(horizon_angle) = self.horizon_angle {
+            location.serialize_field("horizon-angle", &horizon_angle)?;
+        } else {
+            location.skip_field("horizon-angle")?;
+        }
+        location.end()
+    }
+}
+
+// Relative offsets beyond half a day are more likely a unit mistake than intended, though large
+// offsets are allowed
+const LIKELY_RELATIVE_OFFSET_SECONDS: i32 = 12 * 60 * 60;
+
+// From below the Dead Sea shore to above Everest's summit, so anything outside is a typo
+const PLAUSIBLE_ALTITUDE_M: Range<f32> = -500.0..9000.0;
+
+// How far a timezone's standard offset can be from the one implied by the longitude (15° an
+// hour) before it looks like the wrong timezone.  Some, like western China on UTC+8, are
+// nearly 3 hours off.
+const TIMEZONE_LONGITUDE_TOLERANCE_HOURS: f32 = 4.0;
+
+// Sunrise and sunset times change slowly enough to reuse between the tropics
+const TROPIC_LATITUDE: f32 = 23.44;
+
+// Longest device name that fits in the binary config without being truncated
+const MAX_BINARY_DEVICE_NAME_LEN: usize = 63;
+
+// Default distance two location sources can disagree by when no accuracy is set.
+const LOCATION_MISMATCH_THRESHOLD_M: f32 = 100.0;
+
+// Bits of `DeviceConfig::status_code`
+pub const STATUS_REGISTERED: u8 = 1 << 0;
+pub const STATUS_LOCATED: u8 = 1 << 1;
+pub const STATUS_IN_WINDOW: u8 = 1 << 2;
+pub const STATUS_CONTINUOUS: u8 = 1 << 3;
+pub const STATUS_LOW_POWER: u8 = 1 << 4;
+pub const STATUS_DISABLED: u8 = 1 << 5;
+
+// Whether the standard (non daylight saving) offset of `tz` is close to the solar time at
+// `longitude`, going around the date line, so e.g. Kiribati on UTC+14 at 157°W still matches.
+fn timezone_matches_longitude(tz: &Tz, longitude: f32) -> bool {
+    let reference = NaiveDate::from_ymd_opt(2024, 1, 1)
+        .unwrap()
+        .and_time(NaiveTime::MIN);
+    let standard_offset = tz.offset_from_utc_datetime(&reference).base_utc_offset();
+    let offset_hours = standard_offset.num_minutes() as f32 / 60.0;
+    let difference = (offset_hours - longitude / 15.0).rem_euclid(24.0);
+    difference.min(24.0 - difference) <= TIMEZONE_LONGITUDE_TOLERANCE_HOURS
+}
+
+/// Great-circle distance in meters between two (latitude, longitude) points in degrees.
+pub fn haversine_distance_m(a: (f32, f32), b: (f32, f32)) -> f32 {
+    const EARTH_RADIUS_M: f64 = 6_371_000.0;
+    let (lat_a, lat_b) = ((a.0 as f64).to_radians(), (b.0 as f64).to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lng = (b.1 as f64 - a.1 as f64).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lng / 2.0).sin().powi(2);
+    (2.0 * EARTH_RADIUS_M * h.sqrt().asin()) as f32
 }
 
 #[derive(Debug, PartialEq, Clone)]
 struct HourMin {
     hour: u8,
     min: u8,
+    sec: u8,
+}
+
+impl HourMin {
+    fn naive_time(&self) -> NaiveTime {
+        NaiveTime::from_hms_opt(self.hour as u32, self.min as u32, self.sec as u32).unwrap()
+    }
 }
 
 fn timezone_offset_seconds() -> i32 {
@@ -374,47 +1033,634 @@ fn timezone_offset_seconds() -> i32 {
         .local_minus_utc()
 }
 
+/// The UTC instant of the wall clock time `local` in `tz`.  A time in the gap when the clocks go
+/// forward is moved to the first valid time after it (e.g. 02:30 becomes 03:00 if the clocks jump
+/// from 02:00 to 03:00), and a time that happens twice when they go back is the first of the two.
+pub fn local_to_utc_in(tz: &Tz, local: NaiveDateTime) -> NaiveDateTime {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.naive_utc(),
+        LocalResult::None => {
+            // Transitions happen on a whole minute, so the first valid minute is the new time
+            let start_of_minute = local.with_second(0).unwrap().with_nanosecond(0).unwrap();
+            let shifted = (1..=24 * 60)
+                .map(|minutes| start_of_minute + Duration::minutes(minutes))
+                .find_map(|time| tz.from_local_datetime(&time).earliest())
+                .expect("Timezone transitions are less than a day long");
+            warn!(
+                "{} doesn't exist in {}, the clocks go forward, using {} instead",
+                local,
+                tz,
+                shifted.naive_local()
+            );
+            shifted.naive_utc()
+        }
+    }
+}
+
+/// Formats a duration as whole hours and minutes, e.g. `"1h30m"`, truncating any seconds.
+/// Negative durations get a single leading sign, e.g. `"-1h30m"`.
+pub fn format_duration_hm(duration: Duration) -> String {
+    let minutes = duration.num_minutes();
+    let sign = if minutes < 0 { "-" } else { "" };
+    let minutes = minutes.unsigned_abs();
+    format!("{}{}h{}m", sign, minutes / 60, minutes % 60)
+}
+
+fn escape_ical_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct AbsRelTime {
     absolute_time: Option<HourMin>,
     relative_time_seconds: Option<i32>,
+    // Set when the time names its solar event, e.g. "civil_dusk-30m", overriding the window's
+    // start/stop anchor
+    anchor: Option<SolarAnchor>,
 }
 
 impl AbsRelTime {
     pub fn time_offset(&self) -> (bool, i32) {
+        self.time_offset_for_tz(timezone_offset_seconds())
+    }
+
+    /// Like `time_offset`, converting absolute times to UTC with `tz_offset_seconds` (east of
+    /// UTC) instead of the system timezone.  Absolute offsets are always within `0..86_400`.
+    pub fn time_offset_for_tz(&self, tz_offset_seconds: i32) -> (bool, i32) {
         // Absolute or relative time in seconds in the day
         if let Some(abs_time) = &self.absolute_time {
             // NOTE: We need to convert this to UTC offsets, since that's what our timestamp is.
-            let seconds_past_midnight =
-                (abs_time.hour as i32 * 60 * 60) + (abs_time.min as i32 * 60);
-            //println!("Seconds past midnight local {}", seconds_past_midnight);
-            let tz_offset = timezone_offset_seconds();
-            // println!(
-            //     "TZ offset {}, seconds past UTC midnight {}",
-            //     tz_offset,
-            //     (seconds_past_midnight - tz_offset) % 86_400
-            // );
-            (true, (seconds_past_midnight - tz_offset) % 86_400)
+            // The parts are widened first so out of range values can't overflow, and the
+            // euclidean remainder keeps times that are the day before in UTC positive.
+            let seconds_past_midnight = i32::from(abs_time.hour) * 60 * 60
+                + i32::from(abs_time.min) * 60
+                + i32::from(abs_time.sec);
+            (
+                true,
+                (seconds_past_midnight - tz_offset_seconds).rem_euclid(86_400),
+            )
         } else {
             (false, self.relative_time_seconds.unwrap())
         }
     }
-}
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
-struct TimeWindow {
-    #[serde(
-        rename = "start-recording",
-        deserialize_with = "from_time_abs_or_rel_str",
-        default = "default_recording_start_time"
-    )]
-    start_recording: AbsRelTime,
-    #[serde(
-        rename = "stop-recording",
+    /// The UTC instant this time resolves to on the local `date`.  Absolute times are converted
+    /// using `tz_offset_seconds` (east of UTC), and relative times are offset from their solar
+    /// event at `location`, or from sunset if no event was given.  Times relative to midnight
+    /// are offset from the start of `date` in `tz_offset_seconds`.  Returns `None` for a time
+    /// relative to the sun without a location, or when the sun doesn't rise or set that day.
+    pub fn resolve_for(
+        &self,
+        date: NaiveDate,
+        location: Option<&ResolvedLocation>,
+        tz_offset_seconds: i32,
+    ) -> Option<NaiveDateTime> {
+        if let Some(abs_time) = &self.absolute_time {
+            let local = date.and_hms_opt(
+                abs_time.hour as u32,
+                abs_time.min as u32,
+                abs_time.sec as u32,
+            )?;
+            return Some(local - Duration::seconds(tz_offset_seconds as i64));
+        }
+        let anchor = self.anchor.unwrap_or(SolarAnchor::Sunset);
+        let relative = Duration::seconds(self.relative_time_seconds.unwrap_or(0) as i64);
+        if anchor == SolarAnchor::Midnight {
+            return Some(local_midnight(date, tz_offset_seconds) + relative);
+        }
+        let event = solar_event_time(anchor, date, location?).ok()?;
+        Some(event + relative)
+    }
+
+    /// Like `resolve_for`, but with local times in `tz` on `date`, so absolute times follow any
+    /// daylight saving change that day.  See `local_to_utc_in` for times the clocks skip.
+    pub fn resolve_in_timezone(
+        &self,
+        date: NaiveDate,
+        location: Option<&ResolvedLocation>,
+        tz: &Tz,
+    ) -> Option<NaiveDateTime> {
+        if let Some(abs_time) = &self.absolute_time {
+            return Some(local_to_utc_in(tz, date.and_time(abs_time.naive_time())));
+        }
+        if self.anchor == Some(SolarAnchor::Midnight) {
+            let midnight = local_to_utc_in(tz, date.and_time(NaiveTime::MIN));
+            return Some(
+                midnight + Duration::seconds(self.relative_time_seconds.unwrap_or(0) as i64),
+            );
+        }
+        self.resolve_for(date, location, 0)
+    }
+
+    /// Formats the time the way it would be written in the config file, so `"HH:MM"` (or
+    /// `"HH:MM:SS"`) for absolute times and `"-1h30m"` style offsets for relative times, prefixed
+    /// with the solar event if one was given (e.g. `"civil_dusk-30m"`).
+    pub fn to_config_string(&self) -> String {
+        if let Some(abs_time) = &self.absolute_time {
+            if abs_time.sec != 0 {
+                format!(
+                    "{:02}:{:02}:{:02}",
+                    abs_time.hour, abs_time.min, abs_time.sec
+                )
+            } else {
+                format!("{:02}:{:02}", abs_time.hour, abs_time.min)
+            }
+        } else {
+            let seconds = self.relative_time_seconds.unwrap_or(0);
+            let mut out = String::new();
+            if let Some(anchor) = self.anchor {
+                out.push_str(anchor.keyword());
+                if seconds == 0 {
+                    return out;
+                }
+                if seconds > 0 {
+                    out.push('+');
+                }
+            }
+            if seconds < 0 {
+                out.push('-');
+            }
+            let seconds = seconds.unsigned_abs();
+            let (hours, mins, secs) = (seconds / 3600, (seconds % 3600) / 60, seconds % 60);
+            if hours != 0 {
+                out.push_str(&format!("{}h", hours));
+            }
+            if mins != 0 || (hours == 0 && secs == 0) {
+                out.push_str(&format!("{}m", mins));
+            }
+            if secs != 0 {
+                out.push_str(&format!("{}s", secs));
+            }
+            out
+        }
+    }
+}
+
+impl Serialize for AbsRelTime {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_config_string())
+    }
+}
+
+/// The solar event a relative recording time is offset from.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SolarAnchor {
+    Sunrise,
+    Sunset,
+    #[serde(rename = "civil_dawn")]
+    CivilDawn,
+    #[serde(rename = "civil_dusk")]
+    CivilDusk,
+    // Local midnight at the start of the day, for windows like "midnight+3h" that follow the
+    // clock without being absolute times
+    Midnight,
+}
+
+impl SolarAnchor {
+    const ALL: [SolarAnchor; 5] = [
+        SolarAnchor::Sunrise,
+        SolarAnchor::Sunset,
+        SolarAnchor::CivilDawn,
+        SolarAnchor::CivilDusk,
+        SolarAnchor::Midnight,
+    ];
+
+    /// The name used for the anchor in the config file.
+    pub fn keyword(self) -> &'static str {
+        match self {
+            SolarAnchor::Sunrise => "sunrise",
+            SolarAnchor::Sunset => "sunset",
+            SolarAnchor::CivilDawn => "civil_dawn",
+            SolarAnchor::CivilDusk => "civil_dusk",
+            SolarAnchor::Midnight => "midnight",
+        }
+    }
+
+    fn is_morning(self) -> bool {
+        matches!(
+            self,
+            SolarAnchor::Sunrise | SolarAnchor::CivilDawn | SolarAnchor::Midnight
+        )
+    }
+}
+
+fn default_start_anchor() -> SolarAnchor {
+    SolarAnchor::Sunset
+}
+
+fn default_stop_anchor() -> SolarAnchor {
+    SolarAnchor::Sunrise
+}
+
+/// Whether a relative window should record when the sun doesn't rise or set on a given day.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PolarBehaviour {
+    Record,
+    Off,
+}
+
+fn default_polar_day() -> PolarBehaviour {
+    PolarBehaviour::Off
+}
+
+fn default_polar_night() -> PolarBehaviour {
+    PolarBehaviour::Record
+}
+
+/// Why a recording window couldn't be worked out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowError {
+    /// Sunrise or sunset couldn't be calculated, e.g. during a polar day or night
+    Sun(SunTimesError),
+    /// A relative window has no coordinates to calculate the sun times for, e.g. a config with
+    /// a partial [location]
+    MissingLocation,
+    /// The offsets put the end of a window before its start, e.g. "sunset+1h" to "sunset-1h",
+    /// or "sunrise+6h" to "sunset-6h" on short winter days
+    EmptyWindow,
+}
+
+impl From<SunTimesError> for WindowError {
+    fn from(error: SunTimesError) -> Self {
+        WindowError::Sun(error)
+    }
+}
+
+// Midnight at the start of the local `date`, in UTC
+fn local_midnight(date: NaiveDate, tz_offset_seconds: i32) -> NaiveDateTime {
+    date.and_time(NaiveTime::MIN) - Duration::seconds(tz_offset_seconds as i64)
+}
+
+// When `anchor` happens on `date` at the given location, in UTC.  Midnight isn't a solar event,
+// and depends on the timezone, so callers resolve it themselves.  Only sunrise and sunset are
+// moved by a raised horizon, as twilight is about how dark the sky is.
+fn solar_event_time(
+    anchor: SolarAnchor,
+    date: NaiveDate,
+    location: &ResolvedLocation,
+) -> Result<NaiveDateTime, SunTimesError> {
+    let (lat, lng) = (location.lat_lng.0 as f64, location.lat_lng.1 as f64);
+    let altitude = location.altitude.unwrap_or(0.0) as f64;
+    let (morning, evening) = match anchor {
+        SolarAnchor::Midnight => unreachable!("midnight is resolved before the sun times"),
+        SolarAnchor::Sunrise | SolarAnchor::Sunset => {
+            let horizon_angle = location.horizon_angle.unwrap_or(0.0) as f64;
+            try_sun_times_with_horizon(date, lat, lng, altitude, horizon_angle)?
+        }
+        SolarAnchor::CivilDawn | SolarAnchor::CivilDusk => {
+            try_civil_twilight(date, lat, lng, altitude)?
+        }
+    };
+    Ok(if anchor.is_morning() {
+        morning.naive_utc()
+    } else {
+        evening.naive_utc()
+    })
+}
+
+/// The clock time `offset` seconds after midnight.  Offsets outside a single day, such as 86400
+/// from a "24:00" time, wrap around rather than panicking.
+fn time_of_day_from_offset(offset: i32) -> NaiveTime {
+    let seconds = offset.rem_euclid(86_400) as u32;
+    NaiveTime::from_num_seconds_from_midnight_opt(seconds, 0)
+        .expect("a wrapped offset is always within a day")
+}
+
+// The window to report out of each window's next (or current) one: one that is already active,
+// then whichever starts first.
+fn earliest_window(
+    windows: Vec<(NaiveDateTime, NaiveDateTime)>,
+    now_utc: &NaiveDateTime,
+) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    windows
+        .into_iter()
+        .min_by_key(|(start, end)| ((*start).max(*now_utc), *end))
+}
+
+// Whether `window` records through a polar day or night that stops its times being calculated.
+fn records_through(window: &TimeWindow, error: &WindowError) -> bool {
+    match error {
+        WindowError::Sun(SunTimesError::PolarDay) => window.polar_day == PolarBehaviour::Record,
+        WindowError::Sun(SunTimesError::PolarNight) => window.polar_night == PolarBehaviour::Record,
+        _ => false,
+    }
+}
+
+/// Picks the next (or current) recording window for `now_utc` from a window's start and end
+/// offsets, as returned by `AbsRelTime::time_offset`.  Relative offsets are applied to the solar
+/// events given by `anchors`, whose times come from `sun_event_time`, so the selection can be
+/// tested with made up sunrise and sunset times.
+fn select_window<F>(
+    now_utc: &NaiveDateTime,
+    mut start_offset: i32,
+    mut end_offset: i32,
+    is_absolute_start: bool,
+    is_absolute_end: bool,
+    anchors: (SolarAnchor, SolarAnchor),
+    sun_event_time: F,
+) -> Result<(NaiveDateTime, NaiveDateTime), WindowError>
+where
+    F: Fn(SolarAnchor, NaiveDate) -> Result<NaiveDateTime, WindowError>,
+{
+    if is_absolute_end && end_offset < 0 {
+        end_offset = 86_400 + end_offset;
+    }
+    if is_absolute_start && start_offset < 0 {
+        start_offset = 86_400 + start_offset;
+    }
+    if !is_absolute_start || !is_absolute_end {
+        let (start_anchor, stop_anchor) = anchors;
+        // A window that starts relative to a morning event and stops relative to an evening
+        // one falls within a single day, otherwise it stops relative to the next day's event.
+        // Anything after midnight, or around a single event, is on the same day.
+        let stop_day_offset = if start_anchor == SolarAnchor::Midnight
+            || start_anchor == stop_anchor
+            || (start_anchor.is_morning() && !stop_anchor.is_morning())
+        {
+            0
+        } else {
+            1
+        };
+        // Large offsets can push a window's start before the previous day's window ends, or
+        // its end before its own start, so order the candidates explicitly, dropping empty
+        // ones and merging any that overlap.  In a mixed window the absolute time is the nearest
+        // one after a relative start or before a relative end, so a relative start with an
+        // earlier absolute stop, e.g. "-1h" to "02:00", crosses midnight.
+        let candidate = |days| -> Result<(NaiveDateTime, NaiveDateTime), WindowError> {
+            let date = now_utc.date() + Duration::days(days);
+            if is_absolute_start {
+                let end = sun_event_time(stop_anchor, date + Duration::days(stop_day_offset))?
+                    + Duration::seconds(end_offset as i64);
+                return Ok((time_of_day_before(end, start_offset), end));
+            }
+            let start =
+                sun_event_time(start_anchor, date)? + Duration::seconds(start_offset as i64);
+            if is_absolute_end {
+                return Ok((start, time_of_day_after(start, end_offset)));
+            }
+            let end = sun_event_time(stop_anchor, date + Duration::days(stop_day_offset))?
+                + Duration::seconds(end_offset as i64);
+            Ok((start, end))
+        };
+        let mut results: Vec<_> = (-2..=1).map(|days| (days, candidate(days))).collect();
+        // Far east of UTC, the UTC date can still be the day before, so a window that ends in
+        // the local morning may have finished for every date so far.
+        if results
+            .iter()
+            .all(|(_, result)| !matches!(result, Ok((_, end)) if end >= now_utc))
+        {
+            results.push((2, candidate(2)));
+        }
+        // A day without a sunrise or sunset only matters if its window could have been the one
+        // for `now_utc`, i.e. the other days' windows all end before now or start after it.  So
+        // a polar night ending yesterday doesn't stop today's window from being used.
+        let could_contain_now = |days: i64| {
+            results.iter().all(|(other, result)| match result {
+                Ok((start, end)) => {
+                    (*other < days && end < now_utc) || (*other > days && start > now_utc)
+                }
+                Err(_) => true,
+            })
+        };
+        if let Some(error) = results
+            .iter()
+            .find_map(|(days, result)| result.as_ref().err().filter(|_| could_contain_now(*days)))
+        {
+            return Err(*error);
+        }
+        let mut candidates: Vec<_> = results
+            .into_iter()
+            .filter_map(|(_, result)| result.ok())
+            .collect();
+        candidates.retain(|(start, end)| end > start);
+        candidates.sort();
+        let mut merged: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+        for (start, end) in candidates {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        // Take the first window that hasn't finished yet, if the offsets give one at all
+        return merged
+            .into_iter()
+            .find(|(_, end)| *end >= *now_utc)
+            .ok_or(WindowError::EmptyWindow);
+    }
+
+    let mut start_time = NaiveDateTime::new(now_utc.date(), time_of_day_from_offset(start_offset));
+    let mut end_time = NaiveDateTime::new(now_utc.date(), time_of_day_from_offset(end_offset));
+    let start_minus_one_day = start_time - Duration::days(1);
+    let mut start_plus_one_day = start_time + Duration::days(1);
+    let mut end_minus_one_day = end_time - Duration::days(1);
+    let end_plus_one_day = end_time + Duration::days(1);
+
+    if start_minus_one_day > end_minus_one_day {
+        end_minus_one_day = end_minus_one_day + Duration::days(1);
+    }
+    if start_plus_one_day > end_plus_one_day {
+        start_plus_one_day = start_time;
+    }
+    if end_minus_one_day > *now_utc {
+        start_time = start_minus_one_day;
+        end_time = end_minus_one_day;
+    }
+    if end_time < start_time {
+        end_time = end_plus_one_day;
+    }
+    if *now_utc > end_time {
+        start_time = start_plus_one_day;
+        end_time = end_plus_one_day;
+    }
+    Ok((start_time, end_time))
+}
+
+// The first time `offset` seconds past a UTC midnight that comes after `after`.
+fn time_of_day_after(after: NaiveDateTime, offset: i32) -> NaiveDateTime {
+    let time = NaiveDateTime::new(after.date(), time_of_day_from_offset(offset));
+    if time > after {
+        time
+    } else {
+        time + Duration::days(1)
+    }
+}
+
+// The last time `offset` seconds past a UTC midnight that comes before `before`.
+fn time_of_day_before(before: NaiveDateTime, offset: i32) -> NaiveDateTime {
+    let time = NaiveDateTime::new(before.date(), time_of_day_from_offset(offset));
+    if time < before {
+        time
+    } else {
+        time - Duration::days(1)
+    }
+}
+
+/// The next (or current) window between the wall clock times `start` and `stop` in `tz`, so each
+/// day follows any daylight saving change.  A window that stops before it starts crosses
+/// midnight, and times the clocks skip are handled as in `local_to_utc_in`.
+fn select_window_in_timezone(
+    now_utc: &NaiveDateTime,
+    start: NaiveTime,
+    stop: NaiveTime,
+    tz: &Tz,
+) -> (NaiveDateTime, NaiveDateTime) {
+    let today = tz.from_utc_datetime(now_utc).date_naive();
+    // Yesterday's window may still be running, and tomorrow's always ends after now
+    (-1..=1)
+        .map(|days| {
+            let date = today + Duration::days(days);
+            let stop_date = if stop < start {
+                date + Duration::days(1)
+            } else {
+                date
+            };
+            (
+                local_to_utc_in(tz, date.and_time(start)),
+                local_to_utc_in(tz, stop_date.and_time(stop)),
+            )
+        })
+        .find(|(_, end)| end >= now_utc)
+        .expect("Tomorrow's window ends after now")
+}
+
+// The first time after `after` that the wall clock in `tz` reads `time`.
+fn wall_clock_after(tz: &Tz, after: NaiveDateTime, time: NaiveTime) -> NaiveDateTime {
+    let date = tz.from_utc_datetime(&after).date_naive();
+    (0..=2)
+        .map(|days| local_to_utc_in(tz, (date + Duration::days(days)).and_time(time)))
+        .find(|utc| *utc > after)
+        .expect("The wall clock reads every time within two days")
+}
+
+// The last time before `before` that the wall clock in `tz` read `time`.
+fn wall_clock_before(tz: &Tz, before: NaiveDateTime, time: NaiveTime) -> NaiveDateTime {
+    let date = tz.from_utc_datetime(&before).date_naive();
+    (0..=2)
+        .map(|days| local_to_utc_in(tz, (date - Duration::days(days)).and_time(time)))
+        .find(|utc| *utc < before)
+        .expect("The wall clock reads every time within two days")
+}
+
+/// What the absolute window offsets sent to the firmware were converted from, so it can redo the
+/// conversion if its own idea of local time differs.  Written to the binary config as a byte.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(u8)]
+pub enum TimezoneBasis {
+    // The device's system timezone at the time the config was written
+    SystemLocal = 0,
+    // A timezone named in the config, e.g. "Pacific/Auckland"
+    ConfiguredIana = 1,
+    // No timezone, the window only has offsets from sunrise/sunset
+    Utc = 2,
+}
+
+impl TryFrom<u8> for TimezoneBasis {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TimezoneBasis::SystemLocal),
+            1 => Ok(TimezoneBasis::ConfiguredIana),
+            2 => Ok(TimezoneBasis::Utc),
+            other => Err(other),
+        }
+    }
+}
+
+/// Trade off between recording detail and storage, from `[thermal-recorder].quality`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum RecordingQuality {
+    Low = 0,
+    Medium = 1,
+    #[default]
+    High = 2,
+}
+
+/// What to do once free space drops to `[thermal-recorder].min-disk-space-mb`, from
+/// `[thermal-recorder].disk-policy`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum DiskPolicy {
+    // Stop making new recordings
+    #[default]
+    Stop = 0,
+    // Delete the oldest recordings to make room
+    Rotate = 1,
+}
+
+/// Why a config records continuously, from `DeviceConfig::continuous_reason`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ContinuousReason {
+    // [thermal-recorder].constant-recorder = true
+    Explicit,
+    // A window with the same absolute start and stop time, e.g. "12:00" to "12:00"
+    EqualAbsoluteTimes,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+struct TimeWindow {
+    #[serde(
+        rename = "start-recording",
+        deserialize_with = "from_time_abs_or_rel_str",
+        default = "default_recording_start_time"
+    )]
+    start_recording: AbsRelTime,
+    #[serde(
+        rename = "stop-recording",
         deserialize_with = "from_time_abs_or_rel_str",
         default = "default_recording_stop_time"
     )]
     stop_recording: AbsRelTime,
+    #[serde(rename = "start-anchor", default = "default_start_anchor")]
+    start_anchor: SolarAnchor,
+    #[serde(rename = "stop-anchor", default = "default_stop_anchor")]
+    stop_anchor: SolarAnchor,
+    #[serde(rename = "polar-day", default = "default_polar_day")]
+    polar_day: PolarBehaviour,
+    #[serde(rename = "polar-night", default = "default_polar_night")]
+    polar_night: PolarBehaviour,
+    #[serde(default, deserialize_with = "deserialize_cron_schedule")]
+    cron: Option<CronSchedule>,
+    // Overrides the global low power mode while this window is active
+    #[serde(rename = "use-low-power-mode", default)]
+    use_low_power_mode: Option<bool>,
+}
+
+// A window is either a cron schedule or a start/stop pair, so only one of them is written back.
+impl Serialize for TimeWindow {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut window = serializer.serialize_struct("TimeWindow", 7)?;
+        if let Some(cron) = &self.cron {
+            window.serialize_field("cron", cron)?;
+        } else {
+            window.serialize_field("start-recording", &self.start_recording)?;
+            window.serialize_field("stop-recording", &self.stop_recording)?;
+            window.serialize_field("start-anchor", &self.start_anchor)?;
+            window.serialize_field("stop-anchor", &self.stop_anchor)?;
+            window.serialize_field("polar-day", &self.polar_day)?;
+            window.serialize_field("polar-night", &self.polar_night)?;
+        }
+        if let Some(use_low_power_mode) = self.use_low_power_mode {
+            window.serialize_field("use-low-power-mode", &use_low_power_mode)?;
+        } else {
+            window.skip_field("use-low-power-mode")?;
+        }
+        window.end()
+    }
 }
 
 impl Default for TimeWindow {
@@ -422,32 +1668,98 @@ impl Default for TimeWindow {
         TimeWindow {
             start_recording: default_recording_start_time(),
             stop_recording: default_recording_stop_time(),
+            start_anchor: default_start_anchor(),
+            stop_anchor: default_stop_anchor(),
+            polar_day: default_polar_day(),
+            polar_night: default_polar_night(),
+            cron: None,
+            use_low_power_mode: None,
         }
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
-struct DeviceRegistration {
-    id: Option<u32>,
-    group: Option<String>,
-    name: Option<String>,
-    server: Option<String>,
+/// The `[device]` section, set when the device is registered with the server.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct DeviceRegistration {
+    pub id: Option<u32>,
+    pub group: Option<String>,
+    pub name: Option<String>,
+    pub server: Option<String>,
+    /// The largest device id the installed firmware can handle, if it has a limit
+    #[serde(
+        rename = "firmware-max-id",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub firmware_max_id: Option<u32>,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+/// The optional `[contact]` section, for who looks after the device in the field.  Only kept
+/// so it survives edits, nothing on the device uses it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ContactInfo {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct ThermalRecordingSettings {
-    #[serde(rename = "output-dir", default = "default_output_dir")]
+    #[serde(
+        rename = "output-dir",
+        default = "default_output_dir",
+        deserialize_with = "deserialize_output_dir"
+    )]
     output_dir: String,
+    // Where to record to when output-dir can't be written to, e.g. a missing SD card
+    #[serde(
+        rename = "backup-dir",
+        default,
+        deserialize_with = "deserialize_backup_dir",
+        skip_serializing_if = "Option::is_none"
+    )]
+    backup_dir: Option<String>,
     #[serde(rename = "constant-recorder", default = "default_constant_recorder")]
     constant_recorder: bool,
     #[serde(rename = "use-low-power-mode", default = "default_low_power_mode")]
     use_low_power_mode: bool,
+    #[serde(rename = "motion-only", default = "default_motion_only")]
+    motion_only: bool,
+    // Seconds to power on before each window starts, so the sensor can stabilise
+    #[serde(
+        rename = "warmup",
+        default,
+        deserialize_with = "deserialize_warmup",
+        serialize_with = "serialize_warmup"
+    )]
+    warmup_seconds: u32,
+    // Seconds of video kept from before motion triggers a recording
+    #[serde(
+        rename = "pre-trigger",
+        default,
+        deserialize_with = "deserialize_pre_trigger",
+        serialize_with = "serialize_pre_trigger"
+    )]
+    pre_trigger_secs: u16,
+    #[serde(default)]
+    quality: RecordingQuality,
+    // Chance of recording in each eligible minute, for studies that sample at random
+    #[serde(
+        rename = "sample-probability",
+        default = "default_sample_probability",
+        deserialize_with = "deserialize_sample_probability"
+    )]
+    sample_probability: f32,
     #[serde(rename = "min-disk-space-mb", default = "default_min_disk_space_mb")]
     min_disk_space_mb: u32,
+    #[serde(rename = "disk-policy", default)]
+    disk_policy: DiskPolicy,
+    // The mask is rasterised on load, so the original regions can't be written back
     #[serde(
         rename = "mask-regions",
         default = "default_mask_regions",
-        deserialize_with = "deserialize_mask_regions"
+        deserialize_with = "deserialize_mask_regions",
+        skip_serializing
     )]
     mask_regions: DetectionMask,
 }
@@ -456,29 +1768,317 @@ impl Default for ThermalRecordingSettings {
     fn default() -> Self {
         ThermalRecordingSettings {
             output_dir: default_output_dir(),
+            backup_dir: None,
             constant_recorder: default_constant_recorder(),
             min_disk_space_mb: default_min_disk_space_mb(),
             use_low_power_mode: default_low_power_mode(),
+            motion_only: default_motion_only(),
+            warmup_seconds: 0,
+            pre_trigger_secs: 0,
+            quality: RecordingQuality::default(),
+            sample_probability: default_sample_probability(),
+            disk_policy: DiskPolicy::default(),
             mask_regions: default_mask_regions(),
         }
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct ThermalThrottlerSettings {
     #[serde(default = "default_activate_thermal_throttler")]
     activate: bool,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+impl Default for ThermalThrottlerSettings {
+    fn default() -> Self {
+        ThermalThrottlerSettings {
+            activate: default_activate_thermal_throttler(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct PowerSettings {
+    #[serde(rename = "min-voltage")]
+    min_voltage: Option<f32>,
+}
+
+// The settings that fall back to a default when missing, as `section.key` paths
+const DEFAULTABLE_FIELDS: [&str; 13] = [
+    "windows",
+    "thermal-recorder.output-dir",
+    "thermal-recorder.constant-recorder",
+    "thermal-recorder.use-low-power-mode",
+    "thermal-recorder.motion-only",
+    "thermal-recorder.warmup",
+    "thermal-recorder.pre-trigger",
+    "thermal-recorder.quality",
+    "thermal-recorder.sample-probability",
+    "thermal-recorder.min-disk-space-mb",
+    "thermal-recorder.disk-policy",
+    "thermal-recorder.mask-regions",
+    "thermal-throttler.activate",
+];
+
+// Settings that weren't in the source file, filled in when loaded through `from_toml_str`.  This
+// is where the settings came from rather than a setting itself, so it doesn't affect equality.
+#[derive(Debug, Clone, Default)]
+struct DefaultedFields(Vec<&'static str>);
+
+impl PartialEq for DefaultedFields {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+// Version 1 is the layout used by the old Go thermal-recorder, before schema versions were added
+const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+fn default_schema_version() -> u32 {
+    CURRENT_SCHEMA_VERSION
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct MetaSettings {
+    #[serde(rename = "schema-version", default = "default_schema_version")]
+    schema_version: u32,
+}
+
+impl Default for MetaSettings {
+    fn default() -> Self {
+        MetaSettings {
+            schema_version: default_schema_version(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Utf8(std::string::FromUtf8Error),
+    Parse(toml::de::Error),
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    #[cfg(feature = "yaml")]
+    Yaml(serde_yaml::Error),
+    #[cfg(feature = "protobuf")]
+    Protobuf(prost::DecodeError),
+    #[cfg(feature = "protobuf")]
+    Decode(String),
+    #[cfg(feature = "protobuf")]
+    Mask(MaskError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Error reading config: {}", e),
+            ConfigError::Utf8(e) => write!(f, "Error parsing config as utf8: {}", e),
+            ConfigError::Parse(e) => write!(f, "Error deserializing TOML config: {}", e),
+            #[cfg(feature = "json")]
+            ConfigError::Json(e) => write!(f, "Error parsing JSON config: {}", e),
+            #[cfg(feature = "yaml")]
+            ConfigError::Yaml(e) => write!(f, "Error parsing YAML config: {}", e),
+            #[cfg(feature = "protobuf")]
+            ConfigError::Protobuf(e) => write!(f, "Error decoding protobuf config: {}", e),
+            #[cfg(feature = "protobuf")]
+            ConfigError::Decode(e) => write!(f, "Invalid value in protobuf config: {}", e),
+            #[cfg(feature = "protobuf")]
+            ConfigError::Mask(e) => write!(f, "Invalid detection mask: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ConfigWarning {
+    LocationMismatch { distance_m: f32 },
+    InvalidServerUrl { server: String, reason: String },
+    OverlappingWindows { first: usize, second: usize },
+    DeviceNameTooLong { length: usize },
+    DeviceIdTooLarge { id: u32, max: u32 },
+    ImplausibleAltitude { altitude_m: f32 },
+    LargeRelativeOffset { window: usize, time: String },
+    TimezoneMismatch { timezone: String, longitude: f32 },
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigWarning::LocationMismatch { distance_m } => write!(
+                f,
+                "Config location is {:.0}m away from the GPS location, the device may have moved",
+                distance_m
+            ),
+            ConfigWarning::InvalidServerUrl { server, reason } => write!(
+                f,
+                "Server '{}' is not a valid http(s) URL: {}",
+                server, reason
+            ),
+            ConfigWarning::OverlappingWindows { first, second } => {
+                write!(f, "Recording windows {} and {} overlap", first, second)
+            }
+            ConfigWarning::DeviceNameTooLong { length } => write!(
+                f,
+                "Device name is {} bytes, only the first {} are sent to the firmware",
+                length, MAX_BINARY_DEVICE_NAME_LEN
+            ),
+            ConfigWarning::DeviceIdTooLarge { id, max } => write!(
+                f,
+                "Device id {} is above {}, the largest the firmware supports",
+                id, max
+            ),
+            ConfigWarning::ImplausibleAltitude { altitude_m } => write!(
+                f,
+                "Altitude {}m is outside {}m to {}m, it may have an extra digit",
+                altitude_m, PLAUSIBLE_ALTITUDE_M.start, PLAUSIBLE_ALTITUDE_M.end
+            ),
+            ConfigWarning::LargeRelativeOffset { window, time } => write!(
+                f,
+                "Recording window {} has a large offset '{}', check it isn't in the wrong unit",
+                window, time
+            ),
+            ConfigWarning::TimezoneMismatch {
+                timezone,
+                longitude,
+            } => write!(
+                f,
+                "Timezone '{}' is more than {} hours from the local solar time at longitude {}, \
+                 check it is for this location",
+                timezone, TIMEZONE_LONGITUDE_TOLERANCE_HOURS, longitude
+            ),
+        }
+    }
+}
+
+/// The config location with its optional metadata, as returned by `DeviceConfig::location`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResolvedLocation {
+    pub lat_lng: (f32, f32),
+    pub altitude: Option<f32>,
+    pub accuracy: Option<f32>,
+    pub timestamp: Option<u64>,
+    pub updated: Option<u64>,
+    pub horizon_angle: Option<f32>,
+}
+
+/// New location values, e.g. from a GPS fix, for `DeviceConfig::apply_location_update`.  Only
+/// the fields that are set are changed.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LocationUpdate {
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub altitude: Option<f32>,
+    pub accuracy: Option<f32>,
+    // Microseconds since the epoch, like the config timestamps
+    pub timestamp: Option<u64>,
+}
+
+/// The solar events a relative recording window was calculated from, from
+/// `DeviceConfig::debug_solar_anchors`, for comparing with the firmware's own sun times.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SolarAnchors {
+    /// The event the window start is offset from and when it was, unless the start is absolute
+    pub start: Option<(SolarAnchor, NaiveDateTime)>,
+    /// The event the window end is offset from and when it was, unless the end is absolute
+    pub end: Option<(SolarAnchor, NaiveDateTime)>,
+}
+
+/// How a new config can be applied while the device is running, from
+/// `DeviceConfig::reload_impact`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ReloadImpact {
+    /// Nothing that changed affects a recording in progress
+    LiveSafe,
+    /// A setting used by the recording in progress changed, so apply it once the window ends
+    DeferUntilWindowEnd,
+}
+
+/// Conditions outside the config that affect whether the device records.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct RecordingContext {
+    pub battery_voltage: Option<f32>,
+}
+
+/// Why the device isn't recording, from `DeviceConfig::inactive_reason`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InactiveReason {
+    /// The next window starts later on the same local day
+    BeforeWindow,
+    /// Today's windows have finished, and the next one is on a later day
+    AfterWindow,
+    /// The sun doesn't set, and the window is set to be off during polar day
+    PolarDay,
+    /// The sun doesn't rise, and the window is set to be off during polar night
+    PolarNight,
+    /// A relative window has no usable latitude and longitude to calculate sun times for
+    InvalidLocation,
+    /// The window's offsets put its end before its start around now, so it never opens
+    EmptyWindow,
+    /// The battery is below `[power].min-voltage`
+    LowVoltage,
+}
+
+/// The commonly used config values in one place, with anything that may be missing from the
+/// config as an explicit `Option`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConfigParts {
+    pub device_id: Option<u32>,
+    pub device_name: Option<String>,
+    pub group: Option<String>,
+    pub server: Option<String>,
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub altitude: Option<f32>,
+    pub accuracy: Option<f32>,
+    pub location_timestamp: Option<u64>,
+    pub location_updated: Option<u64>,
+    pub recording_window: (AbsRelTime, AbsRelTime),
+    pub output_dir: String,
+    pub continuous_recorder: bool,
+    pub low_power_mode: bool,
+    pub motion_only: bool,
+    pub thermal_throttler_enabled: bool,
+}
+
+/// Every section of the config is optional, so an empty file gives a device that is unregistered,
+/// has no location, and records from 30 minutes before sunset until 30 minutes after sunrise.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct DeviceConfig {
-    #[serde(rename = "windows", default)]
-    recording_window: TimeWindow,
+    #[serde(
+        rename = "windows",
+        default = "default_recording_windows",
+        deserialize_with = "deserialize_time_windows"
+    )]
+    recording_windows: Vec<TimeWindow>,
     #[serde(rename = "device")]
     device_info: Option<DeviceRegistration>,
-    #[serde(rename = "thermal-recorder", default)]
+    contact: Option<ContactInfo>,
+    #[serde(
+        rename = "thermal-recorder",
+        default,
+        deserialize_with = "deserialize_recording_settings"
+    )]
     recording_settings: ThermalRecordingSettings,
+    #[serde(rename = "thermal-throttler", default)]
+    thermal_throttler: ThermalThrottlerSettings,
+    #[serde(default, deserialize_with = "deserialize_location")]
     location: Option<LocationSettings>,
+    power: Option<PowerSettings>,
+    // The audio recorder's own schedule, which works the same way as a [windows] table
+    #[serde(
+        rename = "audio-recorder",
+        default,
+        deserialize_with = "deserialize_audio_window"
+    )]
+    audio_window: Option<TimeWindow>,
+    #[serde(default)]
+    meta: MetaSettings,
+    #[serde(skip)]
+    sun_times_cache: SunTimesCache<SolarAnchor>,
+    #[serde(skip)]
+    defaulted_fields: DefaultedFields,
 }
 
 impl DeviceConfig {
@@ -512,55 +2112,575 @@ impl DeviceConfig {
             .as_bytes()
     }
 
-    pub fn lat_lng(&self) -> (f32, f32) {
-        (
-            self.location.as_ref().unwrap().latitude.unwrap(),
-            self.location.as_ref().unwrap().longitude.unwrap(),
-        )
+    pub fn server(&self) -> Option<&str> {
+        self.device_info.as_ref()?.server.as_deref()
     }
-    pub fn location_timestamp(&self) -> Option<u64> {
-        self.location.as_ref().unwrap().timestamp
+
+    pub fn contact_name(&self) -> Option<&str> {
+        self.contact.as_ref()?.name.as_deref()
     }
-    pub fn location_altitude(&self) -> Option<f32> {
-        self.location.as_ref().unwrap().altitude
+
+    pub fn contact_email(&self) -> Option<&str> {
+        self.contact.as_ref()?.email.as_deref()
     }
-    pub fn location_accuracy(&self) -> Option<f32> {
-        self.location.as_ref().unwrap().accuracy
+
+    pub fn contact_phone(&self) -> Option<&str> {
+        self.contact.as_ref()?.phone.as_deref()
     }
-    pub fn recording_window(&self) -> (AbsRelTime, AbsRelTime) {
-        (
-            self.recording_window.start_recording.clone(),
-            self.recording_window.stop_recording.clone(),
-        )
+
+    /// The server to upload to, or `None` if it isn't set or isn't a valid http(s) URL (see
+    /// `validate`).
+    pub fn server_url(&self) -> Option<Url> {
+        self.try_server_url().ok().flatten()
     }
 
-    pub fn output_dir(&self) -> &str {
-        &self.recording_settings.output_dir
+    fn try_server_url(&self) -> Result<Option<Url>, String> {
+        let Some(server) = self.server() else {
+            return Ok(None);
+        };
+        let url = Url::parse(server).map_err(|e| e.to_string())?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(format!("unsupported scheme '{}'", url.scheme()));
+        }
+        Ok(Some(url))
     }
 
-    pub fn is_continuous_recorder(&self) -> bool {
-        self.recording_settings.constant_recorder
-            || (self
-                .recording_window
-                .start_recording
-                .absolute_time
-                .is_some()
-                && self.recording_window.stop_recording.absolute_time.is_some()
-                && self.recording_window.start_recording == self.recording_window.stop_recording)
+    /// Whether the device name fits in the binary config shared with the firmware, which only
+    /// has room for 63 bytes.
+    // `Option::is_none_or` would need Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn name_fits_binary(&self) -> bool {
+        let name = self
+            .device_info
+            .as_ref()
+            .and_then(|device| device.name.as_ref());
+        name.map_or(true, |name| name.len() <= MAX_BINARY_DEVICE_NAME_LEN)
     }
-    pub fn use_low_power_mode(&self) -> bool {
-        self.recording_settings.use_low_power_mode
+
+    /// Whether the device id is at most `max`, e.g. the largest id older firmware expects.  An
+    /// unregistered device has no id, so it always fits.
+    // `Option::is_none_or` would need Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn device_id_fits(&self, max: u32) -> bool {
+        let id = self.device_info.as_ref().and_then(|device| device.id);
+        id.map_or(true, |id| id <= max)
     }
 
-    pub fn load_from_fs() -> Result<DeviceConfig, &'static str> {
-        let config_toml =
-            fs::read("/etc/cacophony/config.toml").map_err(|_| "Error reading file from disk")?;
-        let config_toml_str =
-            String::from_utf8(config_toml).map_err(|_| "Error parsing string from utf8")?;
-        let device_config: Result<DeviceConfig, _> = toml::from_str(&config_toml_str);
-        match device_config {
-            Ok(device_config) => {
-                // TODO: Make sure device has sane windows etc.
+    pub fn lat_lng(&self) -> (f32, f32) {
+        (
+            self.location.as_ref().unwrap().latitude.unwrap(),
+            self.location.as_ref().unwrap().longitude.unwrap(),
+        )
+    }
+
+    /// Compares the config location against a location from another source (e.g. the GPS file),
+    /// warning if they disagree by more than the location accuracy.
+    pub fn check_location_conflict(&self, other_lat_lng: (f32, f32)) -> Option<ConfigWarning> {
+        if !self.has_location() {
+            return None;
+        }
+        let threshold = self
+            .location_accuracy()
+            .unwrap_or(LOCATION_MISMATCH_THRESHOLD_M);
+        let distance_m = haversine_distance_m(self.lat_lng(), other_lat_lng);
+        if distance_m > threshold {
+            let warning = ConfigWarning::LocationMismatch { distance_m };
+            warn!("{}", warning);
+            Some(warning)
+        } else {
+            None
+        }
+    }
+    /// Whether `new` can replace this config straight away.  Changes to where or how recordings
+    /// are made (output-dir, backup-dir, quality, motion-only and the detection mask) would
+    /// split a recording in progress, so they wait for the window to end.  Anything else, like
+    /// the location or windows, is only used between recordings.
+    pub fn reload_impact(&self, new: &DeviceConfig) -> ReloadImpact {
+        let (old, new) = (&self.recording_settings, &new.recording_settings);
+        if old.output_dir != new.output_dir
+            || old.backup_dir != new.backup_dir
+            || old.quality != new.quality
+            || old.motion_only != new.motion_only
+            || old.mask_regions != new.mask_regions
+        {
+            ReloadImpact::DeferUntilWindowEnd
+        } else {
+            ReloadImpact::LiveSafe
+        }
+    }
+
+    /// A copy of this config at another location, e.g. to move a template to a new site.
+    pub fn clone_with_location(&self, location: LocationSettings) -> DeviceConfig {
+        DeviceConfig {
+            location: Some(location),
+            ..self.clone()
+        }
+    }
+
+    /// A copy of this config registered as another device, keeping everything else, e.g. when
+    /// provisioning several devices at one site from a template.
+    pub fn clone_with_registration(&self, registration: DeviceRegistration) -> DeviceConfig {
+        DeviceConfig {
+            device_info: Some(registration),
+            ..self.clone()
+        }
+    }
+
+    /// Applies the fields that are set in `update` over the current location, leaving the rest
+    /// as they were, and sets `updated` to the time on `clock`.
+    pub fn apply_location_update(&mut self, update: LocationUpdate, clock: &dyn Clock) {
+        let location = self.location.get_or_insert_with(|| LocationSettings {
+            latitude: None,
+            longitude: None,
+            altitude: None,
+            timestamp: default_location_timestamp(),
+            updated: default_location_updated(),
+            accuracy: default_location_accuracy(),
+            solar_cache_days: None,
+            timezone: None,
+            timestamp_offset_minutes: None,
+            horizon_angle: None,
+        });
+        if let Some(latitude) = update.latitude {
+            location.latitude = Some(latitude);
+        }
+        if let Some(longitude) = update.longitude {
+            location.longitude = Some(longitude);
+        }
+        if let Some(altitude) = update.altitude {
+            location.altitude = Some(altitude);
+        }
+        if let Some(accuracy) = update.accuracy {
+            location.accuracy = Some(accuracy);
+        }
+        if let Some(timestamp) = update.timestamp {
+            location.timestamp = Some(timestamp);
+            location.timestamp_offset_minutes = None;
+        }
+        location.updated = Some(clock.now_utc().and_utc().timestamp_micros() as u64);
+        // The cached sun times are for the old location
+        self.sun_times_cache.clear();
+    }
+
+    /// All the location fields at once, or `None` if the config has no latitude and longitude.
+    pub fn location(&self) -> Option<ResolvedLocation> {
+        let location = self.location.as_ref()?;
+        Some(ResolvedLocation {
+            lat_lng: (location.latitude?, location.longitude?),
+            altitude: location.altitude,
+            accuracy: location.accuracy,
+            timestamp: location.timestamp,
+            updated: location.updated,
+            horizon_angle: location.horizon_angle,
+        })
+    }
+    // Location timestamps are stored as microseconds since the unix epoch
+    pub fn location_timestamp(&self) -> Option<u64> {
+        self.location.as_ref().unwrap().timestamp
+    }
+    pub fn location_timestamp_millis(&self) -> Option<u64> {
+        self.location_timestamp().map(|micros| micros / 1_000)
+    }
+    pub fn location_timestamp_secs(&self) -> Option<u64> {
+        self.location_timestamp().map(|micros| micros / 1_000_000)
+    }
+    pub fn location_updated(&self) -> Option<u64> {
+        self.location.as_ref().unwrap().updated
+    }
+    pub fn location_altitude(&self) -> Option<f32> {
+        self.location.as_ref().unwrap().altitude
+    }
+    /// The UTC offset `[location].timestamp` was written with, in minutes east of UTC, e.g. 780
+    /// for "+13:00".
+    pub fn location_timestamp_offset_minutes(&self) -> Option<i32> {
+        self.location.as_ref()?.timestamp_offset_minutes
+    }
+    pub fn location_accuracy(&self) -> Option<f32> {
+        self.location.as_ref().unwrap().accuracy
+    }
+    // The first recording window, which is the one shared with the firmware
+    fn primary_window(&self) -> &TimeWindow {
+        &self.recording_windows[0]
+    }
+
+    pub fn recording_window(&self) -> (AbsRelTime, AbsRelTime) {
+        let window = self.primary_window();
+        (
+            window.start_recording.clone(),
+            window.stop_recording.clone(),
+        )
+    }
+
+    pub fn output_dir(&self) -> &str {
+        &self.recording_settings.output_dir
+    }
+
+    pub fn backup_dir(&self) -> Option<&str> {
+        self.recording_settings.backup_dir.as_deref()
+    }
+
+    /// The directory the recorder should write to: `output-dir` if it can be created and
+    /// written to, otherwise `backup-dir`.  The chosen directory is created if it doesn't exist,
+    /// and the error from `output-dir` is returned when there is no backup.
+    pub fn effective_output_dir(&self) -> std::io::Result<PathBuf> {
+        let usable = |dir: &str| -> std::io::Result<PathBuf> {
+            fs::create_dir_all(dir)?;
+            // Permission bits don't account for the owner or for root, so actually try writing
+            let probe = Path::new(dir).join(format!(".tc2-config-probe-{}", std::process::id()));
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&probe)?;
+            fs::remove_file(&probe)?;
+            Ok(PathBuf::from(dir))
+        };
+        match (usable(self.output_dir()), self.backup_dir()) {
+            (Err(e), Some(backup_dir)) => {
+                warn!(
+                    "Can't use output directory '{}' ({}), using '{}'",
+                    self.output_dir(),
+                    e,
+                    backup_dir
+                );
+                usable(backup_dir)
+            }
+            (result, _) => result,
+        }
+    }
+
+    pub fn is_continuous_recorder(&self) -> bool {
+        self.continuous_reason().is_some()
+    }
+
+    /// Why the device records continuously, if it does, for diagnostics.  The explicit flag
+    /// takes precedence if both apply.
+    pub fn continuous_reason(&self) -> Option<ContinuousReason> {
+        if self.recording_settings.constant_recorder {
+            Some(ContinuousReason::Explicit)
+        } else if self.recording_windows.iter().any(|window| {
+            window.start_recording.absolute_time.is_some()
+                && window.stop_recording.absolute_time.is_some()
+                && window.start_recording == window.stop_recording
+        }) {
+            Some(ContinuousReason::EqualAbsoluteTimes)
+        } else {
+            None
+        }
+    }
+
+    /// Only save motion triggered recordings inside the recording window, rather than recording
+    /// constantly.
+    pub fn motion_only(&self) -> bool {
+        self.recording_settings.motion_only
+    }
+
+    pub fn quality(&self) -> RecordingQuality {
+        self.recording_settings.quality
+    }
+
+    /// The chance of recording in each eligible minute, 1.0 (the default) to record in all of them.
+    pub fn sample_probability(&self) -> f32 {
+        self.recording_settings.sample_probability
+    }
+
+    /// Whether to record in the minute identified by `minute_seed`, e.g. the minutes since the
+    /// Unix epoch, picked at random with `sample_probability`.  The seed is mixed with SplitMix64
+    /// rather than Rust's hasher, whose output isn't stable, so the firmware and server can work
+    /// out the same minutes.
+    pub fn should_sample(&self, minute_seed: u64) -> bool {
+        let mut z = minute_seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // The top 53 bits as a fraction in [0, 1), which is never below a probability of 0.0
+        // and always below 1.0
+        let fraction = (z >> 11) as f64 / (1u64 << 53) as f64;
+        fraction < self.sample_probability() as f64
+    }
+
+    /// What the firmware does when free space reaches `min-disk-space-mb`.
+    pub fn disk_policy(&self) -> DiskPolicy {
+        self.recording_settings.disk_policy
+    }
+
+    /// How long before each window starts the device should power on, in seconds.
+    pub fn warmup_seconds(&self) -> u32 {
+        self.recording_settings.warmup_seconds
+    }
+
+    /// Seconds of video to keep from before motion triggers a recording.
+    pub fn pre_trigger_secs(&self) -> u16 {
+        self.recording_settings.pre_trigger_secs
+    }
+
+    pub fn thermal_throttler_enabled(&self) -> bool {
+        self.thermal_throttler.activate
+    }
+
+    /// Battery voltage below which the device shouldn't record, if any.
+    pub fn min_voltage(&self) -> Option<f32> {
+        self.power.as_ref()?.min_voltage
+    }
+
+    pub fn use_low_power_mode(&self) -> bool {
+        self.recording_settings.use_low_power_mode
+    }
+
+    /// Whether the sun is up at the device's location at `now_utc`, or `None` if it has no
+    /// location.  The sun counts as down once it is below `[location].horizon-angle`.
+    pub fn is_daytime(&self, now_utc: &NaiveDateTime) -> Option<bool> {
+        let location = self.location()?;
+        let (lat, lng) = location.lat_lng;
+        let altitude = location.altitude.unwrap_or(0.0) as f64;
+        let horizon_angle = location.horizon_angle.unwrap_or(0.0) as f64;
+        // The UTC date doesn't line up with the local day, so check the neighbouring days too
+        let mut is_daytime = false;
+        for days in -1..=1 {
+            let date = now_utc.date() + Duration::days(days);
+            match try_sun_times_with_horizon(date, lat as f64, lng as f64, altitude, horizon_angle)
+            {
+                Ok((sunrise, sunset)) => {
+                    is_daytime |= sunrise.naive_utc() <= *now_utc && *now_utc < sunset.naive_utc()
+                }
+                Err(SunTimesError::PolarDay) if days == 0 => return Some(true),
+                Err(SunTimesError::PolarNight) if days == 0 => return Some(false),
+                Err(SunTimesError::PolarDay | SunTimesError::PolarNight) => {}
+                Err(_) => return None,
+            }
+        }
+        Some(is_daytime)
+    }
+
+    /// Whether low power mode applies at `now_utc`: the setting of the first active window that
+    /// overrides it, otherwise the global `use-low-power-mode`.
+    pub fn effective_low_power_mode(&self, now_utc: &NaiveDateTime) -> bool {
+        self.recording_windows
+            .iter()
+            .filter(|window| window.use_low_power_mode.is_some())
+            .find(|window| self.window_contains(window, now_utc))
+            .and_then(|window| window.use_low_power_mode)
+            .unwrap_or(self.use_low_power_mode())
+    }
+
+    /// Metadata for the uploader to attach to recordings, or `None` if the device is not yet
+    /// registered.
+    pub fn recording_metadata(&self) -> Option<RecordingMetadata> {
+        if !self.is_registered() {
+            return None;
+        }
+        let (latitude, longitude) = if self.has_location() {
+            let (latitude, longitude) = self.lat_lng();
+            (Some(latitude), Some(longitude))
+        } else {
+            (None, None)
+        };
+        let device_info = self.device_info.as_ref().unwrap();
+        Some(RecordingMetadata {
+            device_id: self.device_id(),
+            device_name: device_info.name.clone().unwrap(),
+            group: device_info.group.clone().unwrap(),
+            latitude,
+            longitude,
+            continuous_recorder: self.is_continuous_recorder(),
+            low_power_mode: self.use_low_power_mode(),
+        })
+    }
+
+    /// All of the commonly used values at once, without the getters' unwrapping.
+    pub fn parts(&self) -> ConfigParts {
+        let device_info = self.device_info.as_ref();
+        let location = self.location.as_ref();
+        ConfigParts {
+            device_id: device_info.and_then(|device| device.id),
+            device_name: device_info.and_then(|device| device.name.clone()),
+            group: device_info.and_then(|device| device.group.clone()),
+            server: device_info.and_then(|device| device.server.clone()),
+            latitude: location.and_then(|location| location.latitude),
+            longitude: location.and_then(|location| location.longitude),
+            altitude: location.and_then(|location| location.altitude),
+            accuracy: location.and_then(|location| location.accuracy),
+            location_timestamp: location.and_then(|location| location.timestamp),
+            location_updated: location.and_then(|location| location.updated),
+            recording_window: self.recording_window(),
+            output_dir: self.output_dir().to_string(),
+            continuous_recorder: self.is_continuous_recorder(),
+            low_power_mode: self.use_low_power_mode(),
+            motion_only: self.motion_only(),
+            thermal_throttler_enabled: self.thermal_throttler_enabled(),
+        }
+    }
+
+    pub fn into_parts(self) -> ConfigParts {
+        self.parts()
+    }
+
+    /// Writes the config back out as TOML.  Keys this crate doesn't know about aren't kept, and
+    /// mask regions are dropped as only the rasterised mask is stored.
+    pub fn to_toml_string(&self) -> String {
+        toml::to_string(self).expect("Config should serialize to TOML")
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<DeviceConfig, ConfigError> {
+        let mut config_toml = Vec::new();
+        reader
+            .read_to_end(&mut config_toml)
+            .map_err(ConfigError::Io)?;
+        let config_toml_str = String::from_utf8(config_toml).map_err(ConfigError::Utf8)?;
+        DeviceConfig::from_toml_str(&config_toml_str)
+    }
+
+    /// Parses and migrates a config file's contents.  Syntax errors keep the line and column
+    /// from toml, for highlighting in an editor.
+    pub fn from_toml_str(config_toml: &str) -> Result<DeviceConfig, ConfigError> {
+        let config: Value = toml::from_str(strip_bom(config_toml)).map_err(ConfigError::Parse)?;
+        DeviceConfig::from_migrated_value(DeviceConfig::migrate(config))
+    }
+
+    /// Parses and migrates a config written as JSON, with the same structure and values as the
+    /// TOML file.  Timestamps are RFC 3339 strings, e.g. `"2023-11-02T08:24:21+13:00"`.
+    #[cfg(feature = "json")]
+    pub fn from_json_str(config_json: &str) -> Result<DeviceConfig, ConfigError> {
+        // Going through a TOML value keeps loading the same as from_toml_str, including migration
+        let config: Value =
+            serde_json::from_str(strip_bom(config_json)).map_err(ConfigError::Json)?;
+        DeviceConfig::from_migrated_value(DeviceConfig::migrate(config))
+    }
+
+    /// Parses and migrates a config written as YAML, with the same structure and values as the
+    /// TOML file.  Times and timestamps should be quoted so they stay strings.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml_str(config_yaml: &str) -> Result<DeviceConfig, ConfigError> {
+        let config: Value =
+            serde_yaml::from_str(strip_bom(config_yaml)).map_err(ConfigError::Yaml)?;
+        DeviceConfig::from_migrated_value(DeviceConfig::migrate(config))
+    }
+
+    /// The config as a `DeviceConfig` message from `proto/device_config.proto`, including the
+    /// rasterised detection mask, for servers that would rather not parse TOML or the firmware's
+    /// binary layout.
+    #[cfg(feature = "protobuf")]
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        protobuf::encode(self)
+    }
+
+    /// Loads a config written by `to_protobuf`, with the same parsing and defaults as a TOML
+    /// file for anything the message doesn't set.
+    #[cfg(feature = "protobuf")]
+    pub fn from_protobuf(bytes: &[u8]) -> Result<DeviceConfig, ConfigError> {
+        protobuf::decode(bytes)
+    }
+
+    fn from_migrated_value(config: Value) -> Result<DeviceConfig, ConfigError> {
+        let defaulted_fields = DEFAULTABLE_FIELDS
+            .into_iter()
+            .filter(|path| {
+                let mut value = Some(&config);
+                for key in path.split('.') {
+                    value = value.and_then(|value| value.get(key));
+                }
+                value.is_none()
+            })
+            .collect();
+        let mut device_config: DeviceConfig = config.try_into().map_err(ConfigError::Parse)?;
+        device_config.defaulted_fields = DefaultedFields(defaulted_fields);
+        Ok(device_config)
+    }
+
+    /// The settings that weren't set in the config file and are using their defaults, e.g.
+    /// `"thermal-recorder.output-dir"`, so a provisioning tool can point out anything forgotten.
+    /// Only known for configs loaded from a file or with `from_toml_str`.
+    pub fn defaulted_fields(&self) -> Vec<&'static str> {
+        self.defaulted_fields.0.clone()
+    }
+
+    pub fn schema_version(&self) -> u32 {
+        self.meta.schema_version
+    }
+
+    /// Upgrades a config written for an older schema to the current one, so it can be
+    /// deserialized.  A config without `[meta].schema-version` is checked for legacy keys, and
+    /// anything already current is returned unchanged apart from the version being set.  A
+    /// config from a newer schema is left as it is, with a warning, as it can't be downgraded.
+    // `Option::is_none_or` would need Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn migrate(mut config: Value) -> Value {
+        let Value::Table(table) = &mut config else {
+            return config;
+        };
+        let version = table
+            .get("meta")
+            .and_then(|meta| meta.get("schema-version"))
+            .and_then(|version| version.as_integer());
+        if let Some(version) = version.filter(|version| *version > CURRENT_SCHEMA_VERSION as i64) {
+            warn!(
+                "Config schema version {} is newer than {}, loading it without migrating",
+                version, CURRENT_SCHEMA_VERSION
+            );
+            return config;
+        }
+        if version.map_or(true, |version| version < 2) {
+            let has_windows = table.contains_key("windows");
+            if let Some(Value::Table(recorder)) = table.get_mut("thermal-recorder") {
+                match recorder.remove("use-sunrise-sunset") {
+                    Some(Value::Boolean(false)) if !has_windows => {
+                        // Without sunrise/sunset or any windows the old recorder never stopped
+                        info!("Migrating use-sunrise-sunset = false to constant-recorder = true");
+                        recorder.insert(String::from("constant-recorder"), Value::Boolean(true));
+                    }
+                    Some(_) => {
+                        info!("Removed use-sunrise-sunset, recording windows are set by [windows]")
+                    }
+                    None => {}
+                }
+                if recorder.remove("max-secs").is_some() {
+                    info!("Removed max-secs, recordings now last as long as there is motion");
+                }
+            }
+        }
+        let meta = table
+            .entry("meta")
+            .or_insert_with(|| Value::Table(toml::map::Map::new()));
+        if let Value::Table(meta) = meta {
+            meta.insert(
+                String::from("schema-version"),
+                Value::Integer(CURRENT_SCHEMA_VERSION as i64),
+            );
+        }
+        config
+    }
+
+    /// Loads and merges every `*.toml` file in `dir` (e.g. `/etc/cacophony/config.d`), in file
+    /// name order.  Tables are merged key by key, and any other value in a later file replaces
+    /// the earlier one, so later files override earlier ones.
+    pub fn load_from_dir<P: AsRef<Path>>(dir: P) -> Result<DeviceConfig, ConfigError> {
+        let mut paths = fs::read_dir(dir)
+            .map_err(ConfigError::Io)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ConfigError::Io)?;
+        paths.retain(|path| path.is_file() && path.extension() == Some(OsStr::new("toml")));
+        paths.sort();
+        let mut merged = Value::Table(toml::map::Map::new());
+        for path in paths {
+            let config_toml = fs::read(&path).map_err(ConfigError::Io)?;
+            let config_toml_str = String::from_utf8(config_toml).map_err(ConfigError::Utf8)?;
+            let fragment: Value =
+                toml::from_str(strip_bom(&config_toml_str)).map_err(ConfigError::Parse)?;
+            merge_toml(&mut merged, fragment);
+        }
+        DeviceConfig::from_migrated_value(DeviceConfig::migrate(merged))
+    }
+
+    pub fn load_from_fs() -> Result<DeviceConfig, ConfigError> {
+        DeviceConfig::load_from_fs_with_clock(&SystemClock)
+    }
+
+    pub fn load_from_fs_with_clock(clock: &dyn Clock) -> Result<DeviceConfig, ConfigError> {
+        let config_toml = fs::read("/etc/cacophony/config.toml").map_err(ConfigError::Io)?;
+        let config_toml_str = String::from_utf8(config_toml).map_err(ConfigError::Utf8)?;
+        match DeviceConfig::from_toml_str(&config_toml_str) {
+            Ok(device_config) => {
+                // TODO: Make sure device has sane windows etc.
                 if !device_config.has_location() {
                     error!(
                 "No location set for this device. To enter recording mode, a location must be set."
@@ -575,260 +2695,1183 @@ impl DeviceConfig {
                 }
                 info!("Got config {:?}", device_config);
 
-                let inside_recording_window =
-                    device_config.time_is_in_recording_window(&Utc::now().naive_utc());
+                let now_utc = clock.now_utc();
+                let inside_recording_window = device_config.time_is_in_recording_window(&now_utc);
                 info!("Inside recording window: {}", inside_recording_window);
                 if !inside_recording_window {
-                    device_config.print_next_recording_window(&Utc::now().naive_utc());
+                    device_config.print_next_recording_window(&now_utc);
                 }
 
                 Ok(device_config)
             }
-            Err(msg) => {
-                error!("Toml parse error: {:?}", msg);
-                Err("Error deserializing TOML config")
+            Err(e) => {
+                error!("Toml parse error: {}", e);
+                Err(e)
             }
         }
     }
 
     pub fn next_recording_window(&self, now_utc: &NaiveDateTime) -> (NaiveDateTime, NaiveDateTime) {
-        let (is_absolute_start, mut start_offset) =
-            self.recording_window.start_recording.time_offset();
-        let (is_absolute_end, mut end_offset) = self.recording_window.stop_recording.time_offset();
-        if is_absolute_end && end_offset < 0 {
-            end_offset = 86_400 + end_offset;
-        }
-        if is_absolute_start && start_offset < 0 {
-            start_offset = 86_400 + start_offset;
-        }
-        let (window_start, window_end) = if !is_absolute_start || !is_absolute_end {
-            let location = self
-                .location
-                .as_ref()
-                .expect("Relative recording windows require a location");
-            let (lat, lng) = (
-                location
-                    .latitude
-                    .expect("Relative recording windows require a valid latitude"),
-                location
-                    .longitude
-                    .expect("Relative recording windows require a valid longitude"),
-            );
-            let altitude = location.altitude;
-            let yesterday_utc = *now_utc - Duration::days(1);
-            let (_, yesterday_sunset) = sun_times(
-                yesterday_utc.date(),
-                lat as f64,
-                lng as f64,
-                altitude.unwrap_or(0.0) as f64,
-            )
-            .unwrap();
-            let yesterday_sunset =
-                yesterday_sunset.naive_utc() + Duration::seconds(start_offset as i64);
-            let (today_sunrise, today_sunset) = sun_times(
-                now_utc.date(),
-                lat as f64,
-                lng as f64,
-                altitude.unwrap_or(0.0) as f64,
-            )
-            .unwrap();
-            let today_sunrise = today_sunrise.naive_utc() + Duration::seconds(end_offset as i64);
-            let today_sunset = today_sunset.naive_utc() + Duration::seconds(start_offset as i64);
-            let tomorrow_utc = *now_utc + Duration::days(1);
-            let (tomorrow_sunrise, tomorrow_sunset) = sun_times(
-                tomorrow_utc.date(),
-                lat as f64,
-                lng as f64,
-                altitude.unwrap_or(0.0) as f64,
-            )
-            .unwrap();
-            let tomorrow_sunrise =
-                tomorrow_sunrise.naive_utc() + Duration::seconds(end_offset as i64);
-            let tomorrow_sunset =
-                tomorrow_sunset.naive_utc() + Duration::seconds(start_offset as i64);
-
-            if *now_utc > today_sunset && *now_utc > tomorrow_sunrise {
-                let two_days_from_now_utc = *now_utc + Duration::days(2);
-                let (two_days_sunrise, _) = sun_times(
-                    two_days_from_now_utc.date(),
-                    lat as f64,
-                    lng as f64,
-                    altitude.unwrap_or(0.0) as f64,
-                )
-                .unwrap();
-                let two_days_sunrise =
-                    two_days_sunrise.naive_utc() + Duration::seconds(end_offset as i64);
-                (Some(tomorrow_sunset), Some(two_days_sunrise))
-            } else if (*now_utc > today_sunset && *now_utc < tomorrow_sunrise)
-                || (*now_utc < today_sunset && *now_utc > today_sunrise)
-            {
-                (Some(today_sunset), Some(tomorrow_sunrise))
-            } else if *now_utc < tomorrow_sunset
-                && *now_utc < today_sunrise
-                && *now_utc > yesterday_sunset
-            {
-                (Some(yesterday_sunset), Some(today_sunrise))
-            } else {
-                panic!("Unable to calculate relative time window");
+        self.try_next_recording_window(now_utc)
+            .expect("Unable to calculate recording window, the sun may not rise or set today")
+    }
+
+    /// Like `next_recording_window`, but returns an error rather than panicking when there is no
+    /// sunrise or sunset to base a relative window on (i.e. polar day or night, or no location),
+    /// or when the offsets put the window's end before its start.
+    pub fn try_next_recording_window(
+        &self,
+        now_utc: &NaiveDateTime,
+    ) -> Result<(NaiveDateTime, NaiveDateTime), WindowError> {
+        self.try_next_window_among(&self.recording_windows, now_utc)
+    }
+
+    /// The sunrise/sunset instants behind the next (or current) window of the primary window,
+    /// which is the one sent to the firmware.  `None` if the window isn't relative to the sun, or
+    /// can't be calculated.
+    pub fn debug_solar_anchors(&self, now_utc: &NaiveDateTime) -> Option<SolarAnchors> {
+        let window = self.primary_window();
+        if window.cron.is_some() || self.is_continuous_recorder() {
+            return None;
+        }
+        let (start, end) = self.next_window_for(window, now_utc).ok()?;
+        let event = |time: &AbsRelTime, window_anchor: SolarAnchor, instant: NaiveDateTime| {
+            let offset = time.relative_time_seconds?;
+            if time.absolute_time.is_some() {
+                return None;
             }
-        } else {
-            (None, None)
+            let anchor = time.anchor.unwrap_or(window_anchor);
+            Some((anchor, instant - Duration::seconds(offset as i64)))
         };
+        let anchors = SolarAnchors {
+            start: event(&window.start_recording, window.start_anchor, start),
+            end: event(&window.stop_recording, window.stop_anchor, end),
+        };
+        if anchors.start.is_none() && anchors.end.is_none() {
+            None
+        } else {
+            Some(anchors)
+        }
+    }
+
+    /// The next (or current) audio recording window, or `None` if there is no [audio-recorder]
+    /// schedule or no sunrise or sunset to base a relative window on.
+    pub fn audio_window(&self, now_utc: &NaiveDateTime) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let window = self.audio_window.as_ref()?;
+        self.try_next_window_among(std::slice::from_ref(window), now_utc)
+            .ok()
+    }
+
+    pub fn audio_is_active(&self, date_time_utc: &NaiveDateTime) -> bool {
+        self.audio_window
+            .as_ref()
+            .is_some_and(|window| self.window_contains(window, date_time_utc))
+    }
 
-        let mut start_time = if !is_absolute_start {
-            window_start.unwrap()
+    fn try_next_window_among(
+        &self,
+        windows: &[TimeWindow],
+        now_utc: &NaiveDateTime,
+    ) -> Result<(NaiveDateTime, NaiveDateTime), WindowError> {
+        let windows = windows
+            .iter()
+            .map(|window| self.next_window_for(window, now_utc))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(earliest_window(windows, now_utc).unwrap())
+    }
+
+    fn next_window_for(
+        &self,
+        window: &TimeWindow,
+        now_utc: &NaiveDateTime,
+    ) -> Result<(NaiveDateTime, NaiveDateTime), WindowError> {
+        self.next_window_with(window, now_utc, &SunTimesCache::default())
+    }
+
+    // As `next_window_for`, looking sunrise and sunset up in `sun_times` so that resolving many
+    // windows only calculates each of them once.
+    fn next_window_with(
+        &self,
+        window: &TimeWindow,
+        now_utc: &NaiveDateTime,
+        sun_times: &SunTimesCache<SolarAnchor>,
+    ) -> Result<(NaiveDateTime, NaiveDateTime), WindowError> {
+        if let Some(cron) = &window.cron {
+            let (start, end) = cron
+                .next_window(&self.local_time(now_utc))
+                .expect("Unable to calculate cron recording window");
+            return Ok((self.utc_time(start), self.utc_time(end)));
+        }
+        let (start, stop) = (&window.start_recording, &window.stop_recording);
+        let tz = self.configured_timezone();
+        if let (Some(tz), Some(start_time), Some(stop_time)) =
+            (tz, &start.absolute_time, &stop.absolute_time)
+        {
+            let (start_time, stop_time) = (start_time.naive_time(), stop_time.naive_time());
+            return Ok(select_window_in_timezone(
+                now_utc, start_time, stop_time, &tz,
+            ));
+        }
+        let tz_offset_seconds = self.utc_offset_seconds(now_utc);
+        let (is_absolute_start, start_offset) = start.time_offset_for_tz(tz_offset_seconds);
+        let (is_absolute_end, end_offset) = stop.time_offset_for_tz(tz_offset_seconds);
+        let anchors = (
+            window.start_recording.anchor.unwrap_or(window.start_anchor),
+            window.stop_recording.anchor.unwrap_or(window.stop_anchor),
+        );
+        let (window_start, window_end) = select_window(
+            now_utc,
+            start_offset,
+            end_offset,
+            is_absolute_start,
+            is_absolute_end,
+            anchors,
+            |anchor, date| {
+                if anchor == SolarAnchor::Midnight {
+                    return Ok(self.utc_time(date.and_time(NaiveTime::MIN)));
+                }
+                // Relative windows need a latitude and longitude, which a partially provisioned
+                // [location] may not have yet
+                let location = self.location().ok_or(WindowError::MissingLocation)?;
+                Ok(sun_times.get_or_insert_with(anchor, date, || {
+                    self.cached_solar_event_time(anchor, date, &location)
+                })?)
+            },
+        )?;
+        // The offset is only right for today, so put the absolute end of a mixed window at the
+        // wall clock time on the day it falls on
+        Ok(match (tz, &start.absolute_time, &stop.absolute_time) {
+            (Some(tz), Some(time), None) => (
+                wall_clock_before(&tz, window_end, time.naive_time()),
+                window_end,
+            ),
+            (Some(tz), None, Some(time)) => (
+                window_start,
+                wall_clock_after(&tz, window_start, time.naive_time()),
+            ),
+            _ => (window_start, window_end),
+        })
+    }
+
+    // Seconds east of UTC for local times at `at_utc`: the configured timezone's offset then, or
+    // the system timezone's current offset without one.
+    fn utc_offset_seconds(&self, at_utc: &NaiveDateTime) -> i32 {
+        match self.configured_timezone() {
+            Some(tz) => (tz.from_utc_datetime(at_utc).naive_local() - *at_utc).num_seconds() as i32,
+            None => timezone_offset_seconds(),
+        }
+    }
+
+    // The local wall clock time at `utc`, in the configured timezone or else the system's.
+    fn local_time(&self, utc: &NaiveDateTime) -> NaiveDateTime {
+        *utc + Duration::seconds(self.utc_offset_seconds(utc) as i64)
+    }
+
+    // The UTC instant of the local wall clock time `local`.  With a configured timezone this
+    // follows daylight saving changes, see `local_to_utc_in`.
+    fn utc_time(&self, local: NaiveDateTime) -> NaiveDateTime {
+        match self.configured_timezone() {
+            Some(tz) => local_to_utc_in(&tz, local),
+            None => local - Duration::seconds(timezone_offset_seconds() as i64),
+        }
+    }
+    /// Whether sun times are reused across days, which is only done for tropical devices with
+    /// `[location].solar-cache-days` set, where sunrise and sunset move by at most a few
+    /// minutes a week.
+    pub fn uses_stable_solar_schedule(&self) -> bool {
+        self.solar_cache_days() > 1
+            && self
+                .location()
+                .is_some_and(|location| location.lat_lng.0.abs() <= TROPIC_LATITUDE)
+    }
+
+    fn solar_cache_days(&self) -> u32 {
+        self.location
+            .as_ref()
+            .and_then(|location| location.solar_cache_days)
+            .unwrap_or(1)
+    }
+
+    // The time of `anchor` on `date`, shifted from the first day of its block of
+    // `solar-cache-days` days when the solar schedule is stable enough to reuse.
+    fn cached_solar_event_time(
+        &self,
+        anchor: SolarAnchor,
+        date: NaiveDate,
+        location: &ResolvedLocation,
+    ) -> Result<NaiveDateTime, SunTimesError> {
+        if !self.uses_stable_solar_schedule() {
+            return solar_event_time(anchor, date, location);
+        }
+        let days_into_block = date.num_days_from_ce() as i64 % self.solar_cache_days() as i64;
+        let block_start = date - Duration::days(days_into_block);
+        // Windows are only worked out a few days either side of now, so blocks more than a week
+        // from this one are dropped rather than kept for as long as the config is loaded
+        let keep_days = self.solar_cache_days() as i64 + 7;
+        self.sun_times_cache
+            .retain_dates(|cached| (cached - block_start).num_days().abs() <= keep_days);
+        let event = self
+            .sun_times_cache
+            .get_or_insert_with(anchor, block_start, || {
+                solar_event_time(anchor, block_start, location)
+            })?;
+        Ok(event + Duration::days(days_into_block))
+    }
+
+    /// The most recent recording window, from any of the windows, that started before the next
+    /// (or current) window.  `None` if the next window can't be calculated, e.g. a relative
+    /// window without a location, or if no window started in the three days before it.
+    pub fn previous_recording_window(
+        &self,
+        now_utc: &NaiveDateTime,
+    ) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let (next_start, _) = self.try_next_recording_window(now_utc).ok()?;
+        // Windows are at most a day long, so the previous one started within the last few days
+        let from_utc = next_start - Duration::days(3);
+        self.recording_windows
+            .iter()
+            .flat_map(|window| self.window_occurrences(window, &from_utc, &next_start))
+            .filter(|(start, _)| *start < next_start)
+            .max_by_key(|(start, _)| *start)
+    }
+
+    /// Whether the next recording window starts on the same local date as `now_local`, e.g. for
+    /// saying "next recording: tonight" rather than "tomorrow".  A window that is already active
+    /// counts as today.  `None` if the window can't be worked out, e.g. a relative window without
+    /// a location or during polar day or night.
+    pub fn next_window_is_today(&self, now_local: &NaiveDateTime) -> Option<bool> {
+        let now_utc = self.utc_time(*now_local);
+        let (start, _) = self.try_next_recording_window(&now_utc).ok()?;
+        Some(start <= now_utc || self.local_time(&start).date() == now_local.date())
+    }
+
+    /// The start and end of the recording window as local clock times, for drawing a timeline of
+    /// the day.  Absolute times are returned as configured, relative times are resolved against
+    /// the next (or current) recording window.  `None` if a relative time can't be resolved, e.g.
+    /// without a location or during polar day or night.
+    pub fn todays_local_window(&self, now_local: &NaiveDateTime) -> Option<(NaiveTime, NaiveTime)> {
+        let window = self.primary_window();
+        let (start, stop) = (&window.start_recording, &window.stop_recording);
+        let now_utc = self.utc_time(*now_local);
+        let utc_window = if start.absolute_time.is_none() || stop.absolute_time.is_none() {
+            Some(self.next_window_for(window, &now_utc).ok()?)
         } else {
-            NaiveDateTime::new(
-                now_utc.date(),
-                NaiveTime::from_num_seconds_from_midnight_opt(start_offset as u32, 0).unwrap(),
-            )
+            None
         };
-        let mut end_time = if !is_absolute_end {
-            window_end.unwrap()
-        } else {
-            NaiveDateTime::new(
-                now_utc.date(),
-                NaiveTime::from_num_seconds_from_midnight_opt(end_offset as u32, 0).unwrap(),
-            )
+        let start_time = match &start.absolute_time {
+            Some(abs_time) => abs_time.naive_time(),
+            None => self.local_time(&utc_window?.0).time(),
+        };
+        let end_time = match &stop.absolute_time {
+            Some(abs_time) => abs_time.naive_time(),
+            None => self.local_time(&utc_window?.1).time(),
         };
+        Some((start_time, end_time))
+    }
+
+    /// Roughly when the device records on `date`, as local clock times, for a human readable
+    /// summary of the schedule.  Each window is resolved from local midday, so a dusk to dawn
+    /// window gives that evening's start and the following morning's end, and the result spans
+    /// the earliest start to the latest end of all windows.  Returns `None` when no window can be
+    /// resolved, e.g. a relative window during polar day or night.
+    pub fn approx_local_schedule(&self, date: NaiveDate) -> Option<(NaiveTime, NaiveTime)> {
+        let midday_utc = self.utc_time(date.and_hms_opt(12, 0, 0)?);
+        let windows: Vec<(NaiveDateTime, NaiveDateTime)> = self
+            .recording_windows
+            .iter()
+            .filter_map(|window| self.next_window_for(window, &midday_utc).ok())
+            .collect();
+        let start = windows.iter().map(|(start, _)| *start).min()?;
+        let end = windows.iter().map(|(_, end)| *end).max()?;
+        Some((self.local_time(&start).time(), self.local_time(&end).time()))
+    }
 
-        if is_absolute_start || is_absolute_end {
-            let start_minus_one_day = start_time - Duration::days(1);
-            let mut start_plus_one_day = start_time + Duration::days(1);
-            let mut end_minus_one_day = end_time - Duration::days(1);
-            let end_plus_one_day = end_time + Duration::days(1);
+    /// The recording windows (in UTC) starting on each of the `days` UTC days from `from`, for
+    /// exporting to schedulers that don't know about sunrise/sunset.  Days with no window, such
+    /// as polar days or nights for relative windows, are skipped.
+    pub fn materialize_schedule(
+        &self,
+        from: NaiveDate,
+        days: u32,
+    ) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let from_utc = from.and_hms_opt(0, 0, 0).unwrap();
+        let until_utc = from_utc + Duration::days(days as i64);
+        let mut windows = Vec::new();
+        let mut now_utc = from_utc;
+        while now_utc < until_utc {
+            match self.try_next_recording_window(&now_utc) {
+                Ok((start, end)) => {
+                    if start >= until_utc {
+                        break;
+                    }
+                    if start >= from_utc {
+                        windows.push((start, end));
+                    }
+                    now_utc = end.max(now_utc) + Duration::seconds(1);
+                }
+                Err(_) => {
+                    now_utc = (now_utc.date() + Duration::days(1))
+                        .and_hms_opt(0, 0, 0)
+                        .unwrap();
+                }
+            }
+        }
+        windows
+    }
 
-            if start_minus_one_day > end_minus_one_day {
-                end_minus_one_day = end_minus_one_day + Duration::days(1);
+    /// The points in the 24 hours after `now_utc` where recording turns on (`true`) or off
+    /// (`false`), as seconds from `now_utc`, so the firmware can follow the schedule without
+    /// working out sun times itself.  A window already running at `now_utc` only gives its end,
+    /// and a continuous recorder has no transitions.
+    pub fn transitions_next_24h(&self, now_utc: &NaiveDateTime) -> Vec<(u32, bool)> {
+        let mut transitions = Vec::new();
+        if self.is_continuous_recorder() {
+            return transitions;
+        }
+        let horizon = *now_utc + Duration::days(1);
+        let seconds_from_now = |time: NaiveDateTime| (time - *now_utc).num_seconds() as u32;
+        let mut cursor = *now_utc;
+        while cursor < horizon {
+            match self.try_next_recording_window(&cursor) {
+                Ok((start, end)) => {
+                    if start >= horizon {
+                        break;
+                    }
+                    if start > *now_utc {
+                        transitions.push((seconds_from_now(start), true));
+                    }
+                    if end < horizon {
+                        transitions.push((seconds_from_now(end), false));
+                    }
+                    cursor = end.max(cursor) + Duration::seconds(1);
+                }
+                Err(_) => {
+                    cursor = (cursor.date() + Duration::days(1))
+                        .and_hms_opt(0, 0, 0)
+                        .unwrap();
+                }
             }
-            if start_plus_one_day > end_plus_one_day {
-                start_plus_one_day = start_time;
+        }
+        transitions
+    }
+
+    /// The parts of any recording windows that fall within the UTC day `date`, clipped to
+    /// [00:00, 24:00), for drawing a 24 hour schedule.  Windows that cross midnight give a
+    /// segment at each end of the day, and overlapping windows are merged.  A polar day or night
+    /// that a window records through is recorded for the rest of the day.
+    pub fn window_segments_for_utc_day(
+        &self,
+        date: NaiveDate,
+    ) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let day_start = date.and_hms_opt(0, 0, 0).unwrap();
+        let day_end = day_start + Duration::days(1);
+        if self.is_continuous_recorder() {
+            return vec![(day_start, day_end)];
+        }
+        let sun_times = SunTimesCache::default();
+        let mut spans: Vec<_> = self
+            .recording_windows
+            .iter()
+            .flat_map(|window| self.recorded_spans(window, &day_start, &day_end, &sun_times))
+            .filter(|(start, end)| *start < day_end && *end > day_start)
+            .map(|(start, end)| (start.max(day_start), end.min(day_end)))
+            .collect();
+        spans.sort();
+        let mut segments: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+        for (start, end) in spans {
+            match segments.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => segments.push((start, end)),
             }
-            if end_minus_one_day > *now_utc {
-                if is_absolute_start {
-                    start_time = start_minus_one_day;
+        }
+        segments
+    }
+
+    /// Roughly how many seconds the sensor is powered on during the UTC day `date`, for battery
+    /// planning: the whole day for a continuous recorder, otherwise the time covered by
+    /// recording windows, including polar days or nights that are recorded through.
+    pub fn estimated_active_seconds_per_day(&self, date: NaiveDate) -> f64 {
+        self.window_segments_for_utc_day(date)
+            .iter()
+            .map(|(start, end)| (*end - *start).num_seconds() as f64)
+            .sum()
+    }
+
+    /// The fraction of the UTC day `date` spent recording, e.g. `0.333` for an 8 hour window,
+    /// `1.0` for a continuous recorder or a polar day or night that is recorded through, and
+    /// `0.0` when polar day or night turns recording off.
+    pub fn duty_cycle(&self, date: NaiveDate) -> f64 {
+        self.estimated_active_seconds_per_day(date) / 86_400.0
+    }
+
+    /// The recording windows from `materialize_schedule` as an iCalendar feed, for subscribing to
+    /// in a calendar app.
+    pub fn to_ical(&self, from: NaiveDate, days: u32) -> String {
+        const ICAL_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+        let summary = match self
+            .device_info
+            .as_ref()
+            .and_then(|device| device.name.as_ref())
+        {
+            Some(name) => format!("{} recording", name),
+            None => String::from("Recording"),
+        };
+        let uid_prefix = match self.device_info.as_ref().and_then(|device| device.id) {
+            Some(id) => format!("device-{}", id),
+            None => String::from("device"),
+        };
+        let mut lines = vec![
+            String::from("BEGIN:VCALENDAR"),
+            String::from("VERSION:2.0"),
+            String::from("PRODID:-//The Cacophony Project//tc2-config//EN"),
+        ];
+        for (start, end) in self.materialize_schedule(from, days) {
+            let start = start.format(ICAL_TIME_FORMAT).to_string();
+            lines.push(String::from("BEGIN:VEVENT"));
+            lines.push(format!("UID:{}-{}@tc2-config", uid_prefix, start));
+            // Stamped with the window start so the feed only changes when the schedule does
+            lines.push(format!("DTSTAMP:{}", start));
+            lines.push(format!("DTSTART:{}", start));
+            lines.push(format!("DTEND:{}", end.format(ICAL_TIME_FORMAT)));
+            lines.push(format!("SUMMARY:{}", escape_ical_text(&summary)));
+            lines.push(String::from("END:VEVENT"));
+        }
+        lines.push(String::from("END:VCALENDAR"));
+        // iCalendar lines are CRLF terminated
+        lines.into_iter().map(|line| line + "\r\n").collect()
+    }
+
+    /// Problems with the config that don't stop it loading, but that are worth reporting before
+    /// they cause trouble at record or upload time.
+    pub fn validate(&self) -> Vec<ConfigWarning> {
+        self.validate_with_clock(&SystemClock)
+    }
+
+    /// Like `validate`, checking the recording windows for overlaps on the day `clock` is at.
+    pub fn validate_with_clock(&self, clock: &dyn Clock) -> Vec<ConfigWarning> {
+        let mut warnings = Vec::new();
+        if let Err(reason) = self.try_server_url() {
+            warnings.push(ConfigWarning::InvalidServerUrl {
+                server: self.server().unwrap_or_default().to_string(),
+                reason,
+            });
+        }
+        if !self.name_fits_binary() {
+            let length = self.device_name().len();
+            warnings.push(ConfigWarning::DeviceNameTooLong { length });
+        }
+        if let Some(device) = &self.device_info {
+            if let (Some(id), Some(max)) = (device.id, device.firmware_max_id) {
+                if !self.device_id_fits(max) {
+                    warnings.push(ConfigWarning::DeviceIdTooLarge { id, max });
                 }
-                if is_absolute_end {
-                    end_time = end_minus_one_day;
+            }
+        }
+        if let Some(altitude_m) = self
+            .location
+            .as_ref()
+            .and_then(|location| location.altitude)
+        {
+            if !PLAUSIBLE_ALTITUDE_M.contains(&altitude_m) {
+                warnings.push(ConfigWarning::ImplausibleAltitude { altitude_m });
+            }
+        }
+        if let (Some(tz), Some(location)) = (self.configured_timezone(), self.location()) {
+            let longitude = location.lat_lng.1;
+            if !timezone_matches_longitude(&tz, longitude) {
+                warnings.push(ConfigWarning::TimezoneMismatch {
+                    timezone: tz.name().to_string(),
+                    longitude,
+                });
+            }
+        }
+        for (index, window) in self.recording_windows.iter().enumerate() {
+            let times = [
+                (&window.start_recording, window.start_anchor),
+                (&window.stop_recording, window.stop_anchor),
+            ];
+            for (time, window_anchor) in times {
+                // Offsets from midnight are clock times, so any up to a day are expected
+                let from_sun = time.anchor.unwrap_or(window_anchor) != SolarAnchor::Midnight;
+                let is_large = time
+                    .relative_time_seconds
+                    .is_some_and(|seconds| seconds.abs() > LIKELY_RELATIVE_OFFSET_SECONDS);
+                if time.absolute_time.is_none() && window.cron.is_none() && from_sun && is_large {
+                    warnings.push(ConfigWarning::LargeRelativeOffset {
+                        window: index,
+                        time: time.to_config_string(),
+                    });
                 }
             }
-            if end_time < start_time && is_absolute_end {
-                end_time = end_plus_one_day;
+        }
+        // Relative windows can't be resolved without a location
+        let resolvable = self.has_location()
+            || self.recording_windows.iter().all(|window| {
+                window.cron.is_some()
+                    || (window.start_recording.absolute_time.is_some()
+                        && window.stop_recording.absolute_time.is_some())
+            });
+        if self.recording_windows.len() > 1 && resolvable {
+            if let Err(overlaps) = self.validate_window_overlaps(clock) {
+                for (first, second) in overlaps {
+                    warnings.push(ConfigWarning::OverlappingWindows { first, second });
+                }
             }
-            if *now_utc > end_time {
-                if is_absolute_start {
-                    start_time = start_plus_one_day;
+        }
+        warnings
+    }
+
+    /// Checks the recording windows over a representative day (the UTC day `clock` is at) for
+    /// windows that overlap or touch, returning the index pairs of any that do.
+    pub fn validate_window_overlaps(&self, clock: &dyn Clock) -> Result<(), Vec<(usize, usize)>> {
+        self.window_overlaps_on(clock.now_utc().date())
+    }
+
+    fn window_overlaps_on(&self, date: NaiveDate) -> Result<(), Vec<(usize, usize)>> {
+        let from_utc = date.and_hms_opt(0, 0, 0).unwrap();
+        // Look over two days so windows crossing midnight are compared with both neighbours
+        let until_utc = from_utc + Duration::days(2);
+        let occurrences: Vec<Vec<(NaiveDateTime, NaiveDateTime)>> = self
+            .recording_windows
+            .iter()
+            .map(|window| self.window_occurrences(window, &from_utc, &until_utc))
+            .collect();
+        let mut overlaps = Vec::new();
+        for i in 0..occurrences.len() {
+            for j in (i + 1)..occurrences.len() {
+                let overlapping = occurrences[i].iter().any(|(a_start, a_end)| {
+                    occurrences[j]
+                        .iter()
+                        .any(|(b_start, b_end)| a_start <= b_end && b_start <= a_end)
+                });
+                if overlapping {
+                    overlaps.push((i, j));
+                }
+            }
+        }
+        if overlaps.is_empty() {
+            Ok(())
+        } else {
+            Err(overlaps)
+        }
+    }
+
+    fn window_occurrences(
+        &self,
+        window: &TimeWindow,
+        from_utc: &NaiveDateTime,
+        until_utc: &NaiveDateTime,
+    ) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let mut occurrences = Vec::new();
+        let mut now_utc = *from_utc;
+        while now_utc < *until_utc {
+            match self.next_window_for(window, &now_utc) {
+                Ok((start, end)) if start < *until_utc => {
+                    occurrences.push((start, end));
+                    now_utc = end.max(now_utc) + Duration::seconds(1);
+                }
+                _ => break,
+            }
+        }
+        occurrences
+    }
+
+    // The next (or current) window for `now_utc` with both ends inclusive, so cron windows end
+    // just before the first minute that doesn't match.
+    fn inclusive_window_with(
+        &self,
+        window: &TimeWindow,
+        now_utc: &NaiveDateTime,
+        sun_times: &SunTimesCache<SolarAnchor>,
+    ) -> Result<(NaiveDateTime, NaiveDateTime), WindowError> {
+        let (start, end) = self.next_window_with(window, now_utc, sun_times)?;
+        if window.cron.is_some() {
+            Ok((start, end - Duration::nanoseconds(1)))
+        } else {
+            Ok((start, end))
+        }
+    }
+
+    /// Resolves the recording windows for the local `day` once, for checking lots of timestamps
+    /// in that day against.  Agrees with `time_is_in_recording_window` within the day.  Each
+    /// sunrise and sunset the windows depend on is only calculated once.
+    pub fn window_evaluator(&self, day: NaiveDate) -> WindowEvaluator {
+        if self.is_continuous_recorder() {
+            return WindowEvaluator::new(Vec::new(), true, 0);
+        }
+        let from_utc = self.utc_time(day.and_time(NaiveTime::MIN));
+        let until_utc = self.utc_time((day + Duration::days(1)).and_time(NaiveTime::MIN));
+        let sun_times = SunTimesCache::default();
+        let mut windows = Vec::new();
+        for window in &self.recording_windows {
+            for (start, end) in self.recorded_spans(window, &from_utc, &until_utc, &sun_times) {
+                // Cron windows end at the first minute that doesn't match
+                let end = if window.cron.is_some() {
+                    end - Duration::nanoseconds(1)
+                } else {
+                    end
+                };
+                windows.push((start, end));
+            }
+        }
+        WindowEvaluator::new(windows, false, sun_times.calculations())
+    }
+
+    // When `window` records between `from_utc` and `until_utc`: each of its windows that starts
+    // before `until_utc`, from the one current at `from_utc`, or the rest of the span once a
+    // polar day or night that it records through is reached.
+    fn recorded_spans(
+        &self,
+        window: &TimeWindow,
+        from_utc: &NaiveDateTime,
+        until_utc: &NaiveDateTime,
+        sun_times: &SunTimesCache<SolarAnchor>,
+    ) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let mut spans = Vec::new();
+        let mut now_utc = *from_utc;
+        while now_utc < *until_utc {
+            match self.next_window_with(window, &now_utc, sun_times) {
+                Ok((start, end)) if start < *until_utc => {
+                    spans.push((start, end));
+                    now_utc = end.max(now_utc) + Duration::seconds(1);
+                }
+                Ok(_) => break,
+                Err(e) => {
+                    if records_through(window, &e) {
+                        spans.push((now_utc, *until_utc));
+                    }
+                    break;
+                }
+            }
+        }
+        spans
+    }
+
+    // The windows that are current or next at `now_utc`, and when to resolve them again, which
+    // is when the first of them ends.  A window that can't be resolved, e.g. during a polar day,
+    // is resolved again at the next local midnight.
+    fn current_windows(&self, now_utc: &NaiveDateTime) -> (WindowEvaluator, NaiveDateTime) {
+        if self.is_continuous_recorder() {
+            return (
+                WindowEvaluator::new(Vec::new(), true, 0),
+                NaiveDateTime::MAX,
+            );
+        }
+        let next_midnight = self.utc_time(
+            (self.local_time(now_utc).date() + Duration::days(1)).and_time(NaiveTime::MIN),
+        );
+        let sun_times = SunTimesCache::default();
+        let mut windows = Vec::new();
+        let mut valid_until = NaiveDateTime::MAX;
+        for window in &self.recording_windows {
+            match self.inclusive_window_with(window, now_utc, &sun_times) {
+                Ok((start, end)) => {
+                    windows.push((start, end));
+                    valid_until = valid_until.min(end);
                 }
-                if is_absolute_end {
-                    end_time = end_plus_one_day;
+                Err(e) => {
+                    if records_through(window, &e) {
+                        windows.push((*now_utc, next_midnight));
+                    }
+                    valid_until = valid_until.min(next_midnight);
                 }
             }
         }
-        (start_time, end_time)
+        let evaluator = WindowEvaluator::new(windows, false, sun_times.calculations());
+        (evaluator, valid_until)
     }
+
+    /// A handle for checking whether to record many times, e.g. every few seconds, that only
+    /// recalculates the windows once the current one has ended.
+    pub fn status_cache(&self) -> StatusCache<'_> {
+        StatusCache::new(self)
+    }
+
     pub fn next_recording_window_start(&self, now_utc: &NaiveDateTime) -> NaiveDateTime {
         self.next_recording_window(now_utc).0
     }
 
+    /// When the firmware should set its RTC alarm for: the warmup time before the start of the
+    /// next recording window if one isn't active, or the end of the active window so it can wake
+    /// to shut down.  `None` if there is no window to wake for, e.g. during polar day or night,
+    /// when `time_is_in_recording_window` says whether to record.
+    pub fn next_wake_time(&self, now_utc: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let (start, end) = self.try_next_recording_window(now_utc).ok()?;
+        if start <= *now_utc {
+            Some(end)
+        } else {
+            // Wake early enough for the sensor to warm up, or now if that's already passed
+            Some((start - Duration::seconds(self.warmup_seconds() as i64)).max(*now_utc))
+        }
+    }
+
+    /// How long until the active recording window ends, or `None` if no window is active at
+    /// `now_utc`.  A continuous recorder, or a window recording through polar day or night, has
+    /// no end, so also gives `None`.
+    pub fn time_until_window_end(&self, now_utc: &NaiveDateTime) -> Option<Duration> {
+        if self.is_continuous_recorder() {
+            return None;
+        }
+        match self.try_next_recording_window(now_utc) {
+            Ok((start, end)) if start <= *now_utc && *now_utc <= end => Some(end - *now_utc),
+            _ => None,
+        }
+    }
+
+    /// Whether a recording window is active at `now_utc` or starts within `horizon` of it, e.g.
+    /// to decide whether to sleep for the next few hours.  Always true for a continuous
+    /// recorder, and while polar day or night is being recorded through.
+    pub fn records_within(&self, now_utc: &NaiveDateTime, horizon: Duration) -> bool {
+        if self.is_continuous_recorder() {
+            return true;
+        }
+        match self.try_next_recording_window(now_utc) {
+            Ok((start, _)) => start <= *now_utc + horizon,
+            Err(_) => self.time_is_in_recording_window(now_utc),
+        }
+    }
+
     pub fn print_next_recording_window(&self, date_time_utc: &NaiveDateTime) {
         let (start_time, end_time) = self.next_recording_window(date_time_utc);
-        let starts_in = start_time - *date_time_utc;
-        let starts_in_hours = starts_in.num_hours();
-        let starts_in_mins = starts_in.num_minutes() - (starts_in_hours * 60);
-        let ends_in = end_time - *date_time_utc;
-        let ends_in_hours = ends_in.num_hours();
-        let ends_in_mins = ends_in.num_minutes() - (ends_in_hours * 60);
-        let window = end_time - start_time;
-        let window_hours = window.num_hours();
-        let window_mins = window.num_minutes() - (window_hours * 60);
+        let starts_in = format_duration_hm(start_time - *date_time_utc);
+        let ends_in = format_duration_hm(end_time - *date_time_utc);
+        let window = format_duration_hm(end_time - start_time);
         println!(
-            "Next recording window will start in {}h{}m and end in {}h{}m, window duration {}h{}m",
-            starts_in_hours, starts_in_mins, ends_in_hours, ends_in_mins, window_hours, window_mins
+            "Next recording window will start in {} and end in {}, window duration {}",
+            starts_in, ends_in, window
         );
 
         println!(
-            "Next recording window will end in {}h{}m, window duration {}h{}m",
-            ends_in_hours, ends_in_mins, window_hours, window_mins
+            "Next recording window will end in {}, window duration {}",
+            ends_in, window
         );
     }
 
+    /// A one line summary of whether the device is recording at the clock's current time, and
+    /// until when.
+    pub fn recording_status(&self, clock: &dyn Clock) -> String {
+        let now_utc = clock.now_utc();
+        if self.is_continuous_recorder() {
+            return String::from("Recording continuously");
+        }
+        let recording = self.time_is_in_recording_window(&now_utc);
+        match self.try_next_recording_window(&now_utc) {
+            Ok((start, end)) if recording && start <= now_utc => format!(
+                "Recording, window ends in {}",
+                format_duration_hm(end - now_utc)
+            ),
+            Ok((start, end)) => format!(
+                "{}, next window starts in {} and lasts {}",
+                if recording {
+                    "Recording"
+                } else {
+                    "Not recording"
+                },
+                format_duration_hm(start - now_utc),
+                format_duration_hm(end - start)
+            ),
+            Err(WindowError::EmptyWindow) if !recording => {
+                String::from("Not recording, the window ends before it starts")
+            }
+            Err(_) if recording => String::from("Recording, the sun won't rise or set today"),
+            Err(_) => String::from("Not recording, the sun won't rise or set today"),
+        }
+    }
+
     pub fn time_is_in_recording_window(&self, date_time_utc: &NaiveDateTime) -> bool {
         if self.is_continuous_recorder() {
             return true;
         }
-        let (start_time, end_time) = self.next_recording_window(date_time_utc);
-        let starts_in = start_time - *date_time_utc;
-        let starts_in_hours = starts_in.num_hours();
-        let starts_in_mins = starts_in.num_minutes() - (starts_in_hours * 60);
-        let ends_in = end_time - *date_time_utc;
-        let ends_in_hours = ends_in.num_hours();
-        let ends_in_mins = ends_in.num_minutes() - (ends_in_hours * 60);
-        let window = end_time - start_time;
-        let window_hours = window.num_hours();
-        let window_mins = window.num_minutes() - (window_hours * 60);
-        if start_time > *date_time_utc && end_time > *date_time_utc {
-            info!(
-                "Recording will start in {}h{}m and end in {}h{}m, window duration {}h{}m",
-                starts_in_hours,
-                starts_in_mins,
-                ends_in_hours,
-                ends_in_mins,
-                window_hours,
-                window_mins
-            );
-        } else if end_time > *date_time_utc {
-            info!(
-                "Recording will end in {}h{}m, window duration {}h{}m",
-                ends_in_hours, ends_in_mins, window_hours, window_mins
-            );
+        // Each window is only resolved once, for both the log message and whether it's active
+        let sun_times = SunTimesCache::default();
+        let resolved: Vec<_> = self
+            .recording_windows
+            .iter()
+            .map(|window| {
+                (
+                    window,
+                    self.next_window_with(window, date_time_utc, &sun_times),
+                )
+            })
+            .collect();
+        let next_window = resolved
+            .iter()
+            .map(|(_, result)| *result)
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .and_then(|windows| earliest_window(windows, date_time_utc));
+        if let Some((start_time, end_time)) = next_window {
+            let starts_in = format_duration_hm(start_time - *date_time_utc);
+            let ends_in = format_duration_hm(end_time - *date_time_utc);
+            let window = format_duration_hm(end_time - start_time);
+            if start_time > *date_time_utc && end_time > *date_time_utc {
+                info!(
+                    "Recording will start in {} and end in {}, window duration {}",
+                    starts_in, ends_in, window
+                );
+            } else if end_time > *date_time_utc {
+                info!(
+                    "Recording will end in {}, window duration {}",
+                    ends_in, window
+                );
+            }
         }
-        *date_time_utc >= start_time && *date_time_utc <= end_time
+        resolved
+            .iter()
+            .any(|(window, result)| self.resolved_window_contains(window, date_time_utc, result))
     }
 
-    pub fn write_to_slice(&self, output: &mut [u8]) {
-        let mut buf = Cursor::new(output);
-        let device_id = self.device_id();
-        buf.write_u32::<LittleEndian>(device_id).unwrap();
+    /// Whether the device should record now, which requires being in a recording window and, if
+    /// a minimum voltage is configured, the battery being at or above it.  An unknown battery
+    /// voltage doesn't stop recording.
+    pub fn should_record(&self, now_utc: &NaiveDateTime, battery_voltage: Option<f32>) -> bool {
+        let voltage_ok = match (self.min_voltage(), battery_voltage) {
+            (Some(min_voltage), Some(voltage)) => voltage >= min_voltage,
+            _ => true,
+        };
+        voltage_ok && self.time_is_in_recording_window(now_utc)
+    }
+
+    /// Why the device isn't recording at `now_utc`, or `None` if it should be.  A window being
+    /// active takes priority, so `LowVoltage` is only returned during a window.
+    pub fn inactive_reason(
+        &self,
+        now_utc: &NaiveDateTime,
+        context: &RecordingContext,
+    ) -> Option<InactiveReason> {
+        if self.time_is_in_recording_window(now_utc) {
+            return match (self.min_voltage(), context.battery_voltage) {
+                (Some(min_voltage), Some(voltage)) if voltage < min_voltage => {
+                    Some(InactiveReason::LowVoltage)
+                }
+                _ => None,
+            };
+        }
+        Some(match self.try_next_recording_window(now_utc) {
+            Ok((start, _)) => {
+                if self.local_time(&start).date() == self.local_time(now_utc).date() {
+                    InactiveReason::BeforeWindow
+                } else {
+                    InactiveReason::AfterWindow
+                }
+            }
+            Err(WindowError::Sun(SunTimesError::PolarDay)) => InactiveReason::PolarDay,
+            Err(WindowError::Sun(SunTimesError::PolarNight)) => InactiveReason::PolarNight,
+            Err(WindowError::EmptyWindow) => InactiveReason::EmptyWindow,
+            Err(_) => InactiveReason::InvalidLocation,
+        })
+    }
+
+    /// A single byte summary of the device's state at `now_utc`, for blinking on a status LED or
+    /// sending in telemetry.  From the least significant bit:
+    ///
+    /// - bit 0 (`STATUS_REGISTERED`): the device has an id, name and group
+    /// - bit 1 (`STATUS_LOCATED`): there is a location to calculate sun times from
+    /// - bit 2 (`STATUS_IN_WINDOW`): `now_utc` is inside a recording window
+    /// - bit 3 (`STATUS_CONTINUOUS`): the device records continuously
+    /// - bit 4 (`STATUS_LOW_POWER`): low power mode is in effect at `now_utc`
+    /// - bit 5 (`STATUS_DISABLED`): recording is held off during a window, e.g. by a low battery
+    ///
+    /// Bits 6 and 7 are always 0.
+    pub fn status_code(&self, now_utc: &NaiveDateTime, context: &RecordingContext) -> u8 {
+        let in_window = self.time_is_in_recording_window(now_utc);
+        let disabled = in_window && !self.should_record(now_utc, context.battery_voltage);
+        [
+            (STATUS_REGISTERED, self.is_registered()),
+            (STATUS_LOCATED, self.has_location()),
+            (STATUS_IN_WINDOW, in_window),
+            (STATUS_CONTINUOUS, self.is_continuous_recorder()),
+            (STATUS_LOW_POWER, self.effective_low_power_mode(now_utc)),
+            (STATUS_DISABLED, disabled),
+        ]
+        .iter()
+        .filter(|(_, set)| *set)
+        .fold(0, |code, (bit, _)| code | bit)
+    }
+
+    fn window_contains(&self, window: &TimeWindow, date_time_utc: &NaiveDateTime) -> bool {
+        if let Some(cron) = &window.cron {
+            return cron.matches(&self.local_time(date_time_utc));
+        }
+        let resolved = self.next_window_for(window, date_time_utc);
+        self.resolved_window_contains(window, date_time_utc, &resolved)
+    }
+
+    // Like `window_contains`, with `window` already resolved for `date_time_utc`.
+    fn resolved_window_contains(
+        &self,
+        window: &TimeWindow,
+        date_time_utc: &NaiveDateTime,
+        resolved: &Result<(NaiveDateTime, NaiveDateTime), WindowError>,
+    ) -> bool {
+        if let Some(cron) = &window.cron {
+            return cron.matches(&self.local_time(date_time_utc));
+        }
+        match resolved {
+            Ok((start_time, end_time)) => date_time_utc >= start_time && date_time_utc <= end_time,
+            Err(e @ WindowError::Sun(SunTimesError::PolarDay | SunTimesError::PolarNight)) => {
+                records_through(window, e)
+            }
+            Err(e) => {
+                error!("Unable to calculate recording window: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// The `[location].timezone`, if one is set.
+    pub fn configured_timezone(&self) -> Option<Tz> {
+        self.location.as_ref()?.timezone.as_ref()?.parse().ok()
+    }
+
+    /// What the absolute offsets of the window sent to the firmware are relative to: the
+    /// configured timezone if there is one, otherwise the system's.
+    pub fn timezone_basis(&self) -> TimezoneBasis {
+        let window = self.primary_window();
+        let (start, stop) = (&window.start_recording, &window.stop_recording);
+        let from_midnight = |time: &AbsRelTime, window_anchor: SolarAnchor| {
+            time.absolute_time.is_none()
+                && time.anchor.unwrap_or(window_anchor) == SolarAnchor::Midnight
+        };
+        if start.absolute_time.is_some()
+            || stop.absolute_time.is_some()
+            || from_midnight(start, window.start_anchor)
+            || from_midnight(stop, window.stop_anchor)
+        {
+            if self.configured_timezone().is_some() {
+                TimezoneBasis::ConfiguredIana
+            } else {
+                TimezoneBasis::SystemLocal
+            }
+        } else {
+            TimezoneBasis::Utc
+        }
+    }
+
+    /// The fields of the binary config shared with the firmware, in the order they are written.
+    /// Absolute times are offsets in the configured timezone as it is at `clock`'s time.
+    fn binary_fields(&self, clock: &dyn Clock) -> Vec<(&'static str, BinaryField)> {
+        let mut fields = Vec::new();
+        fields.push(("device_id", BinaryField::U32(self.device_id())));
 
         let (latitude, longitude) = self.lat_lng();
-        buf.write_f32::<LittleEndian>(latitude).unwrap();
-        buf.write_f32::<LittleEndian>(longitude).unwrap();
+        fields.push(("latitude", BinaryField::F32(latitude)));
+        fields.push(("longitude", BinaryField::F32(longitude)));
         let (has_loc_timestamp, timestamp) = if let Some(timestamp) = self.location_timestamp() {
             (1u8, timestamp)
         } else {
             (0u8, 0)
         };
-        buf.write_u8(has_loc_timestamp).unwrap();
-        buf.write_u64::<LittleEndian>(timestamp).unwrap();
+        fields.push(("has_location_timestamp", BinaryField::U8(has_loc_timestamp)));
+        fields.push(("location_timestamp", BinaryField::U64(timestamp)));
         let (has_loc_altitude, altitude) = if let Some(altitude) = self.location_altitude() {
             (1u8, altitude)
         } else {
             (0u8, 0.0)
         };
-        buf.write_u8(has_loc_altitude).unwrap();
-        buf.write_f32::<LittleEndian>(altitude).unwrap();
+        fields.push(("has_location_altitude", BinaryField::U8(has_loc_altitude)));
+        fields.push(("location_altitude", BinaryField::F32(altitude)));
         let (has_loc_accuracy, accuracy) = if let Some(accuracy) = self.location_accuracy() {
             (1u8, accuracy)
         } else {
             (0u8, 0.0)
         };
-        buf.write_u8(has_loc_accuracy).unwrap();
-        buf.write_f32::<LittleEndian>(accuracy).unwrap();
+        fields.push(("has_location_accuracy", BinaryField::U8(has_loc_accuracy)));
+        fields.push(("location_accuracy", BinaryField::F32(accuracy)));
         let (abs_rel_start, abs_rel_end) = self.recording_window();
-        let (start_is_abs, start_seconds_offset) = abs_rel_start.time_offset();
-        let (end_is_abs, end_seconds_offset) = abs_rel_end.time_offset();
-        buf.write_u8(if start_is_abs { 1 } else { 0 }).unwrap();
-        buf.write_i32::<LittleEndian>(start_seconds_offset).unwrap();
-        buf.write_u8(if end_is_abs { 1 } else { 0 }).unwrap();
-        buf.write_i32::<LittleEndian>(end_seconds_offset).unwrap();
-        buf.write_u8(if self.is_continuous_recorder() { 1 } else { 0 })
-            .unwrap();
-        buf.write_u8(if self.use_low_power_mode() { 1 } else { 0 })
-            .unwrap();
+        // Offsets are in whole seconds, either past UTC midnight (absolute) or relative to
+        // sunset/sunrise.  The firmware doesn't know about twilight, so offsets from civil dawn
+        // and dusk are sent as offsets from sunrise and sunset.
+        let tz_offset_seconds = self.utc_offset_seconds(&clock.now_utc());
+        let (start_is_abs, start_seconds_offset) =
+            abs_rel_start.time_offset_for_tz(tz_offset_seconds);
+        let (end_is_abs, end_seconds_offset) = abs_rel_end.time_offset_for_tz(tz_offset_seconds);
+        fields.push(("start_is_absolute", BinaryField::U8(start_is_abs as u8)));
+        fields.push(("start_seconds_offset", BinaryField::I32(start_seconds_offset)));
+        fields.push(("end_is_absolute", BinaryField::U8(end_is_abs as u8)));
+        fields.push(("end_seconds_offset", BinaryField::I32(end_seconds_offset)));
+        fields.push((
+            "is_continuous_recorder",
+            BinaryField::U8(self.is_continuous_recorder() as u8),
+        ));
+        fields.push((
+            "use_low_power_mode",
+            BinaryField::U8(self.use_low_power_mode() as u8),
+        ));
 
         let device_name = self.device_name();
-        let device_name_length = device_name.len().min(63);
-        buf.write_u8(device_name_length as u8).unwrap();
-        buf.write(&device_name[0..device_name_length]).unwrap();
+        if !self.name_fits_binary() {
+            error!(
+                "Device name is {} bytes, it will be truncated to {} bytes for the firmware",
+                device_name.len(),
+                MAX_BINARY_DEVICE_NAME_LEN
+            );
+        }
+        let device_name_length = device_name.len().min(MAX_BINARY_DEVICE_NAME_LEN);
+        fields.push((
+            "device_name_length",
+            BinaryField::U8(device_name_length as u8),
+        ));
+        fields.push((
+            "device_name",
+            BinaryField::Bytes(device_name[0..device_name_length].to_vec()),
+        ));
+        // Fields after the variable length device name must only ever be appended
+        fields.push(("motion_only", BinaryField::U8(self.motion_only() as u8)));
+        fields.push((
+            "thermal_throttler_enabled",
+            BinaryField::U8(self.thermal_throttler_enabled() as u8),
+        ));
+        fields.push(("warmup_seconds", BinaryField::U32(self.warmup_seconds())));
+        fields.push((
+            "timezone_basis",
+            BinaryField::U8(self.timezone_basis() as u8),
+        ));
+        fields.push(("quality", BinaryField::U8(self.quality() as u8)));
+        fields.push((
+            "pre_trigger_secs",
+            BinaryField::U16(self.pre_trigger_secs()),
+        ));
+        fields.push(("disk_policy", BinaryField::U8(self.disk_policy() as u8)));
+        fields
+    }
+
+    /// The binary fields followed by a CRC32 of all of their bytes, which is what is actually
+    /// written.
+    fn binary_fields_with_checksum(&self, clock: &dyn Clock) -> Vec<(&'static str, BinaryField)> {
+        let mut fields = self.binary_fields(clock);
+        let mut bytes = Vec::new();
+        for (_, field) in &fields {
+            field.write(&mut bytes).unwrap();
+        }
+        fields.push(("crc32", BinaryField::U32(crc32fast::hash(&bytes))));
+        fields
+    }
+
+    /// The number of bytes `write_to_slice` writes.
+    pub fn serialized_len(&self) -> usize {
+        // The fields are the same size whatever the time
+        self.binary_fields_with_checksum(&SystemClock)
+            .iter()
+            .map(|(_, field)| field.len())
+            .sum()
+    }
+
+    /// Reads back the fields written by `write_to_slice`, e.g. from the firmware's config region,
+    /// checking the trailing CRC32.
+    pub fn read_from_slice(input: &[u8]) -> Result<Vec<(&'static str, BinaryField)>, BinaryError> {
+        let mut buf = Cursor::new(input);
+        let mut fields = Vec::new();
+        for (name, field) in [
+            ("device_id", BinaryField::U32(0)),
+            ("latitude", BinaryField::F32(0.0)),
+            ("longitude", BinaryField::F32(0.0)),
+            ("has_location_timestamp", BinaryField::U8(0)),
+            ("location_timestamp", BinaryField::U64(0)),
+            ("has_location_altitude", BinaryField::U8(0)),
+            ("location_altitude", BinaryField::F32(0.0)),
+            ("has_location_accuracy", BinaryField::U8(0)),
+            ("location_accuracy", BinaryField::F32(0.0)),
+            ("start_is_absolute", BinaryField::U8(0)),
+            ("start_seconds_offset", BinaryField::I32(0)),
+            ("end_is_absolute", BinaryField::U8(0)),
+            ("end_seconds_offset", BinaryField::I32(0)),
+            ("is_continuous_recorder", BinaryField::U8(0)),
+            ("use_low_power_mode", BinaryField::U8(0)),
+            ("device_name_length", BinaryField::U8(0)),
+        ] {
+            fields.push((name, field.read_like(&mut buf)?));
+        }
+        let device_name_length = match fields.last() {
+            Some((_, BinaryField::U8(length))) => *length as usize,
+            _ => unreachable!(),
+        };
+        let device_name = BinaryField::Bytes(vec![0; device_name_length]);
+        fields.push(("device_name", device_name.read_like(&mut buf)?));
+        for (name, field) in [
+            ("motion_only", BinaryField::U8(0)),
+            ("thermal_throttler_enabled", BinaryField::U8(0)),
+            ("warmup_seconds", BinaryField::U32(0)),
+            ("timezone_basis", BinaryField::U8(0)),
+            ("quality", BinaryField::U8(0)),
+            ("pre_trigger_secs", BinaryField::U16(0)),
+            ("disk_policy", BinaryField::U8(0)),
+        ] {
+            fields.push((name, field.read_like(&mut buf)?));
+        }
+        let expected = crc32fast::hash(&input[0..buf.position() as usize]);
+        let crc32 = BinaryField::U32(0).read_like(&mut buf)?;
+        match crc32 {
+            BinaryField::U32(actual) if actual != expected => {
+                return Err(BinaryError::ChecksumMismatch { expected, actual })
+            }
+            _ => fields.push(("crc32", crc32)),
+        }
+        Ok(fields)
+    }
+
+    /// Whether `write_to_slice` output fits in a firmware config region of `max_bytes`.
+    pub fn fits_in(&self, max_bytes: usize) -> bool {
+        self.serialized_len() <= max_bytes
+    }
+
+    pub fn write_to_slice(&self, output: &mut [u8]) -> Result<(), BinaryError> {
+        self.write_to_slice_with_clock(output, &SystemClock)
+    }
+
+    /// Like `write_to_slice`, with absolute times offset for the configured timezone as it is at
+    /// `clock`'s time, so the output doesn't change across a daylight saving change.
+    pub fn write_to_slice_with_clock(
+        &self,
+        output: &mut [u8],
+        clock: &dyn Clock,
+    ) -> Result<(), BinaryError> {
+        let needed = self.serialized_len();
+        if output.len() < needed {
+            return Err(BinaryError::BufferTooSmall { needed });
+        }
+        let mut buf = Cursor::new(output);
+        for (_, field) in self.binary_fields_with_checksum(clock) {
+            field.write(&mut buf).unwrap();
+        }
+        Ok(())
+    }
+
+    /// The output of `write_to_slice` in a buffer of exactly the right size.
+    pub fn write_to_vec(&self) -> Vec<u8> {
+        let mut output = vec![0u8; self.serialized_len()];
+        self.write_to_slice(&mut output)
+            .expect("Buffer is sized to fit");
+        output
+    }
+
+    /// Whether `bytes`, e.g. read back from the firmware's config region, holds the binary
+    /// config for this config.  Settings that aren't in the binary layout aren't compared, and
+    /// anything that fails to read (including a bad checksum) doesn't match.
+    pub fn matches_binary(&self, bytes: &[u8]) -> bool {
+        self.matches_binary_with_clock(bytes, &SystemClock)
+    }
+
+    /// Like `matches_binary`, comparing against what `write_to_slice_with_clock` writes for
+    /// `clock`.
+    pub fn matches_binary_with_clock(&self, bytes: &[u8], clock: &dyn Clock) -> bool {
+        match DeviceConfig::read_from_slice(bytes) {
+            Ok(fields) => fields == self.binary_fields_with_checksum(clock),
+            Err(_) => false,
+        }
+    }
+
+    /// Describes what `write_to_slice` would output as (byte range, field name, value) tuples,
+    /// for debugging the binary protocol.
+    pub fn describe_serialization(&self) -> Vec<(Range<usize>, String, String)> {
+        let mut offset = 0;
+        self.binary_fields_with_checksum(&SystemClock)
+            .into_iter()
+            .map(|(name, field)| {
+                let range = offset..offset + field.len();
+                offset = range.end;
+                (range, String::from(name), field.to_string())
+            })
+            .collect()
     }
 }
@@ -0,0 +1,415 @@
+// The messages from proto/device_config.proto, written out by hand rather than generated so the
+// build doesn't need protoc.  Tags and types must match the .proto file.
+use crate::detection_mask::DetectionMask;
+use crate::{u64_to_toml_datetime, ConfigError, TimeWindow};
+use prost::Message;
+use serde::Serialize;
+use toml::map::Map;
+use toml::Value;
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DeviceConfig {
+    #[prost(message, optional, tag = "1")]
+    pub device: Option<Device>,
+    #[prost(message, optional, tag = "2")]
+    pub location: Option<Location>,
+    #[prost(message, repeated, tag = "3")]
+    pub windows: Vec<Window>,
+    #[prost(message, optional, tag = "4")]
+    pub thermal_recorder: Option<ThermalRecorder>,
+    #[prost(message, optional, tag = "5")]
+    pub thermal_throttler: Option<ThermalThrottler>,
+    #[prost(message, optional, tag = "6")]
+    pub power: Option<Power>,
+    #[prost(message, optional, tag = "7")]
+    pub audio_window: Option<Window>,
+    #[prost(uint32, tag = "8")]
+    pub schema_version: u32,
+    #[prost(message, optional, tag = "9")]
+    pub contact: Option<Contact>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Device {
+    #[prost(uint32, optional, tag = "1")]
+    pub id: Option<u32>,
+    #[prost(string, optional, tag = "2")]
+    pub group: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub name: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub server: Option<String>,
+    #[prost(uint32, optional, tag = "5")]
+    pub firmware_max_id: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Contact {
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub email: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub phone: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Location {
+    #[prost(float, optional, tag = "1")]
+    pub latitude: Option<f32>,
+    #[prost(float, optional, tag = "2")]
+    pub longitude: Option<f32>,
+    #[prost(float, optional, tag = "3")]
+    pub altitude: Option<f32>,
+    #[prost(float, optional, tag = "4")]
+    pub accuracy: Option<f32>,
+    #[prost(uint64, optional, tag = "5")]
+    pub timestamp: Option<u64>,
+    #[prost(int32, optional, tag = "6")]
+    pub timestamp_offset_minutes: Option<i32>,
+    #[prost(uint64, optional, tag = "7")]
+    pub updated: Option<u64>,
+    #[prost(uint32, optional, tag = "8")]
+    pub solar_cache_days: Option<u32>,
+    #[prost(string, optional, tag = "9")]
+    pub timezone: Option<String>,
+    #[prost(float, optional, tag = "10")]
+    pub horizon_angle: Option<f32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Window {
+    #[prost(string, optional, tag = "1")]
+    pub start_recording: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub stop_recording: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub start_anchor: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub stop_anchor: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub polar_day: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub polar_night: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub cron: Option<String>,
+    #[prost(bool, optional, tag = "8")]
+    pub use_low_power_mode: Option<bool>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ThermalRecorder {
+    #[prost(string, optional, tag = "1")]
+    pub output_dir: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub backup_dir: Option<String>,
+    #[prost(bool, tag = "3")]
+    pub constant_recorder: bool,
+    #[prost(bool, tag = "4")]
+    pub use_low_power_mode: bool,
+    #[prost(bool, tag = "5")]
+    pub motion_only: bool,
+    #[prost(uint32, tag = "6")]
+    pub warmup_seconds: u32,
+    #[prost(uint32, tag = "7")]
+    pub pre_trigger_secs: u32,
+    #[prost(string, optional, tag = "8")]
+    pub quality: Option<String>,
+    #[prost(float, optional, tag = "9")]
+    pub sample_probability: Option<f32>,
+    #[prost(uint32, optional, tag = "10")]
+    pub min_disk_space_mb: Option<u32>,
+    #[prost(string, optional, tag = "11")]
+    pub disk_policy: Option<String>,
+    #[prost(message, optional, tag = "12")]
+    pub mask: Option<Mask>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Mask {
+    #[prost(uint32, tag = "1")]
+    pub width: u32,
+    #[prost(uint32, tag = "2")]
+    pub height: u32,
+    #[prost(bytes = "vec", tag = "3")]
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ThermalThrottler {
+    #[prost(bool, optional, tag = "1")]
+    pub activate: Option<bool>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Power {
+    #[prost(float, optional, tag = "1")]
+    pub min_voltage: Option<f32>,
+}
+
+// How a setting is written in the TOML file, e.g. "high" for `RecordingQuality::High`
+fn config_string<T: Serialize>(value: &T) -> Option<String> {
+    match Value::try_from(value) {
+        Ok(Value::String(value)) => Some(value),
+        _ => None,
+    }
+}
+
+fn to_window(window: &TimeWindow) -> Window {
+    let table = Value::try_from(window).expect("Window should serialize to TOML");
+    let string = |key: &str| table.get(key).and_then(Value::as_str).map(String::from);
+    Window {
+        start_recording: string("start-recording"),
+        stop_recording: string("stop-recording"),
+        start_anchor: string("start-anchor"),
+        stop_anchor: string("stop-anchor"),
+        polar_day: string("polar-day"),
+        polar_night: string("polar-night"),
+        cron: string("cron"),
+        use_low_power_mode: table.get("use-low-power-mode").and_then(Value::as_bool),
+    }
+}
+
+fn window_table(window: Window) -> Value {
+    let mut table = Map::new();
+    for (key, value) in [
+        ("start-recording", window.start_recording),
+        ("stop-recording", window.stop_recording),
+        ("start-anchor", window.start_anchor),
+        ("stop-anchor", window.stop_anchor),
+        ("polar-day", window.polar_day),
+        ("polar-night", window.polar_night),
+        ("cron", window.cron),
+    ] {
+        if let Some(value) = value {
+            table.insert(String::from(key), Value::String(value));
+        }
+    }
+    if let Some(use_low_power_mode) = window.use_low_power_mode {
+        table.insert(
+            String::from("use-low-power-mode"),
+            Value::Boolean(use_low_power_mode),
+        );
+    }
+    Value::Table(table)
+}
+
+pub fn encode(config: &crate::DeviceConfig) -> Vec<u8> {
+    let settings = &config.recording_settings;
+    let message = DeviceConfig {
+        device: config.device_info.as_ref().map(|device| Device {
+            id: device.id,
+            group: device.group.clone(),
+            name: device.name.clone(),
+            server: device.server.clone(),
+            firmware_max_id: device.firmware_max_id,
+        }),
+        location: config.location.as_ref().map(|location| Location {
+            latitude: location.latitude,
+            longitude: location.longitude,
+            altitude: location.altitude,
+            accuracy: location.accuracy,
+            timestamp: location.timestamp,
+            timestamp_offset_minutes: location.timestamp_offset_minutes,
+            updated: location.updated,
+            solar_cache_days: location.solar_cache_days,
+            timezone: location.timezone.clone(),
+            horizon_angle: location.horizon_angle,
+        }),
+        windows: config.recording_windows.iter().map(to_window).collect(),
+        thermal_recorder: Some(ThermalRecorder {
+            output_dir: Some(settings.output_dir.clone()),
+            backup_dir: settings.backup_dir.clone(),
+            constant_recorder: settings.constant_recorder,
+            use_low_power_mode: settings.use_low_power_mode,
+            motion_only: settings.motion_only,
+            warmup_seconds: settings.warmup_seconds,
+            pre_trigger_secs: settings.pre_trigger_secs as u32,
+            quality: config_string(&settings.quality),
+            sample_probability: Some(settings.sample_probability),
+            min_disk_space_mb: Some(settings.min_disk_space_mb),
+            disk_policy: config_string(&settings.disk_policy),
+            mask: Some(Mask {
+                width: settings.mask_regions.width() as u32,
+                height: settings.mask_regions.height() as u32,
+                pixels: settings.mask_regions.as_bytes().to_vec(),
+            }),
+        }),
+        thermal_throttler: Some(ThermalThrottler {
+            activate: Some(config.thermal_throttler.activate),
+        }),
+        power: config.power.as_ref().map(|power| Power {
+            min_voltage: power.min_voltage,
+        }),
+        audio_window: config.audio_window.as_ref().map(to_window),
+        schema_version: config.meta.schema_version,
+        contact: config.contact.as_ref().map(|contact| Contact {
+            name: contact.name.clone(),
+            email: contact.email.clone(),
+            phone: contact.phone.clone(),
+        }),
+    };
+    message.encode_to_vec()
+}
+
+// Builds the same TOML structure as a config file, so loading goes through the same parsing,
+// defaults and validation.  The mask is already rasterised, so it is set afterwards.
+pub fn decode(bytes: &[u8]) -> Result<crate::DeviceConfig, ConfigError> {
+    let message = DeviceConfig::decode(bytes).map_err(ConfigError::Protobuf)?;
+    let mut config = Map::new();
+
+    if let Some(device) = message.device {
+        let mut table = Map::new();
+        if let Some(id) = device.id {
+            table.insert(String::from("id"), Value::Integer(id as i64));
+        }
+        for (key, value) in [
+            ("group", device.group),
+            ("name", device.name),
+            ("server", device.server),
+        ] {
+            if let Some(value) = value {
+                table.insert(String::from(key), Value::String(value));
+            }
+        }
+        if let Some(max) = device.firmware_max_id {
+            table.insert(String::from("firmware-max-id"), Value::Integer(max as i64));
+        }
+        config.insert(String::from("device"), Value::Table(table));
+    }
+
+    if let Some(contact) = message.contact {
+        let mut table = Map::new();
+        for (key, value) in [
+            ("name", contact.name),
+            ("email", contact.email),
+            ("phone", contact.phone),
+        ] {
+            if let Some(value) = value {
+                table.insert(String::from(key), Value::String(value));
+            }
+        }
+        config.insert(String::from("contact"), Value::Table(table));
+    }
+
+    if let Some(location) = message.location {
+        let mut table = Map::new();
+        for (key, value) in [
+            ("latitude", location.latitude),
+            ("longitude", location.longitude),
+            ("altitude", location.altitude),
+            ("accuracy", location.accuracy),
+            ("horizon-angle", location.horizon_angle),
+        ] {
+            if let Some(value) = value {
+                table.insert(String::from(key), Value::Float(value as f64));
+            }
+        }
+        let offset_minutes = location.timestamp_offset_minutes.unwrap_or(0);
+        for (key, micros, offset_minutes) in [
+            ("timestamp", location.timestamp, offset_minutes),
+            ("updated", location.updated, 0),
+        ] {
+            if let Some(micros) = micros {
+                let date_time =
+                    u64_to_toml_datetime(micros, offset_minutes).map_err(ConfigError::Decode)?;
+                table.insert(String::from(key), Value::Datetime(date_time));
+            }
+        }
+        if let Some(days) = location.solar_cache_days {
+            table.insert(
+                String::from("solar-cache-days"),
+                Value::Integer(days as i64),
+            );
+        }
+        if let Some(timezone) = location.timezone {
+            table.insert(String::from("timezone"), Value::String(timezone));
+        }
+        config.insert(String::from("location"), Value::Table(table));
+    }
+
+    if !message.windows.is_empty() {
+        let windows = message.windows.into_iter().map(window_table).collect();
+        config.insert(String::from("windows"), Value::Array(windows));
+    }
+    if let Some(window) = message.audio_window {
+        config.insert(String::from("audio-recorder"), window_table(window));
+    }
+
+    let mut mask = None;
+    if let Some(recorder) = message.thermal_recorder {
+        let mut table = Map::new();
+        for (key, value) in [
+            ("output-dir", recorder.output_dir),
+            ("backup-dir", recorder.backup_dir),
+            ("quality", recorder.quality),
+            ("disk-policy", recorder.disk_policy),
+        ] {
+            if let Some(value) = value {
+                table.insert(String::from(key), Value::String(value));
+            }
+        }
+        for (key, value) in [
+            ("constant-recorder", recorder.constant_recorder),
+            ("use-low-power-mode", recorder.use_low_power_mode),
+            ("motion-only", recorder.motion_only),
+        ] {
+            table.insert(String::from(key), Value::Boolean(value));
+        }
+        for (key, seconds) in [
+            ("warmup", recorder.warmup_seconds),
+            ("pre-trigger", recorder.pre_trigger_secs),
+        ] {
+            table.insert(String::from(key), Value::String(format!("{}s", seconds)));
+        }
+        if let Some(probability) = recorder.sample_probability {
+            table.insert(
+                String::from("sample-probability"),
+                Value::Float(probability as f64),
+            );
+        }
+        if let Some(min_disk_space_mb) = recorder.min_disk_space_mb {
+            table.insert(
+                String::from("min-disk-space-mb"),
+                Value::Integer(min_disk_space_mb as i64),
+            );
+        }
+        mask = recorder.mask;
+        config.insert(String::from("thermal-recorder"), Value::Table(table));
+    }
+    if let Some(activate) = message
+        .thermal_throttler
+        .and_then(|throttler| throttler.activate)
+    {
+        let mut table = Map::new();
+        table.insert(String::from("activate"), Value::Boolean(activate));
+        config.insert(String::from("thermal-throttler"), Value::Table(table));
+    }
+    if let Some(power) = message.power {
+        let mut table = Map::new();
+        if let Some(min_voltage) = power.min_voltage {
+            table.insert(
+                String::from("min-voltage"),
+                Value::Float(min_voltage as f64),
+            );
+        }
+        config.insert(String::from("power"), Value::Table(table));
+    }
+    if message.schema_version != 0 {
+        let mut table = Map::new();
+        table.insert(
+            String::from("schema-version"),
+            Value::Integer(message.schema_version as i64),
+        );
+        config.insert(String::from("meta"), Value::Table(table));
+    }
+
+    let config = crate::DeviceConfig::migrate(Value::Table(config));
+    let mut config = crate::DeviceConfig::from_migrated_value(config)?;
+    if let Some(mask) = mask {
+        let frame = (mask.width as usize, mask.height as usize);
+        config.recording_settings.mask_regions =
+            DetectionMask::from_raw_bytes(mask.pixels, frame).map_err(ConfigError::Mask)?;
+    }
+    Ok(config)
+}
@@ -0,0 +1,63 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use serde::Serialize;
+use std::io::Write;
+
+pub const RECORDING_METADATA_VERSION: u8 = 1;
+
+/// Device details prepended to each CPTV file by the uploader.
+///
+/// The field order and the layout produced by `to_bytes` are stable; new fields must only ever
+/// be appended, and `RECORDING_METADATA_VERSION` bumped when they are.
+///
+/// Binary layout (little endian):
+///
+/// * `u8` version
+/// * `u32` device id
+/// * `u8` name length, followed by the name bytes (max 255)
+/// * `u8` group length, followed by the group bytes (max 255)
+/// * `u8` has location, `f32` latitude, `f32` longitude (zeroes when there is no location)
+/// * `u8` is continuous recorder
+/// * `u8` use low power mode
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct RecordingMetadata {
+    pub device_id: u32,
+    pub device_name: String,
+    pub group: String,
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub continuous_recorder: bool,
+    pub low_power_mode: bool,
+}
+
+fn write_short_str(buf: &mut Vec<u8>, s: &str) {
+    let bytes = s.as_bytes();
+    let len = bytes.len().min(255);
+    buf.write_u8(len as u8).unwrap();
+    buf.write_all(&bytes[0..len]).unwrap();
+}
+
+impl RecordingMetadata {
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_u8(RECORDING_METADATA_VERSION).unwrap();
+        buf.write_u32::<LittleEndian>(self.device_id).unwrap();
+        write_short_str(&mut buf, &self.device_name);
+        write_short_str(&mut buf, &self.group);
+        let (has_location, latitude, longitude) = match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => (1u8, latitude, longitude),
+            _ => (0u8, 0.0, 0.0),
+        };
+        buf.write_u8(has_location).unwrap();
+        buf.write_f32::<LittleEndian>(latitude).unwrap();
+        buf.write_f32::<LittleEndian>(longitude).unwrap();
+        buf.write_u8(if self.continuous_recorder { 1 } else { 0 })
+            .unwrap();
+        buf.write_u8(if self.low_power_mode { 1 } else { 0 }).unwrap();
+        buf
+    }
+}
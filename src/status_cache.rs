@@ -0,0 +1,46 @@
+use crate::window_evaluator::WindowEvaluator;
+use crate::DeviceConfig;
+use chrono::NaiveDateTime;
+
+/// Answers `time_is_in_recording_window` for a device that checks it every few seconds, resolving
+/// the recording windows (and so the sun times) once per window rather than on every check.
+#[derive(Debug)]
+pub struct StatusCache<'a> {
+    config: &'a DeviceConfig,
+    // The windows and the span of time they were resolved for, from the check that resolved
+    // them until the first of them ends
+    cached: Option<(NaiveDateTime, NaiveDateTime, WindowEvaluator)>,
+    sun_time_calculations: usize,
+}
+
+impl<'a> StatusCache<'a> {
+    pub fn new(config: &'a DeviceConfig) -> Self {
+        StatusCache {
+            config,
+            cached: None,
+            sun_time_calculations: 0,
+        }
+    }
+
+    /// The same as `DeviceConfig::time_is_in_recording_window`, only recalculating the windows
+    /// when `now_utc` is past the end of the window they were last resolved for.
+    pub fn time_is_in_recording_window(&mut self, now_utc: &NaiveDateTime) -> bool {
+        match &self.cached {
+            Some((from, until, evaluator)) if from <= now_utc && now_utc <= until => {
+                evaluator.contains(now_utc)
+            }
+            _ => {
+                let (evaluator, until) = self.config.current_windows(now_utc);
+                self.sun_time_calculations += evaluator.sun_time_calculations();
+                let recording = evaluator.contains(now_utc);
+                self.cached = Some((*now_utc, until, evaluator));
+                recording
+            }
+        }
+    }
+
+    /// How many sunrise and sunset times have been calculated across all the checks.
+    pub fn sun_time_calculations(&self) -> usize {
+        self.sun_time_calculations
+    }
+}
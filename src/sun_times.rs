@@ -6,6 +6,22 @@ const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
 const JAN_2000: JulianDate = JulianDate(2451545.0);
 const LEAP_SECONDS: JulianDate = JulianDate(0.0008);
 const OBLIQUITY_OF_THE_ECLIPTIC: f64 = 23.44;
+// Altitude of the centre of the sun at sunrise/sunset, allowing for refraction and its radius
+const SUNRISE_ALTITUDE: f64 = -0.83;
+const CIVIL_TWILIGHT_ALTITUDE: f64 = -6.0;
+
+/// Why sunrise and sunset couldn't be calculated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SunTimesError {
+    /// The sun stays above the horizon all day
+    PolarDay,
+    /// The sun stays below the horizon all day
+    PolarNight,
+    InvalidLatitude,
+    InvalidLongitude,
+    /// The date can't be represented
+    InvalidDate,
+}
 
 #[derive(Debug, Clone, Copy)]
 struct JulianDate(f64);
@@ -70,30 +86,81 @@ fn rem_euclid(lhs: f64, rhs: f64) -> f64 {
 /// # Return value
 ///
 /// Returns
-///  - `None` if the date is not representable in chrono (~5M years from now), or sunsets/rises cannot be calculated due to long arctic/antarctic day/night (outside ~±67° of latitude)
-///  - `Some((sunrise,sunset))` otherwise
+///  - `Err(SunTimesError::InvalidDate)` if the date is not representable in chrono (~5M years from now)
+///  - `Err(SunTimesError::PolarDay)` or `Err(SunTimesError::PolarNight)` if sunsets/rises cannot be calculated due to long arctic/antarctic day/night (outside ~±67° of latitude)
+///  - `Err(SunTimesError::InvalidLatitude)` or `Err(SunTimesError::InvalidLongitude)` for coordinates that aren't on Earth
+///  - `Ok((sunrise,sunset))` otherwise
 ///
 /// # Examples
 ///
 /// ```
 /// //Calculate the sunset and sunrise times today at Sheffield university's new computer science building
-/// let times = sun_times(Utc::today(),53.38,-1.48,100.0);
+/// let times = try_sun_times(Utc::today(),53.38,-1.48,100.0).unwrap();
 /// println!("Sunrise: {}, Sunset: {}",times.0,times.1);
 /// ```
-pub fn sun_times(
+pub fn try_sun_times(
     date: NaiveDate,
     latitude: f64,
     longitude: f64,
     elevation: f64,
-) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+) -> Result<(DateTime<Utc>, DateTime<Utc>), SunTimesError> {
+    try_sun_crossing_times(date, latitude, longitude, elevation, SUNRISE_ALTITUDE)
+}
+
+/// Like `try_sun_times`, but for a horizon `horizon_angle` degrees above the true horizon, e.g. a
+/// ridge that hides the sun before it actually sets.
+pub fn try_sun_times_with_horizon(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+    elevation: f64,
+    horizon_angle: f64,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), SunTimesError> {
+    try_sun_crossing_times(
+        date,
+        latitude,
+        longitude,
+        elevation,
+        SUNRISE_ALTITUDE + horizon_angle,
+    )
+}
+
+/// Calculates the approximate start of morning and end of evening civil twilight (civil dawn and
+/// dusk), when the centre of the sun is 6° below the horizon.  See `try_sun_times`.
+///
+/// `Err(SunTimesError::PolarDay)` here means the sun never gets that far below the horizon.
+pub fn try_civil_twilight(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+    elevation: f64,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), SunTimesError> {
+    try_sun_crossing_times(date, latitude, longitude, elevation, CIVIL_TWILIGHT_ALTITUDE)
+}
+
+// The times the centre of the sun rises through and sets through `sun_altitude` degrees
+fn try_sun_crossing_times(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+    elevation: f64,
+    sun_altitude: f64,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), SunTimesError> {
     //see https://en.wikipedia.org/wiki/Sunrise_equation
 
     const ARGUMENT_OF_PERIHELION: f64 = 102.9372;
 
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(SunTimesError::InvalidLatitude);
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(SunTimesError::InvalidLongitude);
+    }
+
     let julian_date = JulianDate::from(
-        date.and_hms_opt(0, 0, 0)?
-            .and_local_timezone(Utc)
-            .single()?,
+        date.and_hms_opt(0, 0, 0)
+            .and_then(|date_time| date_time.and_local_timezone(Utc).single())
+            .ok_or(SunTimesError::InvalidDate)?,
     );
 
     //elevations below sea level will have minimal atmospheric refraction + the
@@ -116,15 +183,17 @@ pub fn sun_times(
     let declination = (ecliptic_longitude.to_radians().sin()
         * OBLIQUITY_OF_THE_ECLIPTIC.to_radians().sin())
         .asin();
-    let event_hour_angle = (((-0.83 + elevation_correction).to_radians().sin()
+    let cos_event_hour_angle = ((sun_altitude + elevation_correction).to_radians().sin()
         - (latitude.to_radians().sin() * declination.sin()))
-        / (latitude.to_radians().cos() * declination.cos()))
-        .acos()
-        .to_degrees();
+        / (latitude.to_radians().cos() * declination.cos());
 
-    if event_hour_angle.is_nan() {
-        return None;
+    // Outside of [-1, 1] the sun never crosses the horizon today
+    if cos_event_hour_angle > 1.0 {
+        return Err(SunTimesError::PolarNight);
+    } else if cos_event_hour_angle < -1.0 {
+        return Err(SunTimesError::PolarDay);
     }
+    let event_hour_angle = cos_event_hour_angle.acos().to_degrees();
 
     let solar_transit =
         JAN_2000.0 + mean_solar_time + 0.0053 * solar_mean_anomaly.to_radians().sin()
@@ -136,8 +205,20 @@ pub fn sun_times(
     let rise = julian_rise.to_datetime();
     let set = julian_set.to_datetime();
     if let (Some(rise), Some(set)) = (rise, set) {
-        Some((rise, set))
+        Ok((rise, set))
     } else {
-        None
+        Err(SunTimesError::InvalidDate)
     }
 }
+
+/// Calculates the approximate sunset and sunrise times, see `try_sun_times`.
+///
+/// Returns `None` if the times can't be calculated, including during polar day and night.
+pub fn sun_times(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+    elevation: f64,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    try_sun_times(date, latitude, longitude, elevation).ok()
+}
@@ -0,0 +1,79 @@
+use crate::sun_times::SunTimesError;
+use chrono::{NaiveDate, NaiveDateTime};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Remembers solar event times that have already been calculated, so a device whose sun times
+/// barely change can reuse them rather than recalculating every time it checks its windows.
+///
+/// The cache isn't part of the config, so it compares equal to any other cache and clones empty.
+pub struct SunTimesCache<K> {
+    times: Mutex<HashMap<(K, NaiveDate), Result<NaiveDateTime, SunTimesError>>>,
+    calculations: AtomicUsize,
+}
+
+impl<K: Eq + Hash + Copy> SunTimesCache<K> {
+    pub fn get_or_insert_with<F>(
+        &self,
+        key: K,
+        date: NaiveDate,
+        calculate: F,
+    ) -> Result<NaiveDateTime, SunTimesError>
+    where
+        F: FnOnce() -> Result<NaiveDateTime, SunTimesError>,
+    {
+        let mut times = self.times.lock().unwrap();
+        *times.entry((key, date)).or_insert_with(|| {
+            self.calculations.fetch_add(1, Ordering::Relaxed);
+            calculate()
+        })
+    }
+
+    /// Forgets every time, e.g. when the location they were calculated for has changed.
+    pub fn clear(&self) {
+        self.times.lock().unwrap().clear();
+    }
+
+    /// Forgets the times for the dates that `keep` returns false for.
+    pub fn retain_dates<F: Fn(NaiveDate) -> bool>(&self, keep: F) {
+        self.times
+            .lock()
+            .unwrap()
+            .retain(|(_, date), _| keep(*date));
+    }
+
+    /// How many times had to be calculated rather than reused.
+    pub fn calculations(&self) -> usize {
+        self.calculations.load(Ordering::Relaxed)
+    }
+}
+
+impl<K> Default for SunTimesCache<K> {
+    fn default() -> Self {
+        SunTimesCache {
+            times: Mutex::new(HashMap::new()),
+            calculations: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<K> Clone for SunTimesCache<K> {
+    fn clone(&self) -> Self {
+        SunTimesCache::default()
+    }
+}
+
+impl<K> PartialEq for SunTimesCache<K> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<K> fmt::Debug for SunTimesCache<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SunTimesCache")
+    }
+}
@@ -1,6 +1,13 @@
-use crate::tests::{make_time_with_offset, NZ_SUMMER_UTC_OFFSET_SECONDS};
-use crate::DeviceConfig;
-use chrono::Duration;
+use crate::clock::FixedClock;
+use crate::tests::{
+    make_date_time_with_offset, make_time_with_offset, NZ_SUMMER_UTC_OFFSET_SECONDS,
+};
+use crate::{
+    ContinuousReason, DeviceConfig, InactiveReason, RecordingContext, TimezoneBasis,
+    STATUS_DISABLED, STATUS_IN_WINDOW, STATUS_LOCATED, STATUS_LOW_POWER, STATUS_REGISTERED,
+};
+use chrono::{Duration, NaiveDate, NaiveTime};
+use chrono_tz::Tz;
 
 #[test]
 fn test_start_less_than_end() {
@@ -250,3 +257,724 @@ stop-recording = "15:04"
     assert!(config.time_is_in_recording_window(&chrono::Utc::now().naive_utc()));
     assert!(config.is_continuous_recorder());
 }
+
+#[test]
+fn test_todays_local_window() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "20:30"
+stop-recording = "06:00"
+"#,
+    )
+    .unwrap();
+    let now_local = NaiveDate::from_ymd_opt(2024, 1, 2)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    assert_eq!(
+        config.todays_local_window(&now_local),
+        Some((
+            NaiveTime::from_hms_opt(20, 30, 0).unwrap(),
+            NaiveTime::from_hms_opt(6, 0, 0).unwrap()
+        ))
+    );
+
+    // A relative stop can't be resolved without a location
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "20:30"
+stop-recording = "sunrise+30m"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.todays_local_window(&now_local), None);
+}
+
+#[test]
+fn test_previous_recording_window() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "22:10"
+stop-recording = "9:50"
+"#,
+    )
+    .unwrap();
+    // Before today's window starts, the previous window is last night's
+    let now = make_time_with_offset(21, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let (start, end) = config.previous_recording_window(&now).unwrap();
+    assert_eq!(
+        start,
+        make_time_with_offset(22, 10, NZ_SUMMER_UTC_OFFSET_SECONDS) - Duration::days(1)
+    );
+    assert_eq!(end, make_time_with_offset(9, 50, NZ_SUMMER_UTC_OFFSET_SECONDS));
+
+    // Inside tonight's window, the previous window is still last night's
+    let now = make_time_with_offset(23, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(config.previous_recording_window(&now), Some((start, end)));
+}
+
+#[test]
+fn test_recording_status_with_fixed_clock() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "09:10"
+stop-recording = "17:30"
+"#,
+    )
+    .unwrap();
+    let clock = FixedClock(make_time_with_offset(8, 0, NZ_SUMMER_UTC_OFFSET_SECONDS));
+    assert_eq!(
+        config.recording_status(&clock),
+        "Not recording, next window starts in 1h10m and lasts 8h20m"
+    );
+    assert_eq!(
+        config.recording_status(&clock),
+        config.recording_status(&clock)
+    );
+
+    let clock = FixedClock(make_time_with_offset(16, 45, NZ_SUMMER_UTC_OFFSET_SECONDS));
+    assert_eq!(
+        config.recording_status(&clock),
+        "Recording, window ends in 0h45m"
+    );
+}
+
+#[test]
+fn test_should_record_with_min_voltage() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[power]
+min-voltage = 11.5
+
+[windows]
+start-recording = "09:10"
+stop-recording = "17:30"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.min_voltage(), Some(11.5));
+    let in_window = make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(config.should_record(&in_window, Some(12.2)));
+    assert!(config.should_record(&in_window, Some(11.5)));
+    assert!(!config.should_record(&in_window, Some(11.4)));
+    assert!(config.should_record(&in_window, None));
+    let out_of_window = make_time_with_offset(8, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(!config.should_record(&out_of_window, Some(12.2)));
+
+    // Without a [power] section only the window matters
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "09:10"
+stop-recording = "17:30"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.min_voltage(), None);
+    assert!(config.should_record(&in_window, Some(3.0)));
+}
+
+#[test]
+fn test_next_wake_time() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "22:10"
+stop-recording = "9:50"
+"#,
+    )
+    .unwrap();
+    // Inactive, so wake for the start of tonight's window
+    let now = make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(
+        config.next_wake_time(&now),
+        Some(make_time_with_offset(22, 10, NZ_SUMMER_UTC_OFFSET_SECONDS))
+    );
+    assert_eq!(
+        config.next_wake_time(&now),
+        Some(config.next_recording_window_start(&now))
+    );
+
+    // Active, so wake at the end of the window to shut down
+    let now = make_time_with_offset(23, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(
+        config.next_wake_time(&now),
+        Some(make_time_with_offset(9, 50, NZ_SUMMER_UTC_OFFSET_SECONDS) + Duration::days(1))
+    );
+    assert_ne!(
+        config.next_wake_time(&now),
+        Some(config.next_recording_window_start(&now))
+    );
+
+    // There's no window to wake for in a polar night, which is recorded through by default
+    let polar: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = 80.0
+longitude = 15.0
+"#,
+    )
+    .unwrap();
+    let winter = NaiveDate::from_ymd_opt(2023, 12, 21)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    assert_eq!(polar.next_wake_time(&winter), None);
+    assert!(polar.time_is_in_recording_window(&winter));
+}
+
+#[test]
+fn test_time_until_window_end() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "22:10"
+stop-recording = "9:50"
+"#,
+    )
+    .unwrap();
+    let inactive = make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(config.time_until_window_end(&inactive), None);
+
+    let active = make_time_with_offset(23, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(
+        config.time_until_window_end(&active),
+        Some(Duration::hours(10) + Duration::minutes(50))
+    );
+    let end = make_time_with_offset(9, 50, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(config.time_until_window_end(&end), Some(Duration::zero()));
+
+    let continuous: DeviceConfig =
+        toml::from_str("[thermal-recorder]\nconstant-recorder = true\n").unwrap();
+    assert_eq!(continuous.time_until_window_end(&active), None);
+}
+
+#[test]
+fn test_records_within() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "22:10"
+stop-recording = "9:50"
+"#,
+    )
+    .unwrap();
+    let at = |hour| make_time_with_offset(hour, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    // The window starts 6 hours 10 minutes after 16:00
+    assert!(!config.records_within(&at(16), Duration::hours(6)));
+    assert!(config.records_within(&at(16), Duration::hours(7)));
+    assert!(config.records_within(&at(20), Duration::hours(6)));
+    // Already active
+    assert!(config.records_within(&at(23), Duration::zero()));
+    assert!(!config.records_within(&at(12), Duration::hours(6)));
+
+    let continuous: DeviceConfig =
+        toml::from_str("[thermal-recorder]\nconstant-recorder = true\n").unwrap();
+    assert!(continuous.records_within(&at(12), Duration::zero()));
+}
+
+#[test]
+fn test_next_wake_time_with_warmup() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[thermal-recorder]
+warmup = "2m"
+
+[windows]
+start-recording = "22:10"
+stop-recording = "9:50"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.warmup_seconds(), 120);
+    let now = make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let start = make_time_with_offset(22, 10, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(
+        config.next_wake_time(&now),
+        Some(start - Duration::minutes(2))
+    );
+
+    // The window itself still starts at the nominal time
+    let warming_up = start - Duration::minutes(1);
+    assert!(!config.time_is_in_recording_window(&warming_up));
+    assert_eq!(config.next_wake_time(&warming_up), Some(warming_up));
+    assert!(config.time_is_in_recording_window(&start));
+
+    let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+    assert_eq!(reloaded.warmup_seconds(), 120);
+
+    for warmup in ["-2m", "12:00", "sunset"] {
+        let config: Result<DeviceConfig, _> =
+            toml::from_str(&format!("[thermal-recorder]\nwarmup = \"{}\"\n", warmup));
+        assert!(config.is_err(), "'{}' should not parse", warmup);
+    }
+}
+
+#[test]
+fn test_window_segments_for_utc_day() {
+    // 11:00 to 14:30 NZDT is 22:00 to 01:30 UTC, so it crosses midnight UTC
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "11:00"
+stop-recording = "14:30"
+"#,
+    )
+    .unwrap();
+    let date = NaiveDate::from_ymd_opt(2023, 11, 5).unwrap();
+    let at = |day: u32, hour: u32, min: u32| {
+        NaiveDate::from_ymd_opt(2023, 11, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    };
+    assert_eq!(
+        config.window_segments_for_utc_day(date),
+        vec![(at(5, 0, 0), at(5, 1, 30)), (at(5, 22, 0), at(6, 0, 0))]
+    );
+
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[thermal-recorder]
+constant-recorder = true
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.window_segments_for_utc_day(date),
+        vec![(at(5, 0, 0), at(6, 0, 0))]
+    );
+}
+
+#[test]
+fn test_continuous_reason() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "15:04"
+stop-recording = "15:04"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.continuous_reason(),
+        Some(ContinuousReason::EqualAbsoluteTimes)
+    );
+
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[thermal-recorder]
+constant-recorder = true
+
+[windows]
+start-recording = "15:04"
+stop-recording = "15:04"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.continuous_reason(), Some(ContinuousReason::Explicit));
+
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "15:04"
+stop-recording = "15:05"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.continuous_reason(), None);
+    assert!(!config.is_continuous_recorder());
+}
+
+#[test]
+fn test_estimated_active_seconds_per_day() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "20:00"
+stop-recording = "00:00"
+"#,
+    )
+    .unwrap();
+    let date = NaiveDate::from_ymd_opt(2023, 11, 5).unwrap();
+    assert_eq!(config.estimated_active_seconds_per_day(date), 14400.0);
+
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[thermal-recorder]
+constant-recorder = true
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.estimated_active_seconds_per_day(date), 86400.0);
+
+    // The default window records through a polar night
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = 78.22
+longitude = 15.65
+"#,
+    )
+    .unwrap();
+    let midwinter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+    assert_eq!(
+        config.window_segments_for_utc_day(midwinter),
+        vec![(
+            midwinter.and_hms_opt(0, 0, 0).unwrap(),
+            midwinter.and_hms_opt(0, 0, 0).unwrap() + Duration::days(1)
+        )]
+    );
+    assert_eq!(config.estimated_active_seconds_per_day(midwinter), 86400.0);
+}
+
+#[test]
+fn test_duty_cycle() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "09:00"
+stop-recording = "17:00"
+"#,
+    )
+    .unwrap();
+    let date = NaiveDate::from_ymd_opt(2023, 11, 5).unwrap();
+    assert!((config.duty_cycle(date) - 1.0 / 3.0).abs() < 1e-9);
+
+    let config: DeviceConfig =
+        toml::from_str("[thermal-recorder]\nconstant-recorder = true\n").unwrap();
+    assert_eq!(config.duty_cycle(date), 1.0);
+
+    // The sun doesn't set in Svalbard in June, and the window is off during polar day
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = 78.22
+longitude = 15.65
+"#,
+    )
+    .unwrap();
+    let midsummer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+    assert_eq!(config.duty_cycle(midsummer), 0.0);
+
+    // Polar night is recorded through by default, so the whole day is
+    let midwinter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+    assert_eq!(config.duty_cycle(midwinter), 1.0);
+
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = 78.22
+longitude = 15.65
+
+[windows]
+polar-day = "record"
+polar-night = "off"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.duty_cycle(midsummer), 1.0);
+    assert_eq!(config.duty_cycle(midwinter), 0.0);
+}
+
+#[test]
+fn test_next_window_is_today() {
+    let now_local = NaiveDate::from_ymd_opt(2024, 1, 2)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "20:30"
+stop-recording = "06:00"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.next_window_is_today(&now_local), Some(true));
+
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "01:00"
+stop-recording = "03:00"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.next_window_is_today(&now_local), Some(false));
+
+    // Already recording
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "09:00"
+stop-recording = "17:00"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.next_window_is_today(&now_local), Some(true));
+
+    // A relative window can't be placed without a location
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "-30m"
+stop-recording = "30m"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.next_window_is_today(&now_local), None);
+}
+
+#[test]
+fn test_absolute_offsets_stay_within_a_day() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "23:59:59"
+stop-recording = "00:00"
+"#,
+    )
+    .unwrap();
+    let (start, stop) = config.recording_window();
+    assert_eq!(start.time_offset_for_tz(0), (true, 86_399));
+    assert_eq!(
+        start.time_offset_for_tz(NZ_SUMMER_UTC_OFFSET_SECONDS as i32),
+        (true, 86_399 - 13 * 60 * 60)
+    );
+    // West of UTC the time is later in the day, and wraps past UTC midnight
+    let hawaii = -10 * 60 * 60;
+    assert_eq!(start.time_offset_for_tz(hawaii), (true, 10 * 60 * 60 - 1));
+    // East of UTC, local midnight is the afternoon before in UTC
+    assert_eq!(stop.time_offset_for_tz(14 * 60 * 60), (true, 10 * 60 * 60));
+    assert_eq!(stop.time_offset_for_tz(-12 * 60 * 60), (true, 12 * 60 * 60));
+}
+
+#[test]
+fn test_inactive_reason() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[power]
+min-voltage = 3.4
+
+[windows]
+start-recording = "09:00"
+stop-recording = "17:00"
+"#,
+    )
+    .unwrap();
+    let at = |hour| make_time_with_offset(hour, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let unknown = RecordingContext::default();
+    assert_eq!(
+        config.inactive_reason(&at(7), &unknown),
+        Some(InactiveReason::BeforeWindow)
+    );
+    assert_eq!(config.inactive_reason(&at(12), &unknown), None);
+    assert_eq!(
+        config.inactive_reason(&at(20), &unknown),
+        Some(InactiveReason::AfterWindow)
+    );
+
+    let flat = RecordingContext {
+        battery_voltage: Some(3.2),
+    };
+    assert_eq!(
+        config.inactive_reason(&at(12), &flat),
+        Some(InactiveReason::LowVoltage)
+    );
+    assert_eq!(
+        config.inactive_reason(&at(7), &flat),
+        Some(InactiveReason::BeforeWindow)
+    );
+}
+
+#[test]
+fn test_status_code() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[device]
+id = 1
+group = "test-group"
+name = "test-name"
+
+[location]
+latitude = -46.60101
+longitude = 172.71303
+
+[power]
+min-voltage = 3.4
+
+[windows]
+start-recording = "09:00"
+stop-recording = "17:00"
+"#,
+    )
+    .unwrap();
+    let at = |hour| make_time_with_offset(hour, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let unknown = RecordingContext::default();
+    let expected = STATUS_REGISTERED | STATUS_LOCATED | STATUS_IN_WINDOW;
+    assert_eq!(config.status_code(&at(12), &unknown), expected);
+    assert_eq!(expected, 0b0000_0111);
+    assert_eq!(
+        config.status_code(&at(20), &unknown),
+        STATUS_REGISTERED | STATUS_LOCATED
+    );
+
+    let flat = RecordingContext {
+        battery_voltage: Some(3.2),
+    };
+    assert_eq!(
+        config.status_code(&at(12), &flat),
+        expected | STATUS_DISABLED
+    );
+
+    let low_power: DeviceConfig =
+        toml::from_str("[thermal-recorder]\nuse-low-power-mode = true\n").unwrap();
+    assert_eq!(low_power.status_code(&at(20), &unknown), STATUS_LOW_POWER);
+}
+
+#[test]
+fn test_transitions_next_24h() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "20:00"
+stop-recording = "06:00"
+"#,
+    )
+    .unwrap();
+    let offset = NZ_SUMMER_UTC_OFFSET_SECONDS;
+    let midday = make_date_time_with_offset(2024, 1, 10, 12, 0, offset);
+    assert_eq!(
+        config.transitions_next_24h(&midday),
+        vec![(8 * 60 * 60, true), (18 * 60 * 60, false)]
+    );
+
+    // Already inside the window, so recording next turns off and then on again
+    let night = make_date_time_with_offset(2024, 1, 10, 23, 0, offset);
+    assert_eq!(
+        config.transitions_next_24h(&night),
+        vec![(7 * 60 * 60, false), (21 * 60 * 60, true)]
+    );
+
+    let continuous: DeviceConfig =
+        toml::from_str("[thermal-recorder]\nconstant-recorder = true\n").unwrap();
+    assert!(continuous.transitions_next_24h(&midday).is_empty());
+}
+
+#[test]
+fn test_start_in_daylight_saving_gap() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -46.60101
+longitude = 172.71303
+timezone = "Pacific/Auckland"
+
+[windows]
+start-recording = "02:30"
+stop-recording = "04:00"
+"#,
+    )
+    .unwrap();
+    let tz = config.configured_timezone().unwrap();
+    assert_eq!(tz, Tz::Pacific__Auckland);
+    let (start, stop) = config.recording_window();
+    let utc = |day, hour, min| {
+        NaiveDate::from_ymd_opt(2024, 9, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+    };
+
+    // The clocks go from 02:00 NZST to 03:00 NZDT on the 29th of September 2024, so 02:30 is
+    // moved to 03:00 NZDT
+    let spring_forward = NaiveDate::from_ymd_opt(2024, 9, 29).unwrap();
+    assert_eq!(
+        start.resolve_in_timezone(spring_forward, None, &tz),
+        utc(28, 14, 0)
+    );
+    assert_eq!(
+        stop.resolve_in_timezone(spring_forward, None, &tz),
+        utc(28, 15, 0)
+    );
+    // The day before is still NZST
+    assert_eq!(
+        start.resolve_in_timezone(spring_forward - Duration::days(1), None, &tz),
+        utc(27, 14, 30)
+    );
+
+    // 02:30 happens twice when the clocks go back on the 7th of April 2024, so use the first
+    let fall_back = NaiveDate::from_ymd_opt(2024, 4, 7).unwrap();
+    assert_eq!(
+        start.resolve_in_timezone(fall_back, None, &tz),
+        NaiveDate::from_ymd_opt(2024, 4, 6)
+            .unwrap()
+            .and_hms_opt(13, 30, 0)
+    );
+
+    let unknown: Result<DeviceConfig, _> =
+        toml::from_str("[location]\ntimezone = \"Pacific/Atlantis\"\n");
+    assert!(unknown.is_err());
+}
+
+#[test]
+fn test_window_in_configured_timezone() {
+    let window = r#"
+[windows]
+start-recording = "22:10"
+stop-recording = "09:50"
+"#;
+    let system: DeviceConfig = toml::from_str(window).unwrap();
+    let new_york: DeviceConfig = toml::from_str(&format!(
+        "[location]\ntimezone = \"America/New_York\"\n{}",
+        window
+    ))
+    .unwrap();
+    assert_eq!(new_york.timezone_basis(), TimezoneBasis::ConfiguredIana);
+    assert_eq!(system.timezone_basis(), TimezoneBasis::SystemLocal);
+
+    let utc = |month, day, hour, min| {
+        NaiveDate::from_ymd_opt(2024, month, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    };
+    // 07:00 EST, so inside the window from 22:10 EST last night
+    let now = utc(1, 15, 12, 0);
+    assert_eq!(
+        new_york.next_recording_window(&now),
+        (utc(1, 15, 3, 10), utc(1, 15, 14, 50))
+    );
+    assert_ne!(
+        new_york.next_recording_window(&now),
+        system.next_recording_window(&now)
+    );
+    assert!(new_york.time_is_in_recording_window(&now));
+    assert!(!new_york.time_is_in_recording_window(&utc(1, 15, 15, 0)));
+    // Daylight saving time starts on the 10th of March, so the window moves an hour in UTC
+    assert_eq!(
+        new_york.next_recording_window(&utc(3, 11, 0, 0)),
+        (utc(3, 11, 2, 10), utc(3, 11, 13, 50))
+    );
+
+    // 02:30 doesn't exist on the 10th of March, the clocks go from 02:00 EST to 03:00 EDT
+    let in_gap: DeviceConfig = toml::from_str(
+        r#"
+[location]
+timezone = "America/New_York"
+
+[windows]
+start-recording = "02:30"
+stop-recording = "04:00"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        in_gap.next_recording_window(&utc(3, 10, 5, 0)),
+        (utc(3, 10, 7, 0), utc(3, 10, 8, 0))
+    );
+}
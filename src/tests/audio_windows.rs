@@ -0,0 +1,77 @@
+use crate::tests::{make_time_with_offset, NZ_SUMMER_UTC_OFFSET_SECONDS, SAMPLE_CONFIG};
+use crate::DeviceConfig;
+use chrono::Duration;
+
+const AUDIO_CONFIG: &str = r#"
+[windows]
+start-recording = "22:10"
+stop-recording = "09:50"
+
+[audio-recorder]
+start-recording = "06:00"
+stop-recording = "12:00"
+"#;
+
+#[test]
+fn test_audio_window_independent_of_thermal() {
+    let config: DeviceConfig = toml::from_str(AUDIO_CONFIG).unwrap();
+    let at = |hour, min| make_time_with_offset(hour, min, NZ_SUMMER_UTC_OFFSET_SECONDS);
+
+    // 23:00: only thermal
+    assert!(config.time_is_in_recording_window(&at(23, 0)));
+    assert!(!config.audio_is_active(&at(23, 0)));
+    assert_eq!(
+        config.audio_window(&at(23, 0)),
+        Some((at(6, 0) + Duration::days(1), at(12, 0) + Duration::days(1)))
+    );
+
+    // 07:00: both
+    assert!(config.time_is_in_recording_window(&at(7, 0)));
+    assert!(config.audio_is_active(&at(7, 0)));
+
+    // 11:00: only audio
+    assert!(!config.time_is_in_recording_window(&at(11, 0)));
+    assert!(config.audio_is_active(&at(11, 0)));
+    assert_eq!(config.audio_window(&at(11, 0)), Some((at(6, 0), at(12, 0))));
+    assert_eq!(config.next_recording_window(&at(11, 0)).0, at(22, 10));
+
+    let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+    assert_eq!(reloaded, config);
+}
+
+#[test]
+fn test_audio_window_relative_to_sun() {
+    // Audio records through the day while thermal keeps its default overnight window
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -46.60101
+longitude = 172.71303
+
+[audio-recorder]
+start-recording = "sunrise-30m"
+stop-recording = "sunset+30m"
+"#,
+    )
+    .unwrap();
+    let now = make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(config.audio_is_active(&now));
+    assert!(!config.time_is_in_recording_window(&now));
+
+    let (audio_start, audio_end) = config.audio_window(&now).unwrap();
+    let (thermal_start, thermal_end) = config.next_recording_window(&now);
+    assert!(audio_start < now && now < audio_end);
+    // Thermal starts 30 minutes before sunset, audio stops 30 minutes after
+    assert_eq!(audio_end - thermal_start, Duration::hours(1));
+    // Both are recording for the hour around sunrise
+    assert!(config.audio_is_active(&thermal_end));
+    assert!(!config.audio_is_active(&(thermal_end - Duration::hours(1) - Duration::minutes(1))));
+}
+
+#[test]
+fn test_no_audio_window() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let now = make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(config.audio_window(&now), None);
+    assert!(!config.audio_is_active(&now));
+}
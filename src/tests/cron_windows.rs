@@ -0,0 +1,93 @@
+use crate::tests::{make_time_with_offset, NZ_SUMMER_UTC_OFFSET_SECONDS};
+use crate::DeviceConfig;
+use chrono::Duration;
+
+#[test]
+fn test_cron_every_fifteen_minutes() {
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[windows]
+cron = "*/15 * * * *"
+"#,
+    );
+    assert!(config.is_ok());
+    let config = config.unwrap();
+    for min in 0..60 {
+        let now = make_time_with_offset(10, min, NZ_SUMMER_UTC_OFFSET_SECONDS);
+        assert_eq!(
+            config.time_is_in_recording_window(&now),
+            min % 15 == 0,
+            "Minute {}",
+            min
+        );
+    }
+    let now = make_time_with_offset(10, 1, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let (start, end) = config.next_recording_window(&now);
+    assert_eq!(
+        start,
+        make_time_with_offset(10, 15, NZ_SUMMER_UTC_OFFSET_SECONDS)
+    );
+    assert_eq!(end - start, Duration::minutes(1));
+
+    // The previous window is the one that just finished, not yesterday's
+    assert_eq!(
+        config.previous_recording_window(&now),
+        Some((
+            make_time_with_offset(10, 0, NZ_SUMMER_UTC_OFFSET_SECONDS),
+            make_time_with_offset(10, 1, NZ_SUMMER_UTC_OFFSET_SECONDS)
+        ))
+    );
+}
+
+#[test]
+fn test_cron_hour_range() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+cron = "0-9 6-18 * * *"
+"#,
+    )
+    .unwrap();
+    let now = make_time_with_offset(6, 5, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(config.time_is_in_recording_window(&now));
+    let now = make_time_with_offset(6, 10, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(!config.time_is_in_recording_window(&now));
+    let now = make_time_with_offset(19, 5, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(!config.time_is_in_recording_window(&now));
+    let (start, end) = config.next_recording_window(&now);
+    assert_eq!(
+        start,
+        make_time_with_offset(6, 0, NZ_SUMMER_UTC_OFFSET_SECONDS) + Duration::days(1)
+    );
+    assert_eq!(end - start, Duration::minutes(10));
+}
+
+#[test]
+fn test_cron_invalid() {
+    for windows in [
+        "cron = \"*/15 * * * *\"\nstart-recording = \"20:00\"",
+        "cron = \"*/0 * * * *\"",
+        "cron = \"60 * * * *\"",
+        "cron = \"* * 1 * *\"",
+        "cron = \"* *\"",
+    ] {
+        let config: Result<DeviceConfig, _> = toml::from_str(&format!("[windows]\n{}\n", windows));
+        assert!(config.is_err(), "'{}' should not parse", windows);
+    }
+}
+
+#[test]
+fn test_cron_round_trip() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+cron = "0-9 6,18 * * *"
+"#,
+    )
+    .unwrap();
+    let written = config.to_toml_string();
+    assert!(written.contains("cron = \"0-9 6,18 * * *\""));
+    assert!(!written.contains("start-recording"));
+    let reloaded: DeviceConfig = toml::from_str(&written).unwrap();
+    assert_eq!(reloaded, config);
+}
@@ -0,0 +1,256 @@
+use crate::detection_mask::{DetectionMask, MaskError, FRAME_SIZE};
+use crate::DeviceConfig;
+
+fn masked_pixel_count(mask: &DetectionMask) -> usize {
+    (0..160 * 120)
+        .filter(|index| mask.is_masked_at_index(*index))
+        .count()
+}
+
+#[test]
+fn test_new_mask_fill() {
+    assert_eq!(masked_pixel_count(&DetectionMask::new(None, FRAME_SIZE)), 0);
+    assert_eq!(
+        masked_pixel_count(&DetectionMask::new(Some(false), FRAME_SIZE)),
+        0
+    );
+    assert_eq!(
+        masked_pixel_count(&DetectionMask::new(Some(true), FRAME_SIZE)),
+        160 * 120
+    );
+}
+
+#[test]
+fn test_mask_regions_normalized() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[thermal-recorder.mask-regions]
+corner = [[0.0, 0.0], [0.5, 0.0], [0.5, 0.5], [0.0, 0.5]]
+"#,
+    )
+    .unwrap();
+    let masked = masked_pixel_count(&config.recording_settings.mask_regions);
+    assert!(masked > 0 && masked < 160 * 120);
+}
+
+#[test]
+fn test_mask_regions_pixel_coordinates() {
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[thermal-recorder.mask-regions]
+corner = [[0, 0], [80, 0], [80, 60], [0, 60]]
+"#,
+    );
+    let error = config.unwrap_err().to_string();
+    assert!(error.contains("pixel coordinates"), "{}", error);
+}
+
+#[test]
+fn test_mask_region_with_hole() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[thermal-recorder.mask-regions.feeder]
+outer = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]
+holes = [[[0.25, 0.25], [0.75, 0.25], [0.75, 0.75], [0.25, 0.75]]]
+"#,
+    )
+    .unwrap();
+    let mask = &config.recording_settings.mask_regions;
+    let is_masked = |x: usize, y: usize| mask.is_masked_at_index(y * 160 + x);
+    assert!(is_masked(10, 10));
+    assert!(is_masked(150, 110));
+    assert!(!is_masked(80, 60));
+    assert!(!is_masked(50, 40));
+    assert!(masked_pixel_count(mask) < 160 * 120);
+
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[thermal-recorder.mask-regions.feeder]
+holes = [[[0.25, 0.25], [0.75, 0.25], [0.75, 0.75]]]
+"#,
+    );
+    assert!(config.is_err());
+}
+
+#[test]
+fn test_mask_region_past_frame_edge() {
+    // Slightly past the right edge, as mask editors sometimes produce
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[thermal-recorder.mask-regions]
+right = [[0.9, 0.4], [1.004, 0.4], [1.004, 0.6], [0.9, 0.6]]
+"#,
+    )
+    .unwrap();
+    let mask = &config.recording_settings.mask_regions;
+    for x in 145..160 {
+        assert!(
+            mask.is_masked_at_pos(x, 60),
+            "Pixel ({}, 60) should be masked",
+            x
+        );
+    }
+    assert!(!mask.is_masked_at_pos(140, 60));
+    assert!(!mask.is_masked_at_pos(159, 30));
+
+    // Well past the edge is still an error
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[thermal-recorder.mask-regions]
+right = [[0.9, 0.4], [1.1, 0.4], [1.1, 0.6], [0.9, 0.6]]
+"#,
+    );
+    assert!(config.is_err());
+}
+
+#[test]
+fn test_mask_from_rects() {
+    let full = DetectionMask::from_rects(&[(0.0, 0.0, 1.0, 1.0)], (160, 120));
+    assert_eq!(masked_pixel_count(&full), 160 * 120);
+    assert_eq!(full, DetectionMask::new(Some(true), FRAME_SIZE));
+
+    let corner = DetectionMask::from_rects(&[(0.0, 0.0, 0.5, 0.5)], (160, 120));
+    assert!(corner.is_masked_at_pos(0, 0));
+    assert!(corner.is_masked_at_pos(80, 60));
+    assert!(!corner.is_masked_at_pos(81, 60));
+    assert!(!corner.is_masked_at_pos(80, 61));
+    assert_eq!(masked_pixel_count(&corner), 81 * 61);
+
+    let outside = DetectionMask::from_rects(&[(-0.5, -0.5, 0.2, 0.2)], (160, 120));
+    assert_eq!(masked_pixel_count(&outside), 0);
+
+    assert_eq!(
+        masked_pixel_count(&DetectionMask::from_rects(&[], (160, 120))),
+        0
+    );
+}
+
+#[test]
+fn test_mask_resized() {
+    let mut mask = DetectionMask::new(None, FRAME_SIZE);
+    mask.set_pos(10, 20);
+    let resized = mask.resized(320, 240);
+    assert_eq!((resized.width(), resized.height()), (320, 240));
+    for (x, y) in [(20, 40), (21, 40), (20, 41), (21, 41)] {
+        assert!(
+            resized.is_masked_at_pos(x, y),
+            "({}, {}) should be masked",
+            x,
+            y
+        );
+    }
+    let masked = (0..320 * 240)
+        .filter(|index| resized.is_masked_at_index(*index))
+        .count();
+    assert_eq!(masked, 4);
+
+    // And back down again
+    assert_eq!(resized.resized(160, 120), mask);
+}
+
+#[test]
+fn test_from_polygon() {
+    // The top left half of the top left quarter, so pixels where x / 80 + y / 60 <= 1
+    let triangle = [[0.0, 0.0], [0.5, 0.0], [0.0, 0.5]];
+    let mask = DetectionMask::from_polygon(&triangle, (160, 120)).unwrap();
+    for (x, y) in [(0, 0), (79, 0), (0, 59), (40, 30), (20, 40)] {
+        assert!(
+            mask.is_masked_at_pos(x, y),
+            "({}, {}) should be masked",
+            x,
+            y
+        );
+    }
+    for (x, y) in [(81, 0), (0, 61), (41, 31), (159, 119)] {
+        assert!(
+            !mask.is_masked_at_pos(x, y),
+            "({}, {}) shouldn't be masked",
+            x,
+            y
+        );
+    }
+
+    // The same as the mask read from the config
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[thermal-recorder.mask-regions]
+triangle = [[0.0, 0.0], [0.5, 0.0], [0.0, 0.5]]
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.recording_settings.mask_regions, mask);
+
+    let small = DetectionMask::from_polygon(&triangle, (16, 12)).unwrap();
+    assert_eq!((small.width(), small.height()), (16, 12));
+    assert!(small.is_masked_at_pos(7, 0));
+    assert!(!small.is_masked_at_pos(9, 0));
+}
+
+#[test]
+fn test_mask_union() {
+    let mut left = DetectionMask::new(None, FRAME_SIZE);
+    left.set_pos(0, 0);
+    let mut right = DetectionMask::new(None, FRAME_SIZE);
+    right.set_pos(159, 119);
+    let both = left.union(&right).unwrap();
+    assert!(both.is_masked_at_pos(0, 0));
+    assert!(both.is_masked_at_pos(159, 119));
+    assert_eq!(masked_pixel_count(&both), 2);
+
+    // The default mask from a config has to match masks made for the frame
+    let config: DeviceConfig = toml::from_str("").unwrap();
+    assert!(config.recording_settings.mask_regions.union(&left).is_ok());
+
+    let larger = DetectionMask::new(None, (320, 240));
+    assert_eq!(
+        left.union(&larger),
+        Err(MaskError::SizeMismatch {
+            left: (160, 120),
+            right: (320, 240),
+        })
+    );
+    assert!(left.union(&larger.resized(160, 120)).is_ok());
+}
+
+#[test]
+fn test_mask_degenerate_polygons() {
+    for points in [
+        vec![],
+        vec![[0.1, 0.1], [0.5, 0.5]],
+        // No area
+        vec![[0.1, 0.1], [0.5, 0.5], [0.9, 0.9]],
+        // Closed by repeating the first point
+        vec![[0.1, 0.1], [0.9, 0.1], [0.9, 0.9], [0.1, 0.1]],
+        // Bow tie
+        vec![[0.1, 0.1], [0.9, 0.1], [0.1, 0.9], [0.9, 0.9]],
+        // Back along the first edge
+        vec![[0.1, 0.1], [0.9, 0.1], [0.5, 0.1], [0.5, 0.9]],
+    ] {
+        assert!(
+            matches!(
+                DetectionMask::from_polygon(&points, FRAME_SIZE),
+                Err(MaskError::InvalidPolygon(_))
+            ),
+            "{:?} should be rejected",
+            points
+        );
+    }
+
+    // Points along an edge and concave corners are fine
+    for points in [
+        vec![[0.1, 0.1], [0.5, 0.1], [0.9, 0.1], [0.9, 0.9]],
+        vec![[0.1, 0.1], [0.9, 0.1], [0.5, 0.5], [0.9, 0.9], [0.1, 0.9]],
+    ] {
+        let mask = DetectionMask::from_polygon(&points, FRAME_SIZE).unwrap();
+        assert!(masked_pixel_count(&mask) > 0);
+    }
+
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[thermal-recorder.mask-regions]
+bow-tie = [[0.1, 0.1], [0.9, 0.1], [0.1, 0.9], [0.9, 0.9]]
+"#,
+    );
+    let error = config.unwrap_err().to_string();
+    assert!(error.contains("Region 'bow-tie'"), "{}", error);
+}
@@ -0,0 +1,12 @@
+use crate::format_duration_hm;
+use chrono::Duration;
+
+#[test]
+fn test_format_duration_hm() {
+    assert_eq!(format_duration_hm(Duration::minutes(0)), "0h0m");
+    assert_eq!(format_duration_hm(Duration::minutes(59)), "0h59m");
+    assert_eq!(format_duration_hm(Duration::minutes(90)), "1h30m");
+    assert_eq!(format_duration_hm(Duration::seconds(90 * 60 + 59)), "1h30m");
+    assert_eq!(format_duration_hm(Duration::minutes(-90)), "-1h30m");
+    assert_eq!(format_duration_hm(Duration::seconds(-30)), "0h0m");
+}
@@ -0,0 +1,291 @@
+use crate::clock::FixedClock;
+use crate::tests::SAMPLE_CONFIG;
+use crate::{
+    haversine_distance_m, ConfigWarning, DeviceConfig, LocationSettings, LocationUpdate,
+    WindowError,
+};
+use chrono::NaiveDate;
+
+#[test]
+fn test_location_updated() {
+    let config: Result<DeviceConfig, _> = toml::from_str(SAMPLE_CONFIG);
+    assert!(config.is_ok());
+    let config = config.unwrap();
+    // 2023-11-02T08:24:21+13:00 is 2023-11-01T19:24:21Z
+    assert_eq!(config.location_updated(), Some(1_698_866_661_000_000));
+    assert_eq!(config.location_updated(), config.location_timestamp());
+}
+
+#[test]
+fn test_location_conflict() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let (lat, lng) = config.lat_lng();
+    // One degree of latitude is ~111.2km
+    let lat_500m_north = lat + 500.0 / 111_195.0;
+    let distance = haversine_distance_m((lat, lng), (lat_500m_north, lng));
+    assert!((distance - 500.0).abs() < 1.0, "Distance was {}", distance);
+
+    match config.check_location_conflict((lat_500m_north, lng)) {
+        Some(ConfigWarning::LocationMismatch { distance_m }) => {
+            assert!((distance_m - 500.0).abs() < 1.0)
+        }
+        other => panic!("Expected a location mismatch warning, got {:?}", other),
+    }
+    let lat_50m_north = lat + 50.0 / 111_195.0;
+    assert_eq!(config.check_location_conflict((lat_50m_north, lng)), None);
+}
+
+#[test]
+fn test_location_timestamp_leap_second() {
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[location]
+latitude = -46.60101
+longitude = 172.71303
+timestamp = 2016-12-31T23:59:60Z
+"#,
+    );
+    assert!(config.is_ok());
+    let config = config.unwrap();
+    // Clamped to 2016-12-31T23:59:59Z
+    assert_eq!(config.location_timestamp(), Some(1_483_228_799_000_000));
+}
+
+#[test]
+fn test_coordinate_parsing() {
+    for (latitude, expected) in [
+        ("-46.6", Some(-46.6)),
+        ("-46", Some(-46.0)),
+        ("\"-46.6\"", Some(-46.6)),
+        ("\" -46.6 \"", Some(-46.6)),
+        ("\"south\"", None),
+        ("true", None),
+    ] {
+        let config: Result<DeviceConfig, _> = toml::from_str(&format!(
+            r#"
+[location]
+latitude = {}
+longitude = 172.71303
+"#,
+            latitude
+        ));
+        match expected {
+            Some(expected) => {
+                assert!(config.is_ok(), "Latitude {} should parse", latitude);
+                assert_eq!(config.unwrap().lat_lng(), (expected, 172.71303));
+            }
+            None => {
+                let error = config.unwrap_err().to_string();
+                assert!(
+                    error.contains("Invalid coordinate"),
+                    "Latitude {} error: {}",
+                    latitude,
+                    error
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_location_timestamp_resolutions() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -46.60101
+longitude = 172.71303
+timestamp = 2023-11-02T08:24:21.123456+13:00
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.location_timestamp(), Some(1_698_866_661_123_456));
+    assert_eq!(config.location_timestamp_millis(), Some(1_698_866_661_123));
+    assert_eq!(config.location_timestamp_secs(), Some(1_698_866_661));
+}
+
+#[test]
+fn test_resolved_location() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let location = config.location().unwrap();
+    assert_eq!(location.lat_lng, config.lat_lng());
+    assert_eq!(location.altitude, config.location_altitude());
+    assert_eq!(location.accuracy, config.location_accuracy());
+    assert_eq!(location.timestamp, config.location_timestamp());
+    assert_eq!(location.updated, config.location_updated());
+
+    let config: DeviceConfig = toml::from_str("[location]\naltitude = 10.0\n").unwrap();
+    assert_eq!(config.location(), None);
+    let config: DeviceConfig = toml::from_str("").unwrap();
+    assert_eq!(config.location(), None);
+}
+
+#[test]
+fn test_timestamp_only_location() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+timestamp = 2023-11-02T08:24:21+13:00
+updated = 2023-11-02T08:24:21+13:00
+
+[windows]
+start-recording = "-30m"
+stop-recording = "30m"
+"#,
+    )
+    .unwrap();
+    assert!(!config.has_location());
+    assert_eq!(config.location(), None);
+    let now = chrono::NaiveDate::from_ymd_opt(2023, 11, 2)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    assert_eq!(
+        config.try_next_recording_window(&now),
+        Err(WindowError::MissingLocation)
+    );
+    assert!(!config.time_is_in_recording_window(&now));
+}
+
+#[test]
+fn test_apply_location_update() {
+    // 2024-01-02T03:04:05Z
+    let clock = FixedClock(
+        NaiveDate::from_ymd_opt(2024, 1, 2)
+            .unwrap()
+            .and_hms_opt(3, 4, 5)
+            .unwrap(),
+    );
+    let mut config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let before = config.location().unwrap();
+    config.apply_location_update(
+        LocationUpdate {
+            latitude: Some(-43.5),
+            longitude: Some(172.6),
+            ..Default::default()
+        },
+        &clock,
+    );
+    let after = config.location().unwrap();
+    assert_eq!(after.lat_lng, (-43.5, 172.6));
+    assert_eq!(after.altitude, before.altitude);
+    assert_eq!(after.accuracy, before.accuracy);
+    assert_eq!(after.timestamp, before.timestamp);
+    assert_eq!(after.updated, Some(1_704_164_645_000_000));
+
+    let mut config: DeviceConfig = toml::from_str("").unwrap();
+    config.apply_location_update(
+        LocationUpdate {
+            latitude: Some(-43.5),
+            longitude: Some(172.6),
+            altitude: Some(12.0),
+            accuracy: Some(5.0),
+            timestamp: Some(1_698_866_661_000_000),
+        },
+        &clock,
+    );
+    assert!(config.has_location());
+    assert_eq!(config.location_altitude(), Some(12.0));
+    assert_eq!(config.location_timestamp(), Some(1_698_866_661_000_000));
+}
+
+#[test]
+fn test_clone_with_location() {
+    let template: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let config = template.clone_with_location(LocationSettings {
+        latitude: Some(-43.5),
+        longitude: Some(172.6),
+        altitude: None,
+        timestamp: None,
+        updated: None,
+        accuracy: None,
+        solar_cache_days: None,
+        timezone: None,
+        timestamp_offset_minutes: None,
+        horizon_angle: None,
+    });
+    assert_eq!(config.lat_lng(), (-43.5, 172.6));
+    assert_eq!(config.location_altitude(), None);
+    assert_eq!(config.device_id(), template.device_id());
+    assert_eq!(config.device_name(), template.device_name());
+    assert_eq!(config.recording_window(), template.recording_window());
+    assert_eq!(config.output_dir(), template.output_dir());
+    assert_eq!(template.location_altitude(), Some(103.0));
+}
+
+#[test]
+fn test_implausible_altitude() {
+    let altitude_warnings = |config: &DeviceConfig| {
+        config
+            .validate()
+            .into_iter()
+            .filter(|warning| matches!(warning, ConfigWarning::ImplausibleAltitude { .. }))
+            .collect::<Vec<_>>()
+    };
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(config.location_altitude(), Some(103.0));
+    assert!(altitude_warnings(&config).is_empty());
+
+    // An extra digit on a sea level site
+    let config: DeviceConfig =
+        toml::from_str(&SAMPLE_CONFIG.replace("altitude = 103.0", "altitude = 10300.0")).unwrap();
+    assert_eq!(
+        altitude_warnings(&config),
+        vec![ConfigWarning::ImplausibleAltitude {
+            altitude_m: 10300.0
+        }]
+    );
+    let below_sea_level: DeviceConfig =
+        toml::from_str(&SAMPLE_CONFIG.replace("altitude = 103.0", "altitude = -400.0")).unwrap();
+    assert!(altitude_warnings(&below_sea_level).is_empty());
+}
+
+#[test]
+fn test_location_timestamp_offset() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(config.location_timestamp_offset_minutes(), Some(780));
+    assert_eq!(config.location_timestamp(), Some(1_698_866_661_000_000));
+
+    // The offset is written back, so the local time of the fix isn't lost
+    let written = config.to_toml_string();
+    assert!(written.contains("timestamp = 2023-11-02T08:24:21+13:00"));
+    let reloaded = DeviceConfig::from_toml_str(&written).unwrap();
+    assert_eq!(reloaded.location_timestamp_offset_minutes(), Some(780));
+    assert_eq!(reloaded.location_timestamp(), config.location_timestamp());
+
+    let utc: DeviceConfig = toml::from_str(&SAMPLE_CONFIG.replace(
+        "timestamp = 2023-11-02T08:24:21+13:00",
+        "timestamp = 2023-11-01T19:24:21Z",
+    ))
+    .unwrap();
+    assert_eq!(utc.location_timestamp_offset_minutes(), Some(0));
+    assert_eq!(utc.location_timestamp(), config.location_timestamp());
+}
+
+#[test]
+fn test_timezone_longitude_mismatch() {
+    let timezone_warnings = |latitude: f32, longitude: f32, timezone: &str| {
+        let config: DeviceConfig = toml::from_str(&format!(
+            "[location]\nlatitude = {}\nlongitude = {}\ntimezone = \"{}\"\n",
+            latitude, longitude, timezone
+        ))
+        .unwrap();
+        config
+            .validate()
+            .into_iter()
+            .filter(|warning| matches!(warning, ConfigWarning::TimezoneMismatch { .. }))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        timezone_warnings(-46.6, 172.7, "America/New_York"),
+        vec![ConfigWarning::TimezoneMismatch {
+            timezone: String::from("America/New_York"),
+            longitude: 172.7,
+        }]
+    );
+    assert!(timezone_warnings(-46.6, 172.7, "Pacific/Auckland").is_empty());
+    assert!(timezone_warnings(40.7, -74.0, "America/New_York").is_empty());
+    // Across the date line from its solar time
+    assert!(timezone_warnings(1.9, -157.4, "Pacific/Kiritimati").is_empty());
+    // Far from the timezone's centre, but using it
+    assert!(timezone_warnings(39.5, 76.0, "Asia/Shanghai").is_empty());
+}
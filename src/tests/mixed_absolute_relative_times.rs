@@ -74,3 +74,41 @@ stop-recording = "13:00"
         "Next window should start 1hr before todays sunset"
     );
 }
+
+#[test]
+fn test_relative_start_with_earlier_absolute_stop() {
+    let test_latitude: f64 = -41.0;
+    let test_longitude: f64 = 175.0;
+    let config: DeviceConfig = toml::from_str(&format!(
+        r#"
+[location]
+latitude = {}
+longitude = {}
+
+[windows]
+start-recording = "-1h"
+stop-recording = "02:00"
+"#,
+        test_latitude, test_longitude
+    ))
+    .unwrap();
+    let (_, today_sunset) = sun_times(
+        NaiveDate::from_ymd_opt(2000, 1, 2).unwrap(),
+        test_latitude,
+        test_longitude,
+        0.0,
+    )
+    .unwrap();
+    let expected = (
+        (today_sunset - Duration::hours(1)).naive_utc(),
+        make_date_time_with_offset(2000, 1, 3, 2, 0, NZ_SUMMER_UTC_OFFSET_SECONDS),
+    );
+    // From after last night's window ends until the end of tonight's
+    for hour in [3, 12, 18, 21, 23] {
+        let now = make_date_time_with_offset(2000, 1, 2, hour, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+        let (start, end) = config.next_recording_window(&now);
+        assert_eq!((start, end), expected, "Window at {}:00", hour);
+        assert!(end > start);
+        assert!(end - start < Duration::hours(12));
+    }
+}
@@ -1,9 +1,21 @@
-use crate::DeviceConfig;
+use crate::{ConfigError, ConfigWarning, DeviceConfig, DeviceRegistration, ReloadImpact};
 use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike};
+use std::io::Cursor;
 
 mod absolute_times;
+mod audio_windows;
+mod cron_windows;
+mod detection_mask;
+mod format_duration;
+mod location;
 mod mixed_absolute_relative_times;
+mod multiple_windows;
+mod recording_metadata;
 mod relative_times;
+mod select_window;
+mod serialization;
+mod sun_times;
+mod window_evaluator;
 mod window_parsing;
 
 fn make_time_with_offset(hour: u32, min: u32, offset_seconds: i64) -> NaiveDateTime {
@@ -34,10 +46,7 @@ fn make_date_time_with_offset(
     ) - Duration::seconds(offset_seconds)
 }
 
-#[test]
-fn load_config() {
-    let config: Result<DeviceConfig, _> = toml::from_str(
-        r#"
+const SAMPLE_CONFIG: &str = r#"
 [device]
 id = 1
 group = "test-group"
@@ -66,7 +75,518 @@ activate = true
 [windows]
 start-recording = "12:00"
 stop-recording = "11:00"
-"#,
+"#;
+
+#[test]
+fn load_config() {
+    let config: Result<DeviceConfig, _> = toml::from_str(SAMPLE_CONFIG);
+    assert!(config.is_ok());
+}
+
+#[test]
+fn load_config_from_reader() {
+    let reader = Cursor::new(SAMPLE_CONFIG.as_bytes().to_vec());
+    let config = DeviceConfig::from_reader(reader);
+    assert!(config.is_ok());
+    assert_eq!(config.unwrap(), toml::from_str(SAMPLE_CONFIG).unwrap());
+
+    let reader = Cursor::new(vec![0xff, 0xfe, 0xfd]);
+    assert!(matches!(
+        DeviceConfig::from_reader(reader),
+        Err(ConfigError::Utf8(_))
+    ));
+
+    let reader = Cursor::new(b"[windows".to_vec());
+    assert!(matches!(
+        DeviceConfig::from_reader(reader),
+        Err(ConfigError::Parse(_))
+    ));
+}
+
+#[test]
+fn parse_error_has_location() {
+    let error =
+        DeviceConfig::from_toml_str("[device]\nid = 1\nname = \"unterminated\n").unwrap_err();
+    assert!(matches!(error, ConfigError::Parse(_)));
+    let message = error.to_string();
+    assert!(message.contains("line 3"), "No line in '{}'", message);
+    assert!(message.contains("column"), "No column in '{}'", message);
+}
+
+#[test]
+fn load_config_with_bom_and_crlf() {
+    let config_toml = format!("\u{feff}{}", SAMPLE_CONFIG.replace('\n', "\r\n"));
+    let config = DeviceConfig::from_reader(Cursor::new(config_toml.into_bytes()));
+    assert_eq!(
+        config.unwrap(),
+        DeviceConfig::from_reader(Cursor::new(SAMPLE_CONFIG.as_bytes())).unwrap()
     );
+}
+
+#[test]
+fn load_empty_config() {
+    let config: Result<DeviceConfig, _> = toml::from_str("");
     assert!(config.is_ok());
+    let config = config.unwrap();
+    assert!(!config.is_registered());
+    assert!(!config.has_location());
+    assert!(!config.is_continuous_recorder());
+    assert!(!config.use_low_power_mode());
+    assert_eq!(config.output_dir(), "/var/spool/cptv");
+    assert_eq!(config.recording_settings.min_disk_space_mb, 200);
+    let (start, stop) = config.recording_window();
+    assert_eq!(start.to_config_string(), "-30m");
+    assert_eq!(stop.to_config_string(), "30m");
+}
+
+#[test]
+fn config_parts() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let parts = config.parts();
+    assert_eq!(parts.device_id, Some(config.device_id()));
+    assert_eq!(
+        parts.device_name.as_deref().map(str::as_bytes),
+        Some(config.device_name())
+    );
+    assert_eq!(parts.group.as_deref(), Some("test-group"));
+    assert_eq!(parts.server.as_deref(), Some("test-url"));
+    assert_eq!(
+        (parts.latitude.unwrap(), parts.longitude.unwrap()),
+        config.lat_lng()
+    );
+    assert_eq!(parts.altitude, config.location_altitude());
+    assert_eq!(parts.accuracy, config.location_accuracy());
+    assert_eq!(parts.location_timestamp, config.location_timestamp());
+    assert_eq!(parts.location_updated, config.location_updated());
+    assert_eq!(parts.recording_window, config.recording_window());
+    assert_eq!(parts.output_dir, config.output_dir());
+    assert_eq!(parts.continuous_recorder, config.is_continuous_recorder());
+    assert_eq!(parts.low_power_mode, config.use_low_power_mode());
+    assert_eq!(parts.motion_only, config.motion_only());
+    assert_eq!(
+        parts.thermal_throttler_enabled,
+        config.thermal_throttler_enabled()
+    );
+    assert_eq!(config.clone().into_parts(), parts);
+
+    let parts = toml::from_str::<DeviceConfig>("").unwrap().parts();
+    assert_eq!(parts.device_id, None);
+    assert_eq!(parts.latitude, None);
+    assert_eq!(parts.location_timestamp, None);
+}
+
+#[test]
+fn server_url() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[device]
+server = "https://api.cacophony.org.nz"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.server_url().unwrap().as_str(),
+        "https://api.cacophony.org.nz/"
+    );
+    assert!(config.validate().is_empty());
+
+    // The sample config's server isn't a URL
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(config.server_url(), None);
+    assert!(matches!(
+        config.validate().as_slice(),
+        [ConfigWarning::InvalidServerUrl { server, .. }] if server == "test-url"
+    ));
+
+    let config: DeviceConfig = toml::from_str("[device]\nserver = \"ftp://example.com\"").unwrap();
+    assert_eq!(config.server_url(), None);
+    assert_eq!(config.validate().len(), 1);
+}
+
+#[test]
+fn load_config_from_dir() {
+    let dir = std::env::temp_dir().join(format!("tc2-config-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("10-location.toml"),
+        "[location]\nlatitude = -46.60101\nlongitude = 172.71303\n\n[windows]\nstart-recording = \"09:00\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("20-windows.toml"),
+        "[windows]\nstart-recording = \"12:00\"\nstop-recording = \"11:00\"\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("README"), "not a config").unwrap();
+    let config = DeviceConfig::load_from_dir(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let config = config.unwrap();
+    assert!(config.has_location());
+    assert_eq!(config.lat_lng(), (-46.60101, 172.71303));
+    let (start, stop) = config.recording_window();
+    assert_eq!(start.to_config_string(), "12:00");
+    assert_eq!(stop.to_config_string(), "11:00");
+}
+
+#[test]
+fn migrate_legacy_config() {
+    let legacy: toml::Value = toml::from_str(
+        r#"
+[thermal-recorder]
+use-sunrise-sunset = false
+max-secs = 300
+"#,
+    )
+    .unwrap();
+    let migrated = DeviceConfig::migrate(legacy);
+    let recorder = migrated.get("thermal-recorder").unwrap();
+    assert_eq!(recorder.get("use-sunrise-sunset"), None);
+    assert_eq!(recorder.get("max-secs"), None);
+    assert_eq!(
+        recorder.get("constant-recorder"),
+        Some(&toml::Value::Boolean(true))
+    );
+    let config: DeviceConfig = migrated.try_into().unwrap();
+    assert!(config.is_continuous_recorder());
+    assert_eq!(config.schema_version(), 2);
+
+    // With windows set, the legacy flag is just dropped
+    let config = DeviceConfig::from_reader(Cursor::new(SAMPLE_CONFIG.as_bytes())).unwrap();
+    assert!(!config.is_continuous_recorder());
+    assert_eq!(config.recording_window().0.to_config_string(), "12:00");
+
+    let config: DeviceConfig = toml::from_str("").unwrap();
+    assert_eq!(config.schema_version(), 2);
+
+    // A config from newer software keeps its version rather than being stamped as current
+    let newer: toml::Value = toml::from_str("[meta]\nschema-version = 3\n").unwrap();
+    let migrated = DeviceConfig::migrate(newer.clone());
+    assert_eq!(migrated, newer);
+    let config: DeviceConfig = migrated.try_into().unwrap();
+    assert_eq!(config.schema_version(), 3);
+}
+
+#[test]
+fn output_dir_trailing_slash() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let with_slash: DeviceConfig =
+        toml::from_str(&SAMPLE_CONFIG.replace("/var/spool/cptv", "/var/spool/cptv/")).unwrap();
+    assert_eq!(with_slash.output_dir(), "/var/spool/cptv");
+    assert_eq!(with_slash, config);
+
+    let root: DeviceConfig = toml::from_str("[thermal-recorder]\noutput-dir = \"/\"\n").unwrap();
+    assert_eq!(root.output_dir(), "/");
+}
+
+#[test]
+fn defaulted_fields() {
+    let minimal = "[thermal-recorder]\noutput-dir = \"/var/spool/cptv\"\nmotion-only = true\n";
+    let config = DeviceConfig::from_toml_str(minimal).unwrap();
+    assert_eq!(
+        config.defaulted_fields(),
+        vec![
+            "windows",
+            "thermal-recorder.constant-recorder",
+            "thermal-recorder.use-low-power-mode",
+            "thermal-recorder.warmup",
+            "thermal-recorder.pre-trigger",
+            "thermal-recorder.quality",
+            "thermal-recorder.sample-probability",
+            "thermal-recorder.min-disk-space-mb",
+            "thermal-recorder.disk-policy",
+            "thermal-recorder.mask-regions",
+            "thermal-throttler.activate",
+        ]
+    );
+}
+
+#[test]
+fn clone_with_registration() {
+    let template: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let config = template.clone_with_registration(DeviceRegistration {
+        id: Some(1234),
+        group: Some(String::from("site-a")),
+        name: Some(String::from("site-a-02")),
+        server: None,
+        firmware_max_id: None,
+    });
+    assert_eq!(config.device_id(), 1234);
+    assert_eq!(config.device_name(), b"site-a-02");
+    assert_eq!(config.location(), template.location());
+    assert_eq!(config.recording_window(), template.recording_window());
+    assert_eq!(config.output_dir(), template.output_dir());
+    assert_eq!(config.server_url(), None);
+}
+
+#[test]
+fn reload_impact() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(config.reload_impact(&config), ReloadImpact::LiveSafe);
+
+    let new_windows: DeviceConfig = toml::from_str(
+        &SAMPLE_CONFIG.replace("start-recording = \"12:00\"", "start-recording = \"13:00\""),
+    )
+    .unwrap();
+    assert_ne!(new_windows, config);
+    assert_eq!(config.reload_impact(&new_windows), ReloadImpact::LiveSafe);
+
+    let new_output_dir: DeviceConfig =
+        toml::from_str(&SAMPLE_CONFIG.replace("/var/spool/cptv", "/mnt/sd/cptv")).unwrap();
+    assert_eq!(
+        config.reload_impact(&new_output_dir),
+        ReloadImpact::DeferUntilWindowEnd
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn load_config_from_json() {
+    let json = r#"{
+    "device": { "id": 1, "group": "test-group", "name": "test-name", "server": "test-url" },
+    "thermal-recorder": {
+        "use-sunrise-sunset": false,
+        "max-secs": 300,
+        "min-disk-space-mb": 200,
+        "min-secs": 5,
+        "output-dir": "/var/spool/cptv",
+        "preview-secs": 1
+    },
+    "location": {
+        "accuracy": 0.0,
+        "altitude": 103.0,
+        "latitude": -46.60101,
+        "longitude": 172.71303,
+        "timestamp": "2023-11-02T08:24:21+13:00",
+        "updated": "2023-11-02T08:24:21+13:00"
+    },
+    "thermal-throttler": { "activate": true },
+    "windows": { "start-recording": "12:00", "stop-recording": "11:00" }
+}"#;
+    let from_json = DeviceConfig::from_json_str(json).unwrap();
+    let from_toml = DeviceConfig::from_toml_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(from_json, from_toml);
+    assert_eq!(from_json.location_timestamp_offset_minutes(), Some(780));
+    assert_eq!(from_json.recording_window().0.to_config_string(), "12:00");
+
+    // Relative times and mask regions go through the same string and array parsing
+    let json = r#"{
+    "windows": { "start-recording": "civil_dusk-30m", "stop-recording": "PT1H" },
+    "thermal-recorder": { "mask-regions": { "corner": [[0.0, 0.0], [0.5, 0.0], [0.0, 0.5]] } }
+}"#;
+    let toml = r#"
+[windows]
+start-recording = "civil_dusk-30m"
+stop-recording = "PT1H"
+
+[thermal-recorder.mask-regions]
+corner = [[0.0, 0.0], [0.5, 0.0], [0.0, 0.5]]
+"#;
+    assert_eq!(
+        DeviceConfig::from_json_str(json).unwrap(),
+        DeviceConfig::from_toml_str(toml).unwrap()
+    );
+
+    assert!(matches!(
+        DeviceConfig::from_json_str("{ \"windows\": "),
+        Err(ConfigError::Json(_))
+    ));
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn load_config_from_yaml() {
+    let yaml = r#"
+device: { id: 1, group: test-group, name: test-name, server: test-url }
+thermal-recorder:
+  use-sunrise-sunset: false
+  max-secs: 300
+  min-disk-space-mb: 200
+  min-secs: 5
+  output-dir: /var/spool/cptv
+  preview-secs: 1
+location:
+  accuracy: 0.0
+  altitude: 103.0
+  latitude: -46.60101
+  longitude: 172.71303
+  timestamp: "2023-11-02T08:24:21+13:00"
+  updated: "2023-11-02T08:24:21+13:00"
+thermal-throttler: { activate: true }
+windows: { start-recording: "12:00", stop-recording: "11:00" }
+"#;
+    let from_yaml = DeviceConfig::from_yaml_str(yaml).unwrap();
+    let from_toml = DeviceConfig::from_toml_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(from_yaml, from_toml);
+
+    let yaml = r#"
+windows: { start-recording: civil_dusk-30m, stop-recording: PT1H }
+thermal-recorder:
+  mask-regions: { corner: [[0.0, 0.0], [0.5, 0.0], [0.0, 0.5]] }
+"#;
+    let toml = r#"
+[windows]
+start-recording = "civil_dusk-30m"
+stop-recording = "PT1H"
+
+[thermal-recorder.mask-regions]
+corner = [[0.0, 0.0], [0.5, 0.0], [0.0, 0.5]]
+"#;
+    assert_eq!(
+        DeviceConfig::from_yaml_str(yaml).unwrap(),
+        DeviceConfig::from_toml_str(toml).unwrap()
+    );
+
+    assert!(matches!(
+        DeviceConfig::from_yaml_str("windows: ["),
+        Err(ConfigError::Yaml(_))
+    ));
+}
+
+#[test]
+fn sample_probability() {
+    let with_probability = |probability: &str| -> DeviceConfig {
+        toml::from_str(&format!(
+            "[thermal-recorder]\nsample-probability = {}\n",
+            probability
+        ))
+        .unwrap()
+    };
+    let default: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(default.sample_probability(), 1.0);
+    assert!((0..10_000).all(|minute| default.should_sample(minute)));
+
+    let never = with_probability("0.0");
+    assert!((0..10_000).all(|minute| !never.should_sample(minute)));
+    let always = with_probability("1.0");
+    assert!((0..10_000).all(|minute| always.should_sample(minute)));
+
+    // The same minute always gives the same answer, and roughly a quarter of minutes sample
+    let quarter = with_probability("0.25");
+    assert_eq!(quarter.sample_probability(), 0.25);
+    let minute = 28_401_234;
+    assert_eq!(quarter.should_sample(minute), quarter.should_sample(minute));
+    let sampled = (0..10_000)
+        .filter(|minute| quarter.should_sample(*minute))
+        .count();
+    assert!((2_300..2_700).contains(&sampled), "Sampled {}", sampled);
+
+    let reloaded: DeviceConfig = toml::from_str(&quarter.to_toml_string()).unwrap();
+    assert_eq!(reloaded.sample_probability(), 0.25);
+
+    for invalid in ["-0.1", "1.5"] {
+        assert!(toml::from_str::<DeviceConfig>(&format!(
+            "[thermal-recorder]\nsample-probability = {}\n",
+            invalid
+        ))
+        .is_err());
+    }
+}
+
+#[test]
+fn effective_output_dir() {
+    let dir = std::env::temp_dir().join(format!("tc2-config-output-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // A directory can't be created under a file, even when running as root
+    let blocker = dir.join("not-a-dir");
+    std::fs::write(&blocker, "").unwrap();
+    let primary = blocker.join("cptv");
+    let backup = dir.join("backup/cptv");
+    let config: DeviceConfig = toml::from_str(&format!(
+        "[thermal-recorder]\noutput-dir = \"{}\"\nbackup-dir = \"{}/\"\n",
+        primary.display(),
+        backup.display()
+    ))
+    .unwrap();
+    let chosen = config.effective_output_dir();
+    let backup_created = backup.is_dir();
+    let backup_empty = std::fs::read_dir(&backup).is_ok_and(|mut entries| entries.next().is_none());
+
+    let usable = dir.join("primary");
+    let config: DeviceConfig = toml::from_str(&format!(
+        "[thermal-recorder]\noutput-dir = \"{}\"\nbackup-dir = \"{}\"\n",
+        usable.display(),
+        backup.display()
+    ))
+    .unwrap();
+    let primary_chosen = config.effective_output_dir();
+    let primary_created = usable.is_dir();
+
+    let no_backup: DeviceConfig = toml::from_str(&format!(
+        "[thermal-recorder]\noutput-dir = \"{}\"\n",
+        primary.display()
+    ))
+    .unwrap();
+    let no_backup = no_backup.effective_output_dir();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(chosen.unwrap(), backup);
+    assert!(backup_created);
+    // The write probe is cleaned up
+    assert!(backup_empty);
+    assert_eq!(primary_chosen.unwrap(), usable);
+    assert!(primary_created);
+    assert!(no_backup.is_err());
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn protobuf_round_trip() {
+    let config = DeviceConfig::from_toml_str(&format!(
+        "{}{}",
+        SAMPLE_CONFIG.replace("[windows]", "[[windows]]"),
+        r#"
+[[windows]]
+cron = "*/15 6-8 * * *"
+use-low-power-mode = true
+
+[thermal-recorder.mask-regions]
+corner = [[0.0, 0.0], [0.5, 0.0], [0.5, 0.5], [0.0, 0.5]]
+
+[audio-recorder]
+start-recording = "civil_dawn"
+stop-recording = "sunrise+1h"
+
+[power]
+min-voltage = 3.4
+
+[contact]
+name = "Field team"
+email = "field@example.com"
+"#
+    ))
+    .unwrap();
+    let bytes = config.to_protobuf();
+    let decoded = DeviceConfig::from_protobuf(&bytes).unwrap();
+    assert_eq!(decoded, config);
+    assert_eq!(decoded.to_protobuf(), bytes);
+    assert_eq!(decoded.location_timestamp_offset_minutes(), Some(780));
+    assert_eq!(decoded.contact_name(), Some("Field team"));
+    assert_eq!(decoded.contact_email(), Some("field@example.com"));
+    assert_eq!(decoded.contact_phone(), None);
+    assert_eq!(
+        decoded.recording_settings.mask_regions,
+        config.recording_settings.mask_regions
+    );
+
+    let defaults: DeviceConfig = toml::from_str("").unwrap();
+    assert_eq!(
+        DeviceConfig::from_protobuf(&defaults.to_protobuf()).unwrap(),
+        defaults
+    );
+    assert_eq!(DeviceConfig::from_protobuf(&[]).unwrap(), defaults);
+
+    assert!(matches!(
+        DeviceConfig::from_protobuf(&[0xff, 0xff]),
+        Err(ConfigError::Protobuf(_))
+    ));
+
+    // A well-formed message can still carry a value the config can't hold
+    use prost::Message;
+    let mut message = crate::protobuf::DeviceConfig::decode(&bytes[..]).unwrap();
+    message.location.as_mut().unwrap().timestamp_offset_minutes = Some(24 * 60);
+    assert!(matches!(
+        DeviceConfig::from_protobuf(&message.encode_to_vec()),
+        Err(ConfigError::Decode(_))
+    ));
 }
@@ -0,0 +1,193 @@
+use crate::clock::FixedClock;
+use crate::sun_times::sun_times;
+use crate::tests::{make_time_with_offset, NZ_SUMMER_UTC_OFFSET_SECONDS};
+use crate::{ConfigWarning, DeviceConfig};
+use chrono::{Duration, NaiveDate};
+
+#[test]
+fn test_multiple_windows() {
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[[windows]]
+start-recording = "05:00"
+stop-recording = "07:00"
+
+[[windows]]
+start-recording = "19:00"
+stop-recording = "21:00"
+"#,
+    );
+    assert!(config.is_ok());
+    let config = config.unwrap();
+    assert_eq!(config.recording_windows.len(), 2);
+    assert_eq!(config.recording_window().0.to_config_string(), "05:00");
+
+    let now = make_time_with_offset(6, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(config.time_is_in_recording_window(&now));
+    let now = make_time_with_offset(20, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(config.time_is_in_recording_window(&now));
+    let now = make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(!config.time_is_in_recording_window(&now));
+    assert_eq!(
+        config.next_recording_window(&now),
+        (
+            make_time_with_offset(19, 0, NZ_SUMMER_UTC_OFFSET_SECONDS),
+            make_time_with_offset(21, 0, NZ_SUMMER_UTC_OFFSET_SECONDS)
+        )
+    );
+    assert_eq!(config.validate_window_overlaps(&FixedClock(now)), Ok(()));
+
+    // At midday the previous window is this morning's, not yesterday evening's
+    assert_eq!(
+        config.previous_recording_window(&now),
+        Some((
+            make_time_with_offset(5, 0, NZ_SUMMER_UTC_OFFSET_SECONDS),
+            make_time_with_offset(7, 0, NZ_SUMMER_UTC_OFFSET_SECONDS)
+        ))
+    );
+}
+
+#[test]
+fn test_overlapping_windows() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[[windows]]
+start-recording = "20:00"
+stop-recording = "02:00"
+
+[[windows]]
+start-recording = "12:00"
+stop-recording = "14:00"
+
+[[windows]]
+start-recording = "01:00"
+stop-recording = "03:00"
+"#,
+    )
+    .unwrap();
+    let clock = FixedClock(make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS));
+    assert_eq!(config.validate_window_overlaps(&clock), Err(vec![(0, 2)]));
+    assert_eq!(
+        config.window_overlaps_on(NaiveDate::from_ymd_opt(2024, 1, 2).unwrap()),
+        Err(vec![(0, 2)])
+    );
+}
+
+#[test]
+fn test_window_overlaps_depend_on_date() {
+    // Sunset is around 17:00 in June and 20:50 in December
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -41.0
+longitude = 175.0
+
+[[windows]]
+start-recording = "sunset-1h"
+stop-recording = "sunset"
+
+[[windows]]
+start-recording = "20:00"
+stop-recording = "20:30"
+"#,
+    )
+    .unwrap();
+    let at = |month| {
+        let date = NaiveDate::from_ymd_opt(2024, month, 15).unwrap();
+        FixedClock(date.and_hms_opt(0, 0, 0).unwrap())
+    };
+    assert_eq!(config.validate_window_overlaps(&at(6)), Ok(()));
+    assert_eq!(config.validate_window_overlaps(&at(12)), Err(vec![(0, 1)]));
+    let overlaps = |month| {
+        config
+            .validate_with_clock(&at(month))
+            .into_iter()
+            .filter(|warning| matches!(warning, ConfigWarning::OverlappingWindows { .. }))
+            .count()
+    };
+    assert_eq!(overlaps(6), 0);
+    assert_eq!(overlaps(12), 1);
+}
+
+#[test]
+fn test_empty_windows() {
+    let config: Result<DeviceConfig, _> = toml::from_str("windows = []");
+    assert!(config.is_err());
+}
+
+#[test]
+fn test_effective_low_power_mode() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[thermal-recorder]
+use-low-power-mode = true
+
+[[windows]]
+start-recording = "06:00"
+stop-recording = "08:00"
+use-low-power-mode = false
+
+[[windows]]
+start-recording = "18:00"
+stop-recording = "20:00"
+"#,
+    )
+    .unwrap();
+    assert!(config.use_low_power_mode());
+    let morning = make_time_with_offset(7, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let evening = make_time_with_offset(19, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let midday = make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(!config.effective_low_power_mode(&morning));
+    assert!(config.effective_low_power_mode(&evening));
+    assert!(config.effective_low_power_mode(&midday));
+
+    let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+    assert_eq!(reloaded, config);
+}
+
+#[test]
+fn test_crepuscular_windows() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -41.0
+longitude = 175.0
+
+[windows]
+mode = "crepuscular"
+offset = "1h"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.recording_windows.len(), 2);
+    assert_eq!(config.recording_window().0.to_config_string(), "sunrise-1h");
+
+    let date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+    let clock = FixedClock(date.and_hms_opt(0, 0, 0).unwrap());
+    assert_eq!(config.validate_window_overlaps(&clock), Ok(()));
+    let (sunrise, sunset) = sun_times(date, -41.0, 175.0, 0.0).unwrap();
+    let (sunrise, sunset) = (sunrise.naive_utc(), sunset.naive_utc());
+    let hour = Duration::hours(1);
+    let midday = make_time_with_offset(12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert!(!config.time_is_in_recording_window(&midday));
+    assert_eq!(
+        config.next_recording_window(&midday),
+        (sunset - hour, sunset + hour)
+    );
+    assert_eq!(
+        config.next_recording_window(&(sunrise - hour - Duration::minutes(5))),
+        (sunrise - hour, sunrise + hour)
+    );
+    assert!(config.time_is_in_recording_window(&(sunrise + Duration::minutes(30))));
+    assert!(!config.time_is_in_recording_window(&(sunrise + Duration::minutes(90))));
+    assert!(config.time_is_in_recording_window(&(sunset - Duration::minutes(30))));
+    assert!(!config.time_is_in_recording_window(&(sunset + Duration::minutes(90))));
+
+    let missing_offset: Result<DeviceConfig, _> =
+        toml::from_str("[windows]\nmode = \"crepuscular\"\n");
+    assert!(missing_offset.is_err());
+    let with_start: Result<DeviceConfig, _> = toml::from_str(
+        "[windows]\nmode = \"crepuscular\"\noffset = \"1h\"\nstart-recording = \"-30m\"\n",
+    );
+    assert!(with_start.is_err());
+}
@@ -0,0 +1,50 @@
+use crate::recording_metadata::RECORDING_METADATA_VERSION;
+use crate::tests::SAMPLE_CONFIG;
+use crate::DeviceConfig;
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::Cursor;
+
+#[test]
+fn test_recording_metadata() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let metadata = config.recording_metadata();
+    assert!(metadata.is_some());
+    let metadata = metadata.unwrap();
+    assert_eq!(metadata.device_id, 1);
+    assert_eq!(metadata.device_name, "test-name");
+    assert_eq!(metadata.group, "test-group");
+    assert_eq!(metadata.latitude, Some(-46.60101));
+    assert_eq!(metadata.longitude, Some(172.71303));
+
+    let bytes = metadata.to_bytes();
+    let mut cursor = Cursor::new(&bytes[..]);
+    assert_eq!(cursor.read_u8().unwrap(), RECORDING_METADATA_VERSION);
+    assert_eq!(cursor.read_u32::<LittleEndian>().unwrap(), 1);
+    // Skip the name and group
+    cursor.set_position(5 + 1 + 9 + 1 + 10);
+    assert_eq!(cursor.read_u8().unwrap(), 1);
+    assert_eq!(cursor.read_f32::<LittleEndian>().unwrap(), -46.60101);
+    assert_eq!(cursor.read_f32::<LittleEndian>().unwrap(), 172.71303);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_recording_metadata_json() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let metadata = config.recording_metadata().unwrap();
+    let json: serde_json::Value = serde_json::from_str(&metadata.to_json()).unwrap();
+    assert_eq!(json["device_id"], 1);
+    assert_eq!(json["group"], "test-group");
+}
+
+#[test]
+fn test_recording_metadata_unregistered() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[device]
+name = "test-name"
+"#,
+    )
+    .unwrap();
+    assert!(config.recording_metadata().is_none());
+}
@@ -1,12 +1,16 @@
-use crate::sun_times::sun_times;
+use crate::clock::FixedClock;
+use crate::sun_times::{sun_times, try_civil_twilight};
 use crate::tests::{
     make_date_time_with_offset, CAYMAN_ISLANDS_UTC_OFFSET_SECONDS,
     NORFOLK_ISLAND_SUMMER_UTC_OFFSET_SECONDS, NORFOLK_ISLAND_WINTER_UTC_OFFSET_SECONDS,
     NZ_SUMMER_UTC_OFFSET_SECONDS, NZ_WINTER_UTC_OFFSET_SECONDS, UK_SUMMER_UTC_OFFSET_SECONDS,
     UK_WINTER_UTC_OFFSET_SECONDS,
 };
-use crate::DeviceConfig;
-use chrono::{Duration, NaiveDate};
+use crate::{
+    DeviceConfig, InactiveReason, LocationUpdate, RecordingContext, SolarAnchor, SolarAnchors,
+    WindowError,
+};
+use chrono::{Duration, NaiveDate, NaiveTime};
 
 #[test]
 fn test_sunrise_sunset_nz() {
@@ -139,13 +143,13 @@ stop-recording = "2h"
     assert!(config.is_ok());
     let config = config.unwrap();
     assert!(config
-        .recording_window
+        .primary_window()
         .start_recording
         .relative_time_seconds
         .is_some());
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .relative_time_seconds
             .unwrap(),
@@ -154,13 +158,13 @@ stop-recording = "2h"
     );
 
     assert!(config
-        .recording_window
+        .primary_window()
         .stop_recording
         .relative_time_seconds
         .is_some());
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .relative_time_seconds
             .unwrap(),
@@ -239,3 +243,714 @@ stop-recording = "2h"
         next_window_end
     );
 }
+
+#[test]
+fn test_previous_relative_recording_window() {
+    let test_latitude: f64 = -41.0;
+    let test_longitude: f64 = 175.0;
+    let config: DeviceConfig = toml::from_str(&format!(
+        r#"
+[location]
+latitude = {}
+longitude = {}
+
+[windows]
+start-recording = "-1h"
+stop-recording = "2h"
+"#,
+        test_latitude, test_longitude
+    ))
+    .unwrap();
+    let (_, yesterday_sunset) = sun_times(
+        NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
+        test_latitude,
+        test_longitude,
+        0.0,
+    )
+    .unwrap();
+    let (today_sunrise, _) = sun_times(
+        NaiveDate::from_ymd_opt(2000, 1, 2).unwrap(),
+        test_latitude,
+        test_longitude,
+        0.0,
+    )
+    .unwrap();
+    let now = make_date_time_with_offset(2000, 1, 2, 12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(
+        config.previous_recording_window(&now),
+        Some((
+            (yesterday_sunset - Duration::hours(1)).naive_utc(),
+            (today_sunrise + Duration::hours(2)).naive_utc()
+        ))
+    );
+
+    // Without a location there are no sun times to find it from
+    let no_location: DeviceConfig =
+        toml::from_str("[windows]\nstart-recording = \"-1h\"\nstop-recording = \"2h\"\n").unwrap();
+    assert_eq!(no_location.previous_recording_window(&now), None);
+}
+
+#[test]
+fn test_materialize_schedule() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -41.0
+longitude = 175.0
+
+[windows]
+start-recording = "-1h"
+stop-recording = "2h"
+"#,
+    )
+    .unwrap();
+    let windows = config.materialize_schedule(NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(), 7);
+    assert_eq!(windows.len(), 7);
+    for (start, end) in &windows {
+        let duration = *end - *start;
+        assert!(
+            duration > Duration::hours(9) && duration < Duration::hours(13),
+            "Window {} - {} should be a summer night plus offsets",
+            start,
+            end
+        );
+    }
+    for pair in windows.windows(2) {
+        let spacing = pair[1].0 - pair[0].0;
+        assert!((spacing - Duration::days(1)).num_minutes().abs() < 5);
+    }
+}
+
+#[test]
+fn test_sunrise_start_sunset_stop_anchors() {
+    let test_latitude: f64 = -41.0;
+    let test_longitude: f64 = 175.0;
+    let config: DeviceConfig = toml::from_str(&format!(
+        r#"
+[location]
+latitude = {}
+longitude = {}
+
+[windows]
+start-recording = "-1h"
+start-anchor = "sunrise"
+stop-recording = "30m"
+stop-anchor = "sunset"
+"#,
+        test_latitude, test_longitude
+    ))
+    .unwrap();
+    let sun_times_on = |day: u32| {
+        let (sunrise, sunset) = sun_times(
+            NaiveDate::from_ymd_opt(2000, 1, day).unwrap(),
+            test_latitude,
+            test_longitude,
+            0.0,
+        )
+        .unwrap();
+        (
+            (sunrise - Duration::hours(1)).naive_utc(),
+            (sunset + Duration::minutes(30)).naive_utc(),
+        )
+    };
+
+    let before_dawn = make_date_time_with_offset(2000, 1, 2, 3, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(config.next_recording_window(&before_dawn), sun_times_on(2));
+    assert!(!config.time_is_in_recording_window(&before_dawn));
+
+    let midday = make_date_time_with_offset(2000, 1, 2, 12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(config.next_recording_window(&midday), sun_times_on(2));
+    assert!(config.time_is_in_recording_window(&midday));
+
+    let night = make_date_time_with_offset(2000, 1, 2, 22, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(config.next_recording_window(&night), sun_times_on(3));
+    assert!(!config.time_is_in_recording_window(&night));
+}
+
+#[test]
+fn test_invalid_anchor() {
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[windows]
+start-anchor = "noon"
+"#,
+    );
+    assert!(config.is_err());
+}
+
+fn polar_config(polar_day: &str, polar_night: &str) -> DeviceConfig {
+    toml::from_str(&format!(
+        r#"
+[location]
+latitude = 80.0
+longitude = 15.0
+
+[windows]
+start-recording = "-30m"
+stop-recording = "+30m"
+polar-day = "{}"
+polar-night = "{}"
+"#,
+        polar_day, polar_night
+    ))
+    .unwrap()
+}
+
+#[test]
+fn test_polar_day_and_night_defaults() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = 80.0
+longitude = 15.0
+"#,
+    )
+    .unwrap();
+    let midsummer = make_date_time_with_offset(2023, 6, 21, 12, 0, 0);
+    let midwinter = make_date_time_with_offset(2023, 12, 21, 12, 0, 0);
+    assert!(!config.time_is_in_recording_window(&midsummer));
+    assert!(config.time_is_in_recording_window(&midwinter));
+}
+
+#[test]
+fn test_polar_day_and_night_behaviour() {
+    let midsummer = make_date_time_with_offset(2023, 6, 21, 12, 0, 0);
+    let midwinter = make_date_time_with_offset(2023, 12, 21, 12, 0, 0);
+
+    let config = polar_config("record", "off");
+    assert!(config.time_is_in_recording_window(&midsummer));
+    assert!(!config.time_is_in_recording_window(&midwinter));
+
+    let config = polar_config("off", "record");
+    assert!(!config.time_is_in_recording_window(&midsummer));
+    assert!(config.time_is_in_recording_window(&midwinter));
+
+    assert!(toml::from_str::<DeviceConfig>(
+        r#"
+[windows]
+polar-day = "sometimes"
+"#
+    )
+    .is_err());
+}
+
+#[test]
+fn test_approx_local_schedule() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -41.0
+longitude = 175.0
+
+[windows]
+start-recording = "-30m"
+stop-recording = "30m"
+"#,
+    )
+    .unwrap();
+    // The local offset comes from the system timezone at the time the test is run, so only check
+    // the times are a plausible summer evening and morning.
+    let (start, end) = config
+        .approx_local_schedule(NaiveDate::from_ymd_opt(2000, 1, 1).unwrap())
+        .unwrap();
+    assert!(
+        start > NaiveTime::from_hms_opt(19, 0, 0).unwrap()
+            && start < NaiveTime::from_hms_opt(21, 30, 0).unwrap(),
+        "Start {} should be in the evening",
+        start
+    );
+    assert!(
+        end > NaiveTime::from_hms_opt(5, 0, 0).unwrap()
+            && end < NaiveTime::from_hms_opt(7, 30, 0).unwrap(),
+        "End {} should be in the morning",
+        end
+    );
+
+    let polar = polar_config("off", "record");
+    assert_eq!(
+        polar.approx_local_schedule(NaiveDate::from_ymd_opt(2023, 6, 21).unwrap()),
+        None
+    );
+}
+
+#[test]
+fn test_large_negative_start_offset() {
+    let test_latitude: f64 = -41.0;
+    let test_longitude: f64 = 175.0;
+    let config: DeviceConfig = toml::from_str(&format!(
+        r#"
+[location]
+latitude = {}
+longitude = {}
+
+[windows]
+start-recording = "-5h"
+stop-recording = "+2h"
+"#,
+        test_latitude, test_longitude
+    ))
+    .unwrap();
+    let sun_times_on = |month: u32, day: u32| {
+        let (sunrise, sunset) = sun_times(
+            NaiveDate::from_ymd_opt(2000, month, day).unwrap(),
+            test_latitude,
+            test_longitude,
+            0.0,
+        )
+        .unwrap();
+        (sunrise.naive_utc(), sunset.naive_utc())
+    };
+
+    // Winter, when the start is pushed well back into the afternoon
+    let now = make_date_time_with_offset(2000, 6, 20, 10, 0, NZ_WINTER_UTC_OFFSET_SECONDS);
+    let (start, end) = config.next_recording_window(&now);
+    assert!(start < end, "Window {} - {} should be positive", start, end);
+    assert_eq!(start, sun_times_on(6, 20).1 - Duration::hours(5));
+    assert_eq!(end, sun_times_on(6, 21).0 + Duration::hours(2));
+    assert!(!config.time_is_in_recording_window(&now));
+
+    // Just after the window ends, the next one is tonight's
+    let after = end + Duration::minutes(1);
+    let (next_start, next_end) = config.next_recording_window(&after);
+    assert!(next_start > end && next_start < next_end);
+    assert_eq!(next_start, sun_times_on(6, 21).1 - Duration::hours(5));
+}
+
+#[test]
+fn test_civil_twilight_window() {
+    let test_latitude: f64 = -41.0;
+    let test_longitude: f64 = 175.0;
+    let config: DeviceConfig = toml::from_str(&format!(
+        r#"
+[location]
+latitude = {}
+longitude = {}
+
+[windows]
+start-recording = "civil_dusk-30m"
+stop-recording = "civil_dawn"
+"#,
+        test_latitude, test_longitude
+    ))
+    .unwrap();
+    let twilight_on = |day: u32| {
+        let (dawn, dusk) = try_civil_twilight(
+            NaiveDate::from_ymd_opt(2000, 1, day).unwrap(),
+            test_latitude,
+            test_longitude,
+            0.0,
+        )
+        .unwrap();
+        (dawn.naive_utc(), dusk.naive_utc())
+    };
+    let (sunrise, sunset) = sun_times(
+        NaiveDate::from_ymd_opt(2000, 1, 2).unwrap(),
+        test_latitude,
+        test_longitude,
+        0.0,
+    )
+    .unwrap();
+    // Civil twilight is around half an hour either side of sunrise and sunset at this latitude
+    assert!(twilight_on(2).0 < sunrise.naive_utc() - Duration::minutes(20));
+    assert!(twilight_on(2).1 > sunset.naive_utc() + Duration::minutes(20));
+
+    let midday = make_date_time_with_offset(2000, 1, 2, 12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let (start, end) = config.next_recording_window(&midday);
+    assert_eq!(start, twilight_on(2).1 - Duration::minutes(30));
+    assert_eq!(end, twilight_on(3).0);
+    assert!(!config.time_is_in_recording_window(&midday));
+    assert!(config.time_is_in_recording_window(&(start + Duration::minutes(1))));
+}
+
+#[test]
+fn test_to_ical() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[device]
+id = 7
+name = "tc2, north"
+
+[location]
+latitude = -41.0
+longitude = 175.0
+"#,
+    )
+    .unwrap();
+    let from = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+    let ical = config.to_ical(from, 3);
+    let windows = config.materialize_schedule(from, 3);
+    assert_eq!(windows.len(), 3);
+    assert!(ical.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(ical.ends_with("END:VCALENDAR\r\n"));
+    assert_eq!(ical.matches("BEGIN:VEVENT\r\n").count(), 3);
+    assert_eq!(ical.matches("END:VEVENT\r\n").count(), 3);
+    assert_eq!(
+        ical.matches("SUMMARY:tc2\\, north recording\r\n").count(),
+        3
+    );
+    for (start, end) in windows {
+        assert!(ical.contains(&format!("DTSTART:{}\r\n", start.format("%Y%m%dT%H%M%SZ"))));
+        assert!(ical.contains(&format!("DTEND:{}\r\n", end.format("%Y%m%dT%H%M%SZ"))));
+    }
+}
+
+#[test]
+fn test_is_daytime() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -41.0
+longitude = 175.0
+"#,
+    )
+    .unwrap();
+    let noon = make_date_time_with_offset(2000, 1, 2, 12, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let midnight = make_date_time_with_offset(2000, 1, 2, 23, 30, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    let early = make_date_time_with_offset(2000, 1, 2, 3, 0, NZ_SUMMER_UTC_OFFSET_SECONDS);
+    assert_eq!(config.is_daytime(&noon), Some(true));
+    assert_eq!(config.is_daytime(&midnight), Some(false));
+    assert_eq!(config.is_daytime(&early), Some(false));
+
+    let polar = polar_config("off", "record");
+    let june = make_date_time_with_offset(2023, 6, 21, 0, 0, 0);
+    let december = make_date_time_with_offset(2023, 12, 21, 12, 0, 0);
+    assert_eq!(polar.is_daytime(&june), Some(true));
+    assert_eq!(polar.is_daytime(&december), Some(false));
+
+    let config: DeviceConfig = toml::from_str("").unwrap();
+    assert_eq!(config.is_daytime(&noon), None);
+}
+
+#[test]
+fn test_resolve_for_date() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -46.60101
+longitude = 172.71303
+
+[windows]
+start-recording = "-1h"
+stop-recording = "06:00"
+"#,
+    )
+    .unwrap();
+    let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+    let location = config.location();
+    let (start, stop) = config.recording_window();
+
+    // 06:00 NZDT is 17:00 UTC the day before
+    assert_eq!(
+        stop.resolve_for(date, location.as_ref(), NZ_SUMMER_UTC_OFFSET_SECONDS as i32),
+        NaiveDate::from_ymd_opt(2024, 1, 9)
+            .unwrap()
+            .and_hms_opt(17, 0, 0)
+    );
+    // Absolute times don't need a location
+    assert_eq!(stop.resolve_for(date, None, 0), date.and_hms_opt(6, 0, 0));
+
+    let (_, sunset) = sun_times(date, -46.60101, 172.71303, 0.0).unwrap();
+    let resolved = start
+        .resolve_for(date, location.as_ref(), NZ_SUMMER_UTC_OFFSET_SECONDS as i32)
+        .unwrap();
+    assert!(
+        (resolved - (sunset.naive_utc() - Duration::hours(1)))
+            .num_seconds()
+            .abs()
+            <= 1
+    );
+    assert_eq!(start.resolve_for(date, None, 0), None);
+}
+
+#[test]
+fn test_stable_solar_schedule_near_equator() {
+    let location = "[location]\nlatitude = 1.35\nlongitude = 103.82\n";
+    let config: DeviceConfig = toml::from_str(location).unwrap();
+    let cached: DeviceConfig =
+        toml::from_str(&format!("{}solar-cache-days = 7\n", location)).unwrap();
+    assert!(!config.uses_stable_solar_schedule());
+    assert!(cached.uses_stable_solar_schedule());
+
+    for day in 1..=14 {
+        let now = NaiveDate::from_ymd_opt(2024, 2, day)
+            .unwrap()
+            .and_hms_opt(4, 0, 0)
+            .unwrap();
+        let (start, end) = config.next_recording_window(&now);
+        let (cached_start, cached_end) = cached.next_recording_window(&now);
+        assert!((cached_start - start).num_seconds().abs() < 5 * 60);
+        assert!((cached_end - end).num_seconds().abs() < 5 * 60);
+    }
+
+    // Too far from the equator to reuse sun times
+    let config: DeviceConfig =
+        toml::from_str("[location]\nlatitude = -46.6\nlongitude = 172.7\nsolar-cache-days = 7\n")
+            .unwrap();
+    assert!(!config.uses_stable_solar_schedule());
+}
+
+#[test]
+fn test_stable_solar_schedule_after_location_update() {
+    let mut config: DeviceConfig =
+        toml::from_str("[location]\nlatitude = 1.35\nlongitude = 103.82\nsolar-cache-days = 7\n")
+            .unwrap();
+    let now = NaiveDate::from_ymd_opt(2024, 2, 1)
+        .unwrap()
+        .and_hms_opt(4, 0, 0)
+        .unwrap();
+    let before = config.next_recording_window(&now);
+
+    // Two hours of longitude west, so the sun times are two hours later
+    config.apply_location_update(
+        LocationUpdate {
+            longitude: Some(73.82),
+            ..LocationUpdate::default()
+        },
+        &FixedClock(now),
+    );
+    let moved: DeviceConfig =
+        toml::from_str("[location]\nlatitude = 1.35\nlongitude = 73.82\nsolar-cache-days = 7\n")
+            .unwrap();
+    let after = config.next_recording_window(&now);
+    assert_eq!(after, moved.next_recording_window(&now));
+    assert_ne!(after, before);
+
+    // Blocks far from the one in use aren't kept
+    let calculations = config.sun_times_cache.calculations();
+    config.next_recording_window(&now);
+    assert_eq!(config.sun_times_cache.calculations(), calculations);
+    config.next_recording_window(&(now + Duration::days(60)));
+    let calculations = config.sun_times_cache.calculations();
+    config.next_recording_window(&now);
+    assert!(config.sun_times_cache.calculations() > calculations);
+}
+
+#[test]
+fn test_window_relative_to_midnight() {
+    // No location is needed, and equal offsets aren't treated as a continuous recorder
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[windows]
+start-recording = "midnight+3h"
+stop-recording = "midnight+5h"
+"#,
+    )
+    .unwrap();
+    assert!(!config.is_continuous_recorder());
+    let (start, stop) = config.recording_window();
+    let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+    let offset = NZ_SUMMER_UTC_OFFSET_SECONDS;
+    assert_eq!(
+        start.resolve_for(date, None, offset as i32),
+        Some(make_date_time_with_offset(2024, 1, 10, 3, 0, offset))
+    );
+
+    // Before, during and after tonight's window (in local time)
+    for (hour, expected_day) in [(1, 10), (4, 10), (6, 11), (14, 11), (23, 11)] {
+        let now = make_date_time_with_offset(2024, 1, 10, hour, 0, offset);
+        assert_eq!(
+            config.next_recording_window(&now),
+            (
+                make_date_time_with_offset(2024, 1, expected_day, 3, 0, offset),
+                make_date_time_with_offset(2024, 1, expected_day, 5, 0, offset)
+            ),
+            "At {:02}:00",
+            hour
+        );
+    }
+    assert_eq!(stop.resolve_for(date, None, 0), date.and_hms_opt(5, 0, 0));
+
+    // A window from sunset until 3am the next morning
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -46.60101
+longitude = 172.71303
+
+[windows]
+start-recording = "sunset"
+stop-recording = "midnight+3h"
+"#,
+    )
+    .unwrap();
+    let now = make_date_time_with_offset(2024, 1, 10, 12, 0, offset);
+    let (start, end) = config.next_recording_window(&now);
+    let (_, sunset) = sun_times(date, -46.60101, 172.71303, 0.0).unwrap();
+    assert_eq!(start, sunset.naive_utc());
+    assert_eq!(end, make_date_time_with_offset(2024, 1, 11, 3, 0, offset));
+}
+
+#[test]
+fn test_debug_solar_anchors() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -46.60101
+longitude = 172.71303
+"#,
+    )
+    .unwrap();
+    let offset = NZ_SUMMER_UTC_OFFSET_SECONDS;
+    let now = make_date_time_with_offset(2024, 1, 10, 12, 0, offset);
+    let date = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+    let (_, sunset) = sun_times(date, -46.60101, 172.71303, 0.0).unwrap();
+    let (sunrise, _) = sun_times(date + Duration::days(1), -46.60101, 172.71303, 0.0).unwrap();
+    assert_eq!(
+        config.debug_solar_anchors(&now),
+        Some(SolarAnchors {
+            start: Some((SolarAnchor::Sunset, sunset.naive_utc())),
+            end: Some((SolarAnchor::Sunrise, sunrise.naive_utc())),
+        })
+    );
+    let (start, end) = config.next_recording_window(&now);
+    assert_eq!(start, sunset.naive_utc() - Duration::minutes(30));
+    assert_eq!(end, sunrise.naive_utc() + Duration::minutes(30));
+
+    // Only the relative start has an anchor
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -46.60101
+longitude = 172.71303
+
+[windows]
+start-recording = "sunset"
+stop-recording = "03:00"
+"#,
+    )
+    .unwrap();
+    let anchors = config.debug_solar_anchors(&now).unwrap();
+    let (start, _) = config.next_recording_window(&now);
+    assert_eq!(anchors.start, Some((SolarAnchor::Sunset, start)));
+    assert_eq!(anchors.end, None);
+
+    let absolute: DeviceConfig =
+        toml::from_str("[windows]\nstart-recording = \"22:00\"\nstop-recording = \"06:00\"\n")
+            .unwrap();
+    assert_eq!(absolute.debug_solar_anchors(&now), None);
+}
+
+#[test]
+fn test_horizon_angle() {
+    let with_horizon = |horizon: &str| -> DeviceConfig {
+        toml::from_str(&format!(
+            "[location]\nlatitude = -46.60101\nlongitude = 172.71303\n{}\n\n[windows]\nstart-recording = \"sunset\"\nstop-recording = \"sunrise\"\n",
+            horizon
+        ))
+        .unwrap()
+    };
+    let offset = NZ_SUMMER_UTC_OFFSET_SECONDS;
+    let now = make_date_time_with_offset(2024, 1, 10, 12, 0, offset);
+    let (flat_sunset, flat_sunrise) = with_horizon("").next_recording_window(&now);
+    let (zero_sunset, _) = with_horizon("horizon-angle = 0.0").next_recording_window(&now);
+    assert_eq!(zero_sunset, flat_sunset);
+
+    let valley = with_horizon("horizon-angle = 10.0");
+    let (valley_sunset, valley_sunrise) = valley.next_recording_window(&now);
+    assert!(valley_sunset < flat_sunset - Duration::minutes(30));
+    assert!(valley_sunrise > flat_sunrise + Duration::minutes(30));
+    // The sun is behind the ridge between the two sunsets
+    let between = flat_sunset - Duration::minutes(10);
+    assert_eq!(with_horizon("").is_daytime(&between), Some(true));
+    assert_eq!(valley.is_daytime(&between), Some(false));
+
+    let reloaded: DeviceConfig = toml::from_str(&valley.to_toml_string()).unwrap();
+    assert_eq!(reloaded.location().unwrap().horizon_angle, Some(10.0));
+}
+
+#[test]
+fn test_empty_relative_window() {
+    // Ends an hour before it starts, so never opens
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -41.0
+longitude = 175.0
+
+[windows]
+start-recording = "sunset+1h"
+stop-recording = "sunset-1h"
+"#,
+    )
+    .unwrap();
+    let now = make_date_time_with_offset(2000, 6, 20, 10, 0, NZ_WINTER_UTC_OFFSET_SECONDS);
+    assert_eq!(
+        config.try_next_recording_window(&now),
+        Err(WindowError::EmptyWindow)
+    );
+    assert!(!config.time_is_in_recording_window(&now));
+    assert_eq!(
+        config.inactive_reason(&now, &RecordingContext::default()),
+        Some(InactiveReason::EmptyWindow)
+    );
+
+    // Days at 60°N in January are shorter than the 12 hours the offsets take off them
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = 60.0
+longitude = 10.0
+
+[windows]
+start-anchor = "sunrise"
+stop-anchor = "sunset"
+start-recording = "6h"
+stop-recording = "-6h"
+"#,
+    )
+    .unwrap();
+    let now = make_date_time_with_offset(2000, 1, 15, 12, 0, 3600);
+    assert_eq!(
+        config.try_next_recording_window(&now),
+        Err(WindowError::EmptyWindow)
+    );
+    assert!(!config.time_is_in_recording_window(&now));
+    // By midsummer the days are long enough
+    let now = make_date_time_with_offset(2000, 6, 21, 12, 0, 7200);
+    assert!(config.time_is_in_recording_window(&now));
+}
+
+#[test]
+fn test_first_day_after_polar_night() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = 70.0
+longitude = 25.0
+
+[windows]
+start-recording = "-30m"
+stop-recording = "30m"
+"#,
+    )
+    .unwrap();
+    // The sun first rises on the 18th, so the days before it have no sunset to start from
+    let date = |day| NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+    assert_eq!(sun_times(date(17), 70.0, 25.0, 0.0), None);
+    let (_, sunset) = sun_times(date(18), 70.0, 25.0, 0.0).unwrap();
+    let (sunrise, next_sunset) = sun_times(date(19), 70.0, 25.0, 0.0).unwrap();
+    let tonight = (
+        sunset.naive_utc() - Duration::minutes(30),
+        sunrise.naive_utc() + Duration::minutes(30),
+    );
+
+    // Still in the polar night until the first window starts, which records by default
+    let before_sunset = date(18).and_hms_opt(9, 0, 0).unwrap();
+    assert!(config.try_next_recording_window(&before_sunset).is_err());
+    assert!(config.time_is_in_recording_window(&before_sunset));
+
+    // After that today's window is used, rather than the polar night on the days before
+    let evening = date(18).and_hms_opt(12, 0, 0).unwrap();
+    assert_eq!(config.try_next_recording_window(&evening), Ok(tonight));
+
+    // And the next morning the window has ended, so it doesn't keep recording all day
+    let between_windows = tonight.1 + Duration::minutes(5);
+    assert!(between_windows < next_sunset.naive_utc() - Duration::minutes(30));
+    assert_eq!(
+        config
+            .try_next_recording_window(&between_windows)
+            .map(|(start, _)| start),
+        Ok(next_sunset.naive_utc() - Duration::minutes(30))
+    );
+    assert!(!config.time_is_in_recording_window(&between_windows));
+}
@@ -0,0 +1,115 @@
+use crate::sun_times::SunTimesError;
+use crate::{select_window, SolarAnchor, WindowError};
+use chrono::{NaiveDate, NaiveDateTime};
+
+const DEFAULT_ANCHORS: (SolarAnchor, SolarAnchor) = (SolarAnchor::Sunset, SolarAnchor::Sunrise);
+
+fn at(day: u32, hour: u32, min: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2024, 3, day)
+        .unwrap()
+        .and_hms_opt(hour, min, 0)
+        .unwrap()
+}
+
+// Sunrise at 06:00 UTC and sunset at 18:00 UTC every day
+fn equinox_sun(anchor: SolarAnchor, date: NaiveDate) -> Result<NaiveDateTime, WindowError> {
+    let hour = if anchor.is_morning() { 6 } else { 18 };
+    Ok(date.and_hms_opt(hour, 0, 0).unwrap())
+}
+
+#[test]
+fn test_select_relative_window() {
+    let select = |now| {
+        select_window(
+            &now,
+            -30 * 60,
+            30 * 60,
+            false,
+            false,
+            DEFAULT_ANCHORS,
+            equinox_sun,
+        )
+    };
+    // Before tonight's window
+    assert_eq!(select(at(10, 12, 0)), Ok((at(10, 17, 30), at(11, 6, 30))));
+    // During last night's window
+    assert_eq!(select(at(10, 3, 0)), Ok((at(9, 17, 30), at(10, 6, 30))));
+    // The end is inclusive
+    assert_eq!(select(at(10, 6, 30)), Ok((at(9, 17, 30), at(10, 6, 30))));
+    assert_eq!(select(at(10, 6, 31)), Ok((at(10, 17, 30), at(11, 6, 30))));
+}
+
+#[test]
+fn test_select_mixed_window() {
+    // Absolute start at midnight UTC, until an hour after sunrise
+    let (_, end) = select_window(
+        &at(10, 12, 0),
+        0,
+        60 * 60,
+        true,
+        false,
+        DEFAULT_ANCHORS,
+        equinox_sun,
+    )
+    .unwrap();
+    assert_eq!(end, at(11, 7, 0));
+}
+
+#[test]
+fn test_select_window_with_changing_day_length() {
+    // Days getting an hour longer each day, so sunrise is earlier and sunset later
+    let sun = |anchor: SolarAnchor, date: NaiveDate| {
+        let shift = date.signed_duration_since(NaiveDate::from_ymd_opt(2024, 3, 10).unwrap());
+        let minutes = shift.num_days() * 30;
+        let time = equinox_sun(anchor, date)?;
+        Ok(if anchor.is_morning() {
+            time - chrono::Duration::minutes(minutes)
+        } else {
+            time + chrono::Duration::minutes(minutes)
+        })
+    };
+    let window = select_window(&at(10, 12, 0), 0, 0, false, false, DEFAULT_ANCHORS, sun);
+    assert_eq!(window, Ok((at(10, 18, 0), at(11, 5, 30))));
+}
+
+#[test]
+fn test_select_window_without_sun_times() {
+    let polar_night = |_: SolarAnchor, _: NaiveDate| Err(SunTimesError::PolarNight.into());
+    let window = select_window(
+        &at(10, 12, 0),
+        0,
+        0,
+        false,
+        false,
+        DEFAULT_ANCHORS,
+        polar_night,
+    );
+    assert_eq!(window, Err(WindowError::Sun(SunTimesError::PolarNight)));
+
+    // Absolute windows never need the sun times
+    let window = select_window(
+        &at(10, 12, 0),
+        8 * 60 * 60,
+        16 * 60 * 60,
+        true,
+        true,
+        DEFAULT_ANCHORS,
+        polar_night,
+    );
+    assert_eq!(window, Ok((at(10, 8, 0), at(10, 16, 0))));
+}
+
+#[test]
+fn test_select_absolute_offset_of_a_whole_day() {
+    // An offset of 86400 is midnight, and shouldn't panic
+    let window = select_window(
+        &at(10, 12, 0),
+        86_400,
+        6 * 60 * 60,
+        true,
+        true,
+        DEFAULT_ANCHORS,
+        equinox_sun,
+    );
+    assert_eq!(window, Ok((at(11, 0, 0), at(11, 6, 0))));
+}
@@ -0,0 +1,470 @@
+use crate::binary_field::{BinaryError, BinaryField};
+use crate::clock::FixedClock;
+use crate::tests::SAMPLE_CONFIG;
+use crate::{
+    timezone_offset_seconds, ConfigWarning, DeviceConfig, DiskPolicy, RecordingQuality,
+    TimezoneBasis,
+};
+use byteorder::{LittleEndian, ReadBytesExt};
+use chrono::NaiveDate;
+use std::io::Cursor;
+
+// Byte offset of the start recording abs/rel flag in the output of `write_to_slice`
+const START_RECORDING_OFFSET: u64 = 31;
+
+#[test]
+fn test_absolute_offset_seconds_resolution() {
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[device]
+id = 1
+group = "test-group"
+name = "test-name"
+
+[location]
+latitude = -46.60101
+longitude = 172.71303
+
+[windows]
+start-recording = "06:00:30"
+stop-recording = "18:00"
+"#,
+    );
+    assert!(config.is_ok());
+    let config = config.unwrap();
+    let start = &config.primary_window().start_recording;
+    assert_eq!(start.to_config_string(), "06:00:30");
+
+    let mut output = [0u8; 128];
+    config.write_to_slice(&mut output).unwrap();
+    let mut cursor = Cursor::new(&output[..]);
+    cursor.set_position(START_RECORDING_OFFSET);
+    assert_eq!(cursor.read_u8().unwrap(), 1, "Start should be absolute");
+    assert_eq!(
+        cursor.read_i32::<LittleEndian>().unwrap(),
+        (21630 - timezone_offset_seconds()).rem_euclid(86_400),
+        "06:00:30 local should be serialized to the second"
+    );
+}
+
+#[test]
+fn test_describe_serialization() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let description = config.describe_serialization();
+    let (range, name, value) = &description[0];
+    assert_eq!(*range, 0..4);
+    assert_eq!(name, "device_id");
+    assert_eq!(value, "1");
+
+    let (range, _, value) = description
+        .iter()
+        .find(|(_, name, _)| name == "device_name")
+        .unwrap();
+    assert_eq!(value, "\"test-name\"");
+
+    let mut output = [0u8; 128];
+    config.write_to_slice(&mut output).unwrap();
+    assert_eq!(&output[range.clone()], b"test-name");
+    let (last, _, _) = description.last().unwrap();
+    assert!(output[last.end..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn test_motion_only() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert!(!config.motion_only());
+
+    let config: DeviceConfig = toml::from_str(&SAMPLE_CONFIG.replace(
+        "[thermal-recorder]",
+        "[thermal-recorder]\nmotion-only = true",
+    ))
+    .unwrap();
+    assert!(config.motion_only());
+    let mut output = [0u8; 128];
+    config.write_to_slice(&mut output).unwrap();
+    let (range, _, _) = config
+        .describe_serialization()
+        .into_iter()
+        .find(|(_, name, _)| name == "motion_only")
+        .unwrap();
+    assert_eq!(&output[range], &[1]);
+
+    let fields = DeviceConfig::read_from_slice(&output).unwrap();
+    assert_eq!(
+        fields.iter().find(|(name, _)| *name == "motion_only"),
+        Some(&("motion_only", BinaryField::U8(1)))
+    );
+
+    let config: Result<DeviceConfig, _> = toml::from_str(
+        r#"
+[thermal-recorder]
+motion-only = true
+constant-recorder = true
+"#,
+    );
+    assert!(config.is_err());
+}
+
+#[test]
+fn test_read_from_slice_truncated() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let mut output = [0u8; 128];
+    config.write_to_slice(&mut output).unwrap();
+    let (range, _, _) = config.describe_serialization().pop().unwrap();
+    assert_eq!(
+        DeviceConfig::read_from_slice(&output[0..range.start]),
+        Err(BinaryError::Truncated)
+    );
+}
+
+#[test]
+fn test_thermal_throttler_round_trip() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert!(config.thermal_throttler_enabled());
+
+    let written = config.to_toml_string();
+    let reloaded: DeviceConfig = toml::from_str(&written).unwrap();
+    assert_eq!(reloaded, config);
+
+    let mut output = [0u8; 128];
+    config.write_to_slice(&mut output).unwrap();
+    let fields = DeviceConfig::read_from_slice(&output).unwrap();
+    assert_eq!(
+        fields
+            .iter()
+            .find(|(name, _)| *name == "thermal_throttler_enabled"),
+        Some(&("thermal_throttler_enabled", BinaryField::U8(1)))
+    );
+
+    let config: DeviceConfig =
+        toml::from_str(&SAMPLE_CONFIG.replace("activate = true", "activate = false")).unwrap();
+    assert!(!config.thermal_throttler_enabled());
+    let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+    assert!(!reloaded.thermal_throttler_enabled());
+}
+
+#[test]
+fn test_contact_round_trip() {
+    let config: DeviceConfig = toml::from_str(&format!(
+        "{SAMPLE_CONFIG}\n[contact]\nname = \"Field Team\"\nemail = \"field@example.com\"\n"
+    ))
+    .unwrap();
+    assert_eq!(config.contact_name(), Some("Field Team"));
+    assert_eq!(config.contact_email(), Some("field@example.com"));
+    assert_eq!(config.contact_phone(), None);
+
+    let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+    assert_eq!(reloaded, config);
+    assert_eq!(reloaded.contact_name(), Some("Field Team"));
+
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(config.contact_name(), None);
+    assert!(!config.to_toml_string().contains("[contact]"));
+}
+
+#[test]
+fn test_empty_config_round_trip() {
+    let config: DeviceConfig = toml::from_str("").unwrap();
+    let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+    assert_eq!(reloaded, config);
+}
+
+#[test]
+fn test_checksum() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let len = config.serialized_len();
+    let mut output = vec![0u8; len];
+    config.write_to_slice(&mut output).unwrap();
+    let (range, name, _) = config.describe_serialization().pop().unwrap();
+    assert_eq!(name, "crc32");
+    assert_eq!(range, len - 4..len);
+    assert!(DeviceConfig::read_from_slice(&output).is_ok());
+
+    for index in [0, 5, len / 2, len - 5, len - 1] {
+        let mut corrupted = output.clone();
+        corrupted[index] ^= 0x10;
+        assert!(
+            matches!(
+                DeviceConfig::read_from_slice(&corrupted),
+                Err(BinaryError::ChecksumMismatch { .. }) | Err(BinaryError::Truncated)
+            ),
+            "Flipping a bit in byte {} should be rejected",
+            index
+        );
+    }
+    // Outside of the device name length, a flipped bit is always a checksum mismatch
+    let mut corrupted = output.clone();
+    corrupted[len / 2] ^= 0x01;
+    assert!(matches!(
+        DeviceConfig::read_from_slice(&corrupted),
+        Err(BinaryError::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_device_id_fits() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert!(config.device_id_fits(1));
+    assert!(config.device_id_fits(u32::MAX));
+
+    let config: DeviceConfig =
+        toml::from_str(&SAMPLE_CONFIG.replace("id = 1", "id = 70000\nfirmware-max-id = 65535"))
+            .unwrap();
+    assert_eq!(config.device_id(), 70000);
+    assert!(!config.device_id_fits(65535));
+    assert!(config.device_id_fits(70000));
+    assert!(config
+        .validate()
+        .contains(&ConfigWarning::DeviceIdTooLarge {
+            id: 70000,
+            max: 65535
+        }));
+    let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+    assert_eq!(reloaded, config);
+
+    let unregistered: DeviceConfig = toml::from_str("").unwrap();
+    assert!(unregistered.device_id_fits(0));
+}
+
+#[test]
+fn test_long_device_name() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert!(config.name_fits_binary());
+
+    let long_name = "n".repeat(70);
+    let config: DeviceConfig = toml::from_str(
+        &SAMPLE_CONFIG.replace("name = \"test-name\"", &format!("name = \"{}\"", long_name)),
+    )
+    .unwrap();
+    assert!(!config.name_fits_binary());
+    assert!(config
+        .validate()
+        .contains(&ConfigWarning::DeviceNameTooLong { length: 70 }));
+
+    let fields = {
+        let mut output = vec![0u8; config.serialized_len()];
+        config.write_to_slice(&mut output).unwrap();
+        DeviceConfig::read_from_slice(&output).unwrap()
+    };
+    assert!(fields.contains(&("device_name", BinaryField::Bytes(vec![b'n'; 63]))));
+}
+
+#[test]
+fn test_warmup_serialized() {
+    let config: DeviceConfig = toml::from_str(
+        &SAMPLE_CONFIG.replace("[thermal-recorder]", "[thermal-recorder]\nwarmup = \"90s\""),
+    )
+    .unwrap();
+    let mut output = vec![0u8; config.serialized_len()];
+    config.write_to_slice(&mut output).unwrap();
+    let fields = DeviceConfig::read_from_slice(&output).unwrap();
+    assert!(fields.contains(&("warmup_seconds", BinaryField::U32(90))));
+}
+
+#[test]
+fn test_pre_trigger() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(config.pre_trigger_secs(), 0);
+
+    let with_pre_trigger = |value: &str| {
+        toml::from_str::<DeviceConfig>(&SAMPLE_CONFIG.replace(
+            "[thermal-recorder]",
+            &format!("[thermal-recorder]\npre-trigger = \"{}\"", value),
+        ))
+    };
+    let config = with_pre_trigger("3s").unwrap();
+    assert_eq!(config.pre_trigger_secs(), 3);
+    let mut output = vec![0u8; config.serialized_len()];
+    config.write_to_slice(&mut output).unwrap();
+    let fields = DeviceConfig::read_from_slice(&output).unwrap();
+    assert!(fields.contains(&("pre_trigger_secs", BinaryField::U16(3))));
+    let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+    assert_eq!(reloaded.pre_trigger_secs(), 3);
+
+    assert_eq!(with_pre_trigger("30s").unwrap().pre_trigger_secs(), 30);
+    assert!(with_pre_trigger("31s").is_err());
+    assert!(with_pre_trigger("1m").is_err());
+    assert!(with_pre_trigger("-3s").is_err());
+}
+
+#[test]
+fn test_fits_in_buffer() {
+    let config: DeviceConfig = toml::from_str(&SAMPLE_CONFIG.replace(
+        "[thermal-recorder]",
+        r#"[thermal-recorder.mask-regions]
+left = [[0.0, 0.0], [0.3, 0.0], [0.3, 1.0], [0.0, 1.0]]
+right = [[0.7, 0.0], [1.0, 0.0], [1.0, 1.0], [0.7, 1.0]]
+middle = { outer = [[0.3, 0.3], [0.7, 0.3], [0.7, 0.7], [0.3, 0.7]], holes = [[[0.4, 0.4], [0.6, 0.4], [0.6, 0.6], [0.4, 0.6]]] }
+
+[thermal-recorder]"#,
+    ))
+    .unwrap();
+    let needed = config.serialized_len();
+    assert!(config.fits_in(4096));
+    assert!(config.fits_in(needed));
+    assert!(!config.fits_in(needed - 1));
+    assert!(!config.fits_in(16));
+
+    let mut output = vec![0u8; 16];
+    assert_eq!(
+        config.write_to_slice(&mut output),
+        Err(BinaryError::BufferTooSmall { needed })
+    );
+    let mut output = vec![0u8; needed];
+    assert_eq!(config.write_to_slice(&mut output), Ok(()));
+}
+
+#[test]
+fn test_timezone_basis_round_trip() {
+    let relative_config = SAMPLE_CONFIG
+        .replace("\"12:00\"", "\"-30m\"")
+        .replace("\"11:00\"", "\"30m\"");
+    let configured_config = SAMPLE_CONFIG.replace(
+        "[location]\n",
+        "[location]\ntimezone = \"Pacific/Auckland\"\n",
+    );
+    for (config_toml, basis) in [
+        (SAMPLE_CONFIG, TimezoneBasis::SystemLocal),
+        (relative_config.as_str(), TimezoneBasis::Utc),
+        (configured_config.as_str(), TimezoneBasis::ConfiguredIana),
+    ] {
+        let config: DeviceConfig = toml::from_str(config_toml).unwrap();
+        assert_eq!(config.timezone_basis(), basis);
+
+        let mut output = vec![0u8; config.serialized_len()];
+        config.write_to_slice(&mut output).unwrap();
+        let fields = DeviceConfig::read_from_slice(&output).unwrap();
+        let written = fields
+            .iter()
+            .find_map(|(name, field)| match (*name, field) {
+                ("timezone_basis", BinaryField::U8(basis)) => Some(*basis),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(TimezoneBasis::try_from(written), Ok(basis));
+    }
+    assert_eq!(TimezoneBasis::try_from(3), Err(3));
+}
+
+#[test]
+fn test_recording_quality() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(config.quality(), RecordingQuality::High);
+
+    for (quality, expected, byte) in [
+        ("high", RecordingQuality::High, 2),
+        ("medium", RecordingQuality::Medium, 1),
+        ("low", RecordingQuality::Low, 0),
+    ] {
+        let config: DeviceConfig = toml::from_str(&SAMPLE_CONFIG.replace(
+            "[thermal-recorder]",
+            &format!("[thermal-recorder]\nquality = \"{}\"", quality),
+        ))
+        .unwrap();
+        assert_eq!(config.quality(), expected);
+
+        let mut output = vec![0u8; config.serialized_len()];
+        config.write_to_slice(&mut output).unwrap();
+        let fields = DeviceConfig::read_from_slice(&output).unwrap();
+        assert!(fields.contains(&("quality", BinaryField::U8(byte))));
+
+        let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+        assert_eq!(reloaded.quality(), expected);
+    }
+
+    let config: Result<DeviceConfig, _> = toml::from_str(&SAMPLE_CONFIG.replace(
+        "[thermal-recorder]",
+        "[thermal-recorder]\nquality = \"ultra\"",
+    ));
+    let error = config.unwrap_err().to_string();
+    assert!(error.contains("unknown variant `ultra`"), "{}", error);
+}
+
+#[test]
+fn test_disk_policy() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    assert_eq!(config.disk_policy(), DiskPolicy::Stop);
+
+    for (policy, expected, byte) in [
+        ("stop", DiskPolicy::Stop, 0),
+        ("rotate", DiskPolicy::Rotate, 1),
+    ] {
+        let config: DeviceConfig = toml::from_str(&SAMPLE_CONFIG.replace(
+            "[thermal-recorder]",
+            &format!("[thermal-recorder]\ndisk-policy = \"{}\"", policy),
+        ))
+        .unwrap();
+        assert_eq!(config.disk_policy(), expected);
+
+        let fields = DeviceConfig::read_from_slice(&config.write_to_vec()).unwrap();
+        assert!(fields.contains(&("disk_policy", BinaryField::U8(byte))));
+
+        let reloaded: DeviceConfig = toml::from_str(&config.to_toml_string()).unwrap();
+        assert_eq!(reloaded.disk_policy(), expected);
+    }
+
+    let config: Result<DeviceConfig, _> = toml::from_str(&SAMPLE_CONFIG.replace(
+        "[thermal-recorder]",
+        "[thermal-recorder]\ndisk-policy = \"delete\"",
+    ));
+    let error = config.unwrap_err().to_string();
+    assert!(error.contains("unknown variant `delete`"), "{}", error);
+}
+
+#[test]
+fn test_matches_binary() {
+    let config: DeviceConfig = toml::from_str(SAMPLE_CONFIG).unwrap();
+    let bytes = config.write_to_vec();
+    assert_eq!(bytes.len(), config.serialized_len());
+    assert!(config.matches_binary(&bytes));
+
+    // Settings that aren't sent to the firmware don't matter
+    let other_output_dir: DeviceConfig =
+        toml::from_str(&SAMPLE_CONFIG.replace("/var/spool/cptv", "/mnt/sd/cptv")).unwrap();
+    assert!(other_output_dir.matches_binary(&bytes));
+
+    // A different config, a tampered byte, or a truncated region don't match
+    let other_device: DeviceConfig =
+        toml::from_str(&SAMPLE_CONFIG.replace("id = 1", "id = 2")).unwrap();
+    assert!(!other_device.matches_binary(&bytes));
+    let mut tampered = bytes.clone();
+    tampered[0] ^= 0x01;
+    assert!(!config.matches_binary(&tampered));
+    assert!(!config.matches_binary(&bytes[..bytes.len() - 1]));
+}
+
+#[test]
+fn test_binary_offsets_use_the_clock() {
+    let config: DeviceConfig = toml::from_str(&SAMPLE_CONFIG.replace(
+        "[location]\n",
+        "[location]\ntimezone = \"America/New_York\"\n",
+    ))
+    .unwrap();
+    let at = |month| {
+        FixedClock(
+            NaiveDate::from_ymd_opt(2024, month, 15)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap(),
+        )
+    };
+    let start_offset = |clock: &FixedClock| {
+        let mut output = vec![0u8; config.serialized_len()];
+        config
+            .write_to_slice_with_clock(&mut output, clock)
+            .unwrap();
+        let mut cursor = Cursor::new(output);
+        cursor.set_position(START_RECORDING_OFFSET + 1);
+        cursor.read_i32::<LittleEndian>().unwrap()
+    };
+    // 12:00 is 17:00 UTC in winter (EST) and 16:00 UTC in summer (EDT)
+    assert_eq!(start_offset(&at(1)), 17 * 60 * 60);
+    assert_eq!(start_offset(&at(7)), 16 * 60 * 60);
+
+    let mut winter = vec![0u8; config.serialized_len()];
+    config
+        .write_to_slice_with_clock(&mut winter, &at(1))
+        .unwrap();
+    assert!(config.matches_binary_with_clock(&winter, &at(1)));
+    assert!(!config.matches_binary_with_clock(&winter, &at(7)));
+}
@@ -0,0 +1,34 @@
+use crate::sun_times::{sun_times, try_sun_times, SunTimesError};
+use chrono::NaiveDate;
+
+#[test]
+fn test_sun_times_errors() {
+    let midsummer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+    let midwinter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+    assert!(try_sun_times(midsummer, -41.0, 175.0, 0.0).is_ok());
+    assert_eq!(
+        try_sun_times(midsummer, 80.0, 15.0, 0.0),
+        Err(SunTimesError::PolarDay)
+    );
+    assert_eq!(
+        try_sun_times(midwinter, 80.0, 15.0, 0.0),
+        Err(SunTimesError::PolarNight)
+    );
+    assert_eq!(
+        try_sun_times(midsummer, -80.0, 15.0, 0.0),
+        Err(SunTimesError::PolarNight)
+    );
+    assert_eq!(
+        try_sun_times(midsummer, 91.0, 15.0, 0.0),
+        Err(SunTimesError::InvalidLatitude)
+    );
+    assert_eq!(
+        try_sun_times(midsummer, f64::NAN, 15.0, 0.0),
+        Err(SunTimesError::InvalidLatitude)
+    );
+    assert_eq!(
+        try_sun_times(midsummer, 45.0, 181.0, 0.0),
+        Err(SunTimesError::InvalidLongitude)
+    );
+    assert_eq!(sun_times(midsummer, 80.0, 15.0, 0.0), None);
+}
@@ -0,0 +1,128 @@
+use crate::{timezone_offset_seconds, DeviceConfig};
+use chrono::{Duration, NaiveDate};
+
+fn assert_evaluator_agrees(config: &DeviceConfig, day: NaiveDate) {
+    let evaluator = config.window_evaluator(day);
+    let day_start =
+        day.and_hms_opt(0, 0, 0).unwrap() - Duration::seconds(timezone_offset_seconds() as i64);
+    for minute in (0..24 * 60).step_by(7) {
+        let now = day_start + Duration::minutes(minute) + Duration::seconds(13);
+        assert_eq!(
+            evaluator.contains(&now),
+            config.time_is_in_recording_window(&now),
+            "Evaluator disagrees at {}",
+            now
+        );
+    }
+}
+
+#[test]
+fn test_window_evaluator_relative() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -41.0
+longitude = 175.0
+
+[windows]
+start-recording = "-30m"
+stop-recording = "30m"
+"#,
+    )
+    .unwrap();
+    let day = NaiveDate::from_ymd_opt(2000, 1, 2).unwrap();
+    // Sunrise and sunset are resolved up front: this morning's and tonight's windows
+    let evaluator = config.window_evaluator(day);
+    assert_eq!(evaluator.windows().len(), 2);
+    // Each window is chosen from the candidates around four days, so the day's windows need
+    // four sunsets and four sunrises, each calculated once however many lookups use them
+    assert_eq!(evaluator.sun_time_calculations(), 8);
+    assert_evaluator_agrees(&config, day);
+}
+
+#[test]
+fn test_window_evaluator_multiple_windows() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -41.0
+longitude = 175.0
+
+[[windows]]
+start-recording = "09:10"
+stop-recording = "11:30"
+
+[[windows]]
+cron = "*/20 13-15 * * *"
+
+[[windows]]
+start-recording = "-1h"
+stop-recording = "1h"
+"#,
+    )
+    .unwrap();
+    assert_evaluator_agrees(&config, NaiveDate::from_ymd_opt(2000, 1, 2).unwrap());
+}
+
+#[test]
+fn test_window_evaluator_polar() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = 80.0
+longitude = 15.0
+"#,
+    )
+    .unwrap();
+    assert_evaluator_agrees(&config, NaiveDate::from_ymd_opt(2023, 6, 21).unwrap());
+    assert_evaluator_agrees(&config, NaiveDate::from_ymd_opt(2023, 12, 21).unwrap());
+}
+
+#[test]
+fn test_status_cache() {
+    let config: DeviceConfig = toml::from_str(
+        r#"
+[location]
+latitude = -41.0
+longitude = 175.0
+
+[windows]
+start-recording = "-30m"
+stop-recording = "30m"
+"#,
+    )
+    .unwrap();
+    let mut status = config.status_cache();
+    let day = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+    let day_start =
+        day.and_hms_opt(0, 0, 0).unwrap() - Duration::seconds(timezone_offset_seconds() as i64);
+    let (_, window_end) = config.next_recording_window(&day_start);
+    status.time_is_in_recording_window(&day_start);
+    let calculations = status.sun_time_calculations();
+    // Every few seconds through the night's window, which only needs its sun times once
+    let mut now = day_start;
+    while now <= window_end {
+        assert_eq!(
+            status.time_is_in_recording_window(&now),
+            config.time_is_in_recording_window(&now),
+            "Cached status disagrees at {}",
+            now
+        );
+        now += Duration::seconds(5);
+    }
+    assert_eq!(status.sun_time_calculations(), calculations);
+
+    // Once the window has ended the next one is resolved, and used until it ends
+    let (next_start, next_end) = config.next_recording_window(&now);
+    while now <= next_end {
+        assert_eq!(
+            status.time_is_in_recording_window(&now),
+            config.time_is_in_recording_window(&now),
+            "Cached status disagrees at {}",
+            now
+        );
+        now += Duration::seconds(37);
+    }
+    assert!(next_start > window_end);
+    assert_eq!(status.sun_time_calculations(), 2 * calculations);
+}
@@ -1,4 +1,4 @@
-use crate::DeviceConfig;
+use crate::{ConfigWarning, DeviceConfig, SolarAnchor};
 
 #[test]
 fn test_time_parsing() {
@@ -13,7 +13,7 @@ stop-recording = "08:00"
     let config = config.unwrap();
     assert!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .relative_time_seconds
             .is_none(),
@@ -21,7 +21,7 @@ stop-recording = "08:00"
     );
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .absolute_time
             .as_ref()
@@ -31,7 +31,7 @@ stop-recording = "08:00"
     );
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .absolute_time
             .as_ref()
@@ -41,7 +41,7 @@ stop-recording = "08:00"
     );
     assert!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .relative_time_seconds
             .is_none(),
@@ -49,7 +49,7 @@ stop-recording = "08:00"
     );
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .absolute_time
             .as_ref()
@@ -59,7 +59,7 @@ stop-recording = "08:00"
     );
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .absolute_time
             .as_ref()
@@ -79,7 +79,7 @@ stop-recording = "10:31"
     let config = config.unwrap();
     assert!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .relative_time_seconds
             .is_some(),
@@ -88,7 +88,7 @@ stop-recording = "10:31"
 
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .relative_time_seconds
             .unwrap(),
@@ -98,7 +98,7 @@ stop-recording = "10:31"
 
     assert!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .absolute_time
             .is_some(),
@@ -107,7 +107,7 @@ stop-recording = "10:31"
 
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .absolute_time
             .as_ref()
@@ -117,7 +117,7 @@ stop-recording = "10:31"
     );
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .absolute_time
             .as_ref()
@@ -137,7 +137,7 @@ stop-recording = "3h"
     let config = config.unwrap();
     assert!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .absolute_time
             .is_some(),
@@ -145,7 +145,7 @@ stop-recording = "3h"
     );
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .absolute_time
             .as_ref()
@@ -155,7 +155,7 @@ stop-recording = "3h"
     );
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .absolute_time
             .as_ref()
@@ -165,7 +165,7 @@ stop-recording = "3h"
     );
     assert!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .relative_time_seconds
             .is_some(),
@@ -173,7 +173,7 @@ stop-recording = "3h"
     );
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .relative_time_seconds
             .unwrap(),
@@ -191,13 +191,13 @@ stop-recording = "-1h45m"
     assert!(config.is_ok());
     let config = config.unwrap();
     assert!(config
-        .recording_window
+        .primary_window()
         .start_recording
         .relative_time_seconds
         .is_some());
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .start_recording
             .relative_time_seconds
             .unwrap(),
@@ -205,13 +205,13 @@ stop-recording = "-1h45m"
         "End time should be 1800 (30m) seconds after sunset"
     );
     assert!(config
-        .recording_window
+        .primary_window()
         .stop_recording
         .relative_time_seconds
         .is_some());
     assert_eq!(
         config
-            .recording_window
+            .primary_window()
             .stop_recording
             .relative_time_seconds
             .unwrap(),
@@ -264,3 +264,272 @@ stop-recording = "1:30"
     );
     assert!(config.is_err());
 }
+
+#[test]
+fn test_time_serialization_round_trip() {
+    for (time_str, expected) in [
+        ("-1h30m", "-1h30m"),
+        ("20:10", "20:10"),
+        ("8:00", "08:00"),
+        ("30m", "30m"),
+        ("+3h", "3h"),
+        ("1h0m5s", "1h5s"),
+        ("0", "0m"),
+    ] {
+        let config: Result<DeviceConfig, _> = toml::from_str(&format!(
+            r#"
+[windows]
+start-recording = "{}"
+"#,
+            time_str
+        ));
+        assert!(config.is_ok());
+        let config = config.unwrap();
+        let start = &config.primary_window().start_recording;
+        assert_eq!(start.to_config_string(), expected);
+        assert_eq!(
+            toml::Value::try_from(start).unwrap(),
+            toml::Value::String(String::from(expected))
+        );
+    }
+}
+
+#[test]
+fn test_absolute_time_range() {
+    for (time_str, is_valid) in [
+        ("12:99", false),
+        ("12:60", false),
+        ("24:00", false),
+        ("12:59", true),
+        ("23:59:59", true),
+        ("23:59:60", false),
+        ("00:00", true),
+    ] {
+        let config: Result<DeviceConfig, _> = toml::from_str(&format!(
+            r#"
+[windows]
+start-recording = "{}"
+stop-recording = "08:00"
+"#,
+            time_str
+        ));
+        assert_eq!(config.is_ok(), is_valid, "Parsing '{}'", time_str);
+    }
+}
+
+#[test]
+fn test_solar_event_keywords() {
+    for (time_str, anchor, offset_seconds, expected) in [
+        ("civil_dusk", SolarAnchor::CivilDusk, 0, "civil_dusk"),
+        ("civil_dawn", SolarAnchor::CivilDawn, 0, "civil_dawn"),
+        (
+            "civil_dusk-30m",
+            SolarAnchor::CivilDusk,
+            -30 * 60,
+            "civil_dusk-30m",
+        ),
+        (
+            "civil_dawn+1h",
+            SolarAnchor::CivilDawn,
+            60 * 60,
+            "civil_dawn+1h",
+        ),
+        (
+            "sunset-1h30m",
+            SolarAnchor::Sunset,
+            -90 * 60,
+            "sunset-1h30m",
+        ),
+        (
+            "midnight+3h",
+            SolarAnchor::Midnight,
+            3 * 60 * 60,
+            "midnight+3h",
+        ),
+        ("midnight", SolarAnchor::Midnight, 0, "midnight"),
+    ] {
+        let config: DeviceConfig =
+            toml::from_str(&format!("[windows]\nstart-recording = \"{}\"\n", time_str)).unwrap();
+        let start = &config.primary_window().start_recording;
+        assert_eq!(start.anchor, Some(anchor), "Parsing '{}'", time_str);
+        assert_eq!(start.relative_time_seconds, Some(offset_seconds));
+        assert_eq!(start.to_config_string(), expected);
+    }
+
+    for time_str in ["civil_dusk12:00", "civil_noon", "dusk-30m"] {
+        let config: Result<DeviceConfig, _> =
+            toml::from_str(&format!("[windows]\nstart-recording = \"{}\"\n", time_str));
+        assert!(config.is_err(), "'{}' should not parse", time_str);
+    }
+}
+
+#[test]
+fn test_iso8601_durations() {
+    for (time_str, offset_seconds) in [
+        ("PT1H30M", 90 * 60),
+        ("-PT45M", -45 * 60),
+        ("PT30S", 30),
+        ("P1DT2H", 26 * 60 * 60),
+        ("civil_dusk-PT30M", -30 * 60),
+    ] {
+        let config: DeviceConfig =
+            toml::from_str(&format!("[windows]\nstart-recording = \"{}\"\n", time_str)).unwrap();
+        let start = &config.primary_window().start_recording;
+        assert!(start.absolute_time.is_none(), "Parsing '{}'", time_str);
+        assert_eq!(
+            start.relative_time_seconds,
+            Some(offset_seconds),
+            "Parsing '{}'",
+            time_str
+        );
+    }
+
+    for time_str in ["P", "PT", "PT1H30", "PT30M1H", "P1H", "PT1.5H"] {
+        let config: Result<DeviceConfig, _> =
+            toml::from_str(&format!("[windows]\nstart-recording = \"{}\"\n", time_str));
+        assert!(config.is_err(), "'{}' should not parse", time_str);
+    }
+}
+
+#[test]
+fn test_fractional_relative_offsets() {
+    for (time_str, offset_seconds) in [
+        ("1.5h", 90 * 60),
+        ("-0.5h", -30 * 60),
+        ("2.5m", 150),
+        ("-1h7.5m", -(60 * 60 + 450)),
+    ] {
+        let config: DeviceConfig =
+            toml::from_str(&format!("[windows]\nstart-recording = \"{}\"\n", time_str)).unwrap();
+        assert_eq!(
+            config
+                .primary_window()
+                .start_recording
+                .relative_time_seconds,
+            Some(offset_seconds),
+            "Parsing '{}'",
+            time_str
+        );
+    }
+
+    for time_str in ["1.5:30", "12:30.5"] {
+        let config: Result<DeviceConfig, _> =
+            toml::from_str(&format!("[windows]\nstart-recording = \"{}\"\n", time_str));
+        assert!(config.is_err(), "'{}' should not parse", time_str);
+    }
+}
+
+#[test]
+fn test_default_unit() {
+    for (default_unit, time_str, offset_seconds) in [
+        (None, "90", 90 * 60),
+        (Some("minutes"), "90", 90 * 60),
+        (Some("seconds"), "90", 90),
+        (Some("seconds"), "-90", -90),
+        (Some("seconds"), "sunset-90", -90),
+        (Some("seconds"), "1h30", 60 * 60 + 30),
+        (Some("seconds"), "90m", 90 * 60),
+        (Some("seconds"), "PT90S", 90),
+    ] {
+        let default_unit = default_unit
+            .map(|unit| format!("default-unit = \"{}\"\n", unit))
+            .unwrap_or_default();
+        let config: DeviceConfig = toml::from_str(&format!(
+            "[windows]\n{}start-recording = \"{}\"\n",
+            default_unit, time_str
+        ))
+        .unwrap();
+        assert_eq!(
+            config
+                .primary_window()
+                .start_recording
+                .relative_time_seconds,
+            Some(offset_seconds),
+            "Parsing '{}' with {}",
+            time_str,
+            default_unit
+        );
+    }
+
+    // Absolute times are unaffected
+    let config: DeviceConfig =
+        toml::from_str("[windows]\ndefault-unit = \"seconds\"\nstart-recording = \"20:10\"\n")
+            .unwrap();
+    assert_eq!(
+        config.primary_window().start_recording.to_config_string(),
+        "20:10"
+    );
+
+    let config: Result<DeviceConfig, _> =
+        toml::from_str("[windows]\ndefault-unit = \"hours\"\nstart-recording = \"2\"\n");
+    assert!(config.is_err());
+}
+
+#[test]
+fn test_large_relative_offset_warning() {
+    let offset_warnings = |start: &str| {
+        let config: DeviceConfig = toml::from_str(&format!(
+            "[windows]\nstart-recording = \"{}\"\nstop-recording = \"30m\"\n",
+            start
+        ))
+        .unwrap();
+        config
+            .validate()
+            .into_iter()
+            .filter(|warning| matches!(warning, ConfigWarning::LargeRelativeOffset { .. }))
+            .collect::<Vec<_>>()
+    };
+    assert!(offset_warnings("-60m").is_empty());
+    assert_eq!(
+        offset_warnings("-780m"),
+        vec![ConfigWarning::LargeRelativeOffset {
+            window: 0,
+            time: String::from("-13h")
+        }]
+    );
+    // Up to half a day is allowed either side
+    assert!(offset_warnings("-720m").is_empty());
+    assert!(offset_warnings("600m").is_empty());
+    // A bare number is minutes, so "800" is over 13 hours
+    assert_eq!(offset_warnings("800").len(), 1);
+    assert!(offset_warnings("midnight+10h").is_empty());
+    assert!(offset_warnings("22:00").is_empty());
+}
+
+#[test]
+fn test_window_phrase() {
+    let parse = |windows: &str| -> Result<DeviceConfig, _> {
+        toml::from_str::<DeviceConfig>(&format!("[windows]\n{}\n", windows))
+    };
+    let separate = parse("start-recording = \"sunset-1h\"\nstop-recording = \"sunrise+2h\"");
+    let phrase = parse("window = \"from sunset-1h to sunrise+2h\"");
+    assert_eq!(phrase.unwrap(), separate.unwrap());
+
+    let config = parse("window = \"from 22:30 to civil_dawn+30m\"").unwrap();
+    let (start, stop) = config.recording_window();
+    assert_eq!(start.to_config_string(), "22:30");
+    assert_eq!(stop.to_config_string(), "civil_dawn+30m");
+    assert_eq!(stop.anchor, Some(SolarAnchor::CivilDawn));
+
+    let config = parse("window = \"from -30m to 06:00\"").unwrap();
+    let (start, stop) = config.recording_window();
+    assert_eq!(start.relative_time_seconds, Some(-30 * 60));
+    assert_eq!(stop.to_config_string(), "06:00");
+
+    // Works in [[windows]] too
+    let config: DeviceConfig = toml::from_str(
+        "[[windows]]\nwindow = \"from 06:00 to 08:00\"\n\n[[windows]]\nwindow = \"from 18:00 to 20:00\"\n",
+    )
+    .unwrap();
+    assert_eq!(config.recording_windows.len(), 2);
+
+    for invalid in [
+        "window = \"sunset-1h to sunrise\"",
+        "window = \"from sunset-1h until sunrise\"",
+        "window = \"from noon to sunrise\"",
+        "window = 5",
+        "window = \"from 06:00 to 08:00\"\nstart-recording = \"05:00\"",
+    ] {
+        assert!(parse(invalid).is_err(), "'{}' should not parse", invalid);
+    }
+}
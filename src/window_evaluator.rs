@@ -0,0 +1,44 @@
+use chrono::NaiveDateTime;
+
+/// The recording windows for a single local day, resolved up front so that checking many
+/// timestamps (e.g. every frame of a recording) doesn't recalculate sunrise and sunset each time.
+///
+/// Only timestamps within the day the evaluator was created for give meaningful answers.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WindowEvaluator {
+    // UTC (start, end) pairs, both inclusive
+    windows: Vec<(NaiveDateTime, NaiveDateTime)>,
+    always_recording: bool,
+    sun_time_calculations: usize,
+}
+
+impl WindowEvaluator {
+    pub fn new(
+        windows: Vec<(NaiveDateTime, NaiveDateTime)>,
+        always_recording: bool,
+        sun_time_calculations: usize,
+    ) -> Self {
+        WindowEvaluator {
+            windows,
+            always_recording,
+            sun_time_calculations,
+        }
+    }
+
+    pub fn windows(&self) -> &[(NaiveDateTime, NaiveDateTime)] {
+        &self.windows
+    }
+
+    /// How many sunrise and sunset times were calculated to resolve the windows.
+    pub fn sun_time_calculations(&self) -> usize {
+        self.sun_time_calculations
+    }
+
+    pub fn contains(&self, date_time_utc: &NaiveDateTime) -> bool {
+        self.always_recording
+            || self
+                .windows
+                .iter()
+                .any(|(start, end)| date_time_utc >= start && date_time_utc <= end)
+    }
+}